@@ -1,10 +1,42 @@
+use std::{collections::VecDeque, env, fs, io, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 use dll_syringe::{Syringe, process::OwnedProcess};
 use toy_arms::{detect_keypress, VirtualKeyCode};
+use xinput_detour_core::ipc::*;
+use xinput_detour_core::ipc_transport::StreamTransport;
+use xinput_detour_core::named_pipe::NamedPipe;
+use xinput_detour_core::playback::{PlaybackOptions, PlaybackState};
 
 const EXE_NAME: &str = "RocketLeague";
 const DLL_NAME: &str = "rocket_league_hook.dll";
 
+// how long to wait for the injected DLL to open its control channel
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long to wait for the DLL to stop its control channel before ejecting it
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// how often to ask the DLL for its status while it records or plays, so the
+// keys know when a recording or playback ended on its own
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+// where F6 saves recordings, relative to the working directory
+const RECORDINGS_DIR: &str = "recordings";
+
+// the profile to load when none is given, relative to the working directory
+const DEFAULT_PROFILE: &str = "profiles/default.toml";
+
+type Client = ControlClient<StreamTransport<NamedPipe>>;
+
 fn main() {
+    // profiles to switch to once the DLL is injected, given as `path` for every
+    // controller slot or `slot=path` (e.g. `1=profiles/player2.toml`) for one.
+    // Without any the DLL would look for a profile next to itself, which is in
+    // the build directory here.
+    let mut profile_paths: Vec<(Option<u32>, String)> = env::args().skip(1).map(|arg| parse_profile_arg(&arg)).collect();
+    if profile_paths.is_empty() {
+        profile_paths.push(parse_profile_arg(DEFAULT_PROFILE));
+    }
+
     // find the target process by name
     let target_process = OwnedProcess::find_first_by_name(EXE_NAME).unwrap();
     let syringe = Syringe::for_process(target_process);
@@ -12,6 +44,8 @@ fn main() {
     // inject the payload into the target process
     let file_path = format!("{}/{}/{}", env!("CARGO_MANIFEST_DIR"), "target/debug/deps/", DLL_NAME);
     let mut injected_payload = syringe.inject(&file_path).unwrap();
+    let mut connection = Connection::open().expect("could not connect to the DLL");
+    connection.switch_profiles(&profile_paths).unwrap();
 
     // if cfg!(debug_assertions) {
     //     println!("Debugging enabled");
@@ -21,25 +55,314 @@ fn main() {
 
     println!("");
     println!("DLL injected successfully!");
-    println!("  [F9]  to reload the DLL");
+    println!("  [F4]  to pause or resume playback");
+    println!("  [F5]  to play the latest recording or stop playing");
+    println!("  [F6]  to start or stop recording");
+    println!("  [F7]  to show the hook status");
+    println!("  [F8]  to toggle live controller state");
+    println!("  [F9]  to reload the profile");
+    println!("  [F10] to reload the DLL");
     println!("  [F12] to unload the DLL");
     println!("");
 
+    let mut show_controller_state = false;
+
     loop {
-        // Reload the DLL if F9 is pressed
-        if detect_keypress(VirtualKeyCode::VK_F9) {
+        // Talk to the DLL, and connect again if the pipe broke. Once the game has
+        // closed there is nothing to connect to and the injector stops.
+        if let Err(error) = handle_keys(&mut connection, &mut show_controller_state) {
+            println!("Lost the connection to the DLL: {}", error);
+            match Connection::open() {
+                Ok(reconnected) => connection = reconnected,
+                Err(error) => {
+                    println!("Could not reconnect: {}", error);
+                    break;
+                },
+            }
+            show_controller_state = false;
+            continue;
+        }
+
+        // Reload the DLL if F10 is pressed
+        if detect_keypress(VirtualKeyCode::VK_F10) {
             print!("Reloading DLL...");
+            if let Err(error) = say_goodbye(&mut connection) {
+                println!(" Could not stop the DLL, not ejecting it: {}", error);
+                continue;
+            }
             syringe.eject(injected_payload).unwrap();
             println!(" Done!");
             injected_payload = syringe.inject(&file_path).unwrap();
+            connection = Connection::open().expect("could not connect to the DLL");
+            connection.switch_profiles(&profile_paths).unwrap();
+            show_controller_state = false;
         }
 
         // Unload the DLL if F12 is pressed
         if detect_keypress(VirtualKeyCode::VK_F12) {
             print!("Unloading DLL...");
+            if let Err(error) = say_goodbye(&mut connection) {
+                println!(" Could not stop the DLL, not ejecting it: {}", error);
+                continue;
+            }
             syringe.eject(injected_payload).unwrap();
             println!(" Done!");
             break;
         }
     }
-}
\ No newline at end of file
+}
+
+// Prints what the DLL sent and sends the requests of the keys pressed since the
+// last call. Fails once the pipe is broken.
+fn handle_keys(connection: &mut Connection, show_controller_state: &mut bool) -> io::Result<()> {
+    connection.receive()?;
+    connection.refresh_status()?;
+
+    // Pause or resume playback if F4 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F4) && connection.playing {
+        connection.send(Request::PausePlayback { paused: !connection.paused })?;
+    }
+
+    // Play the latest recording or stop playing if F5 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F5) {
+        if connection.playing {
+            connection.send(Request::StopPlayback)?;
+        } else if let Some(path) = latest_recording_path() {
+            println!("Playing {} (move the controller to take over)", path);
+            // hand control back to the controller as soon as it is touched
+            let options = PlaybackOptions { release_on_input: true, ..Default::default() };
+            connection.send(Request::StartPlayback { path, options })?;
+        } else {
+            println!("No recordings in {}", RECORDINGS_DIR);
+        }
+    }
+
+    // Start or stop recording if F6 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F6) {
+        if connection.recording {
+            connection.send(Request::StopRecording)?;
+        } else {
+            let path = new_recording_path();
+            println!("Recording to {}", path);
+            connection.send(Request::StartRecording { path })?;
+        }
+    }
+
+    // Show the hook status if F7 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F7) {
+        connection.send(Request::Status)?;
+    }
+
+    // Toggle the live controller state if F8 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F8) {
+        *show_controller_state = !*show_controller_state;
+        connection.send(Request::Subscribe { logs: true, controller_state: *show_controller_state })?;
+    }
+
+    // Reload the profile if F9 is pressed
+    if detect_keypress(VirtualKeyCode::VK_F9) {
+        connection.send(Request::ReloadProfile { user_index: None })?;
+    }
+
+    Ok(())
+}
+
+// Splits a `slot=path` argument. Anything without a valid slot prefix is a path
+// for every slot. The path is made absolute because the DLL resolves it relative
+// to the game's working directory.
+fn parse_profile_arg(arg: &str) -> (Option<u32>, String) {
+    let (user_index, path) = match arg.split_once('=') {
+        Some((slot, path)) if slot.parse::<u32>().is_ok() => (slot.parse().ok(), path),
+        _ => (None, arg),
+    };
+    (user_index, env::current_dir().unwrap().join(path).display().to_string())
+}
+
+// The control channel, and whether the DLL is recording or playing as far as
+// its answers tell
+struct Connection {
+    client: Client,
+    // the requests the DLL has not answered yet, oldest first, and whether to
+    // print the answer
+    pending: VecDeque<(Request, bool)>,
+    last_status: Instant,
+    recording: bool,
+    playing: bool,
+    paused: bool,
+}
+
+impl Connection {
+    // Connects to the control channel of the DLL, waiting for a freshly
+    // injected one to open it.
+    fn open() -> io::Result<Self> {
+        let started = Instant::now();
+        let pipe = loop {
+            match NamedPipe::connect(PIPE_NAME) {
+                Ok(pipe) => break pipe,
+                Err(error) if started.elapsed() > CONNECT_TIMEOUT => return Err(error),
+                Err(_) => thread::sleep(Duration::from_millis(50)),
+            }
+        };
+
+        let mut connection = Connection {
+            client: ControlClient::new(StreamTransport::new(pipe))?,
+            pending: VecDeque::new(),
+            last_status: Instant::now(),
+            recording: false,
+            playing: false,
+            paused: false,
+        };
+        connection.send(Request::Subscribe { logs: true, controller_state: false })?;
+        // the DLL may already be recording or playing after a reconnect
+        connection.send_quietly(Request::Status)?;
+        Ok(connection)
+    }
+
+    fn switch_profiles(&mut self, profile_paths: &[(Option<u32>, String)]) -> io::Result<()> {
+        for (user_index, path) in profile_paths {
+            self.send(Request::SwitchProfile { path: path.clone(), user_index: *user_index })?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, request: Request) -> io::Result<()> {
+        self.client.send(&request)?;
+        self.pending.push_back((request, true));
+        Ok(())
+    }
+
+    // Sends a request without printing the answer
+    fn send_quietly(&mut self, request: Request) -> io::Result<()> {
+        self.client.send(&request)?;
+        self.pending.push_back((request, false));
+        Ok(())
+    }
+
+    // Asks for the status every STATUS_INTERVAL while recording or playing
+    fn refresh_status(&mut self) -> io::Result<()> {
+        if (self.recording || self.playing) && self.last_status.elapsed() >= STATUS_INTERVAL {
+            self.last_status = Instant::now();
+            self.send_quietly(Request::Status)?;
+        }
+        Ok(())
+    }
+
+    // Prints everything the DLL sent since the last call and updates what the
+    // injector knows from the answers.
+    fn receive(&mut self) -> io::Result<()> {
+        while let Some(message) = self.client.try_recv()? {
+            let answer = matches!(
+                message,
+                Message::Status { .. } | Message::ProfileLoaded { .. } | Message::Done | Message::Error { .. } | Message::RecordingStopped { .. }
+            );
+            // logs and controller states come in between the answers
+            let pending = if answer { self.pending.pop_front() } else { None };
+            let show = match pending {
+                Some((request, show)) => {
+                    self.update(&request, &message);
+                    show
+                },
+                None => true,
+            };
+            if show {
+                print_message(message);
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps the recording and playback flags in line with the DLL's answer to
+    // a request
+    fn update(&mut self, request: &Request, answer: &Message) {
+        let done = matches!(answer, Message::Done);
+        match request {
+            Request::StartRecording { .. } => self.recording = done,
+            // an error means it was not recording either
+            Request::StopRecording => self.recording = false,
+            Request::StartPlayback { .. } if done => {
+                self.playing = true;
+                self.paused = false;
+            },
+            Request::PausePlayback { paused } if done => self.paused = *paused,
+            Request::PausePlayback { .. } | Request::StopPlayback => self.playing = false,
+            Request::Status => {
+                if let Message::Status { status } = answer {
+                    let state = status.playback.as_ref().map(|playback| playback.state);
+                    self.recording = status.recording.is_some();
+                    self.playing = matches!(state, Some(PlaybackState::Playing | PlaybackState::Paused));
+                    self.paused = state == Some(PlaybackState::Paused);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// A new file in RECORDINGS_DIR. The path is absolute because the DLL resolves it
+// relative to the game's working directory.
+fn new_recording_path() -> String {
+    let dir = env::current_dir().unwrap().join(RECORDINGS_DIR);
+    fs::create_dir_all(&dir).unwrap();
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    dir.join(format!("recording-{}.xirec", seconds)).display().to_string()
+}
+
+// The newest file in RECORDINGS_DIR. Recordings are named after the time they
+// were started, so the newest has the greatest name.
+fn latest_recording_path() -> Option<String> {
+    let dir = env::current_dir().ok()?.join(RECORDINGS_DIR);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "xirec"))
+        .max()
+        .map(|path| path.display().to_string())
+}
+
+// Tells the DLL to stop its control channel so it can be ejected safely.
+fn say_goodbye(connection: &mut Connection) -> io::Result<()> {
+    connection.client.send(&Request::Goodbye)?;
+
+    let started = Instant::now();
+    while started.elapsed() < GOODBYE_TIMEOUT {
+        match connection.client.try_recv() {
+            Ok(Some(Message::Goodbye)) | Err(_) => break,
+            Ok(_) => {},
+        }
+    }
+
+    // give the server thread a moment to return before its code is unloaded
+    thread::sleep(Duration::from_millis(100));
+    Ok(())
+}
+
+fn print_message(message: Message) {
+    match message {
+        Message::Hello { version } => println!("Connected to the DLL (protocol version {})", version),
+        Message::Status { status } => {
+            println!("Hooked: {}", status.hooked);
+            println!("Polls: {}", status.polls);
+            if let Some(path) = status.recording {
+                println!("Recording to {}", path);
+            }
+            if let Some(playback) = status.playback {
+                println!("Playing {} ({:?}, {}/{} ms)", playback.path, playback.state, playback.position_ms, playback.duration_ms);
+            }
+            for slot in status.slots {
+                println!("Slot {}: profile '{}' ({})", slot.user_index, slot.profile_name, slot.profile_path);
+                println!("  Scheduled functions: {}", slot.scheduled_functions);
+                println!("  Disabled rules: {:?}", slot.disabled_rules);
+                if slot.virtual_pad {
+                    println!("  Virtual pad plugged in");
+                }
+            }
+        },
+        Message::ProfileLoaded { name } => println!("Loaded profile '{}'", name),
+        Message::Done => {},
+        Message::Error { message } => println!("Error: {}", message),
+        Message::Log { message } => println!("{}", message),
+        Message::ControllerState { user_index, state } => println!("[{}] {:?}", user_index, state.gamepad),
+        Message::RecordingStopped { path, frames } => println!("Recorded {} frames to {}", frames, path),
+        Message::Goodbye => {},
+    }
+}
@@ -0,0 +1,24 @@
+// Converts a recording made with F6 in the injector to JSON lines, one frame
+// per line:
+//
+//   cargo run -p xinput_detour_core --example export_recording -- recordings/recording-1700000000.xirec > frames.jsonl
+
+use std::{env, fs::File, io::{self, BufReader, BufWriter}, process};
+use xinput_detour_core::recording::export_json_lines;
+
+fn main() {
+  let Some(path) = env::args().nth(1) else {
+    eprintln!("usage: export_recording <recording.xirec>");
+    process::exit(2);
+  };
+
+  let result = File::open(&path)
+    .and_then(|file| export_json_lines(BufReader::new(file), BufWriter::new(io::stdout().lock())));
+  match result {
+    Ok(frames) => eprintln!("exported {} frames", frames),
+    Err(error) => {
+      eprintln!("could not export {}: {}", path, error);
+      process::exit(1);
+    },
+  }
+}
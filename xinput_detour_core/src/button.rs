@@ -0,0 +1,239 @@
+// Typed names for the XINPUT_GAMEPAD_* button bits, so rules can work with
+// `Button::A` and sets of buttons instead of raw u16 masks.
+
+use std::{fmt, iter::FromIterator, ops};
+use serde::{Deserialize, Deserializer};
+use super::xinput_state::*;
+
+/// One of the buttons in `XInputGamepad::buttons`. The discriminant is the
+/// button's `XINPUT_GAMEPAD_*` bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum Button {
+  DpadUp = XINPUT_GAMEPAD_DPAD_UP,
+  DpadDown = XINPUT_GAMEPAD_DPAD_DOWN,
+  DpadLeft = XINPUT_GAMEPAD_DPAD_LEFT,
+  DpadRight = XINPUT_GAMEPAD_DPAD_RIGHT,
+  Start = XINPUT_GAMEPAD_START,
+  Back = XINPUT_GAMEPAD_BACK,
+  LeftThumb = XINPUT_GAMEPAD_LEFT_THUMB,
+  RightThumb = XINPUT_GAMEPAD_RIGHT_THUMB,
+  LeftShoulder = XINPUT_GAMEPAD_LEFT_SHOULDER,
+  RightShoulder = XINPUT_GAMEPAD_RIGHT_SHOULDER,
+  /// The Xbox button. XInputGetState never reports it, only XInputGetStateEx.
+  Guide = XINPUT_GAMEPAD_GUIDE,
+  A = XINPUT_GAMEPAD_A,
+  B = XINPUT_GAMEPAD_B,
+  X = XINPUT_GAMEPAD_X,
+  Y = XINPUT_GAMEPAD_Y,
+}
+
+impl Button {
+  /// Every button, in bit order.
+  pub const ALL: [Button; 15] = [
+    Button::DpadUp,
+    Button::DpadDown,
+    Button::DpadLeft,
+    Button::DpadRight,
+    Button::Start,
+    Button::Back,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::Guide,
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+  ];
+
+  /// The button's `XINPUT_GAMEPAD_*` bit.
+  #[inline]
+  pub const fn mask(self) -> u16 {
+    self as u16
+  }
+
+  /// The button with exactly this bit, if there is one.
+  pub fn from_mask(mask: u16) -> Option<Button> {
+    Button::ALL.iter().copied().find(|button| button.mask() == mask)
+  }
+
+  /// The name used in profiles, matching the `XINPUT_GAMEPAD_*` suffix.
+  pub const fn name(self) -> &'static str {
+    match self {
+      Button::DpadUp => "DPAD_UP",
+      Button::DpadDown => "DPAD_DOWN",
+      Button::DpadLeft => "DPAD_LEFT",
+      Button::DpadRight => "DPAD_RIGHT",
+      Button::Start => "START",
+      Button::Back => "BACK",
+      Button::LeftThumb => "LEFT_THUMB",
+      Button::RightThumb => "RIGHT_THUMB",
+      Button::LeftShoulder => "LEFT_SHOULDER",
+      Button::RightShoulder => "RIGHT_SHOULDER",
+      Button::Guide => "GUIDE",
+      Button::A => "A",
+      Button::B => "B",
+      Button::X => "X",
+      Button::Y => "Y",
+    }
+  }
+
+  /// Looks up a button by its name (case-insensitive), e.g. `"dpad_up"`.
+  pub fn from_name(name: &str) -> Option<Button> {
+    Button::ALL.iter().copied().find(|button| button.name().eq_ignore_ascii_case(name))
+  }
+}
+
+impl fmt::Display for Button {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl<'de> Deserialize<'de> for Button {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Button::from_name(&name).ok_or_else(|| {
+      let names: Vec<&str> = Button::ALL.iter().map(|button| button.name()).collect();
+      serde::de::Error::custom(format!("unknown button `{}`, expected one of: {}", name, names.join(", ")))
+    })
+  }
+}
+
+/// A set of buttons, stored as the raw `XInputGamepad::buttons` bit field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Buttons(u16);
+
+impl Buttons {
+  pub const fn empty() -> Self {
+    Buttons(0)
+  }
+
+  /// Wraps a raw bit field. Bits that are not a `Button` are kept as they are.
+  pub const fn from_bits(bits: u16) -> Self {
+    Buttons(bits)
+  }
+
+  #[inline]
+  pub const fn bits(self) -> u16 {
+    self.0
+  }
+
+  #[inline]
+  pub const fn is_empty(self) -> bool {
+    self.0 == 0
+  }
+
+  #[inline]
+  pub const fn contains(self, button: Button) -> bool {
+    self.0 & button.mask() != 0
+  }
+
+  /// True if every button in `other` is also in this set.
+  #[inline]
+  pub const fn contains_all(self, other: Buttons) -> bool {
+    self.0 & other.0 == other.0
+  }
+
+  /// True if any button in `other` is also in this set.
+  #[inline]
+  pub const fn intersects(self, other: Buttons) -> bool {
+    self.0 & other.0 != 0
+  }
+
+  #[inline]
+  pub fn insert(&mut self, button: Button) {
+    self.0 |= button.mask();
+  }
+
+  #[inline]
+  pub fn remove(&mut self, button: Button) {
+    self.0 &= !button.mask();
+  }
+
+  #[inline]
+  pub const fn union(self, other: Buttons) -> Self {
+    Buttons(self.0 | other.0)
+  }
+
+  #[inline]
+  pub const fn intersection(self, other: Buttons) -> Self {
+    Buttons(self.0 & other.0)
+  }
+
+  #[inline]
+  pub const fn difference(self, other: Buttons) -> Self {
+    Buttons(self.0 & !other.0)
+  }
+
+  /// The buttons in the set, in bit order.
+  pub fn iter(self) -> impl Iterator<Item = Button> {
+    Button::ALL.into_iter().filter(move |button| self.contains(*button))
+  }
+}
+
+impl From<Button> for Buttons {
+  fn from(button: Button) -> Self {
+    Buttons(button.mask())
+  }
+}
+
+impl FromIterator<Button> for Buttons {
+  fn from_iter<I: IntoIterator<Item = Button>>(buttons: I) -> Self {
+    buttons.into_iter().fold(Buttons::empty(), |set, button| set | button)
+  }
+}
+
+impl<'a> FromIterator<&'a Button> for Buttons {
+  fn from_iter<I: IntoIterator<Item = &'a Button>>(buttons: I) -> Self {
+    buttons.into_iter().copied().collect()
+  }
+}
+
+impl<B: Into<Buttons>> ops::BitOr<B> for Buttons {
+  type Output = Buttons;
+
+  fn bitor(self, other: B) -> Buttons {
+    self.union(other.into())
+  }
+}
+
+impl<B: Into<Buttons>> ops::BitOrAssign<B> for Buttons {
+  fn bitor_assign(&mut self, other: B) {
+    *self = *self | other;
+  }
+}
+
+impl<B: Into<Buttons>> ops::BitAnd<B> for Buttons {
+  type Output = Buttons;
+
+  fn bitand(self, other: B) -> Buttons {
+    self.intersection(other.into())
+  }
+}
+
+impl<B: Into<Buttons>> ops::Sub<B> for Buttons {
+  type Output = Buttons;
+
+  fn sub(self, other: B) -> Buttons {
+    self.difference(other.into())
+  }
+}
+
+impl ops::Not for Buttons {
+  type Output = Buttons;
+
+  fn not(self) -> Buttons {
+    Buttons(!self.0)
+  }
+}
+
+impl ops::BitOr for Button {
+  type Output = Buttons;
+
+  fn bitor(self, other: Button) -> Buttons {
+    Buttons::from(self) | other
+  }
+}
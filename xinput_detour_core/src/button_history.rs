@@ -0,0 +1,147 @@
+// Tracks how the buttons of one controller change from poll to poll so rules
+// can react to presses and releases instead of only the current level. The
+// history always looks at the buttons as the controller reported them, before
+// any rule modified the state.
+
+use std::time::Duration;
+
+// one entry per bit of wButtons
+const BUTTON_BITS: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ButtonHistory {
+  previous: u16,
+  current: u16,
+  now: Option<Duration>,
+  pressed_at: [Option<Duration>; BUTTON_BITS],
+  released_after: [Option<Duration>; BUTTON_BITS],
+}
+
+// The bit index of a single-button mask such as XINPUT_GAMEPAD_A
+fn bit_index(bit_mask: u16) -> usize {
+  bit_mask.trailing_zeros() as usize % BUTTON_BITS
+}
+
+impl ButtonHistory {
+  /// Records the buttons of a new poll. `now` is the time of the poll as read
+  /// from the slot's clock.
+  pub fn update(&mut self, buttons: u16, now: Duration) {
+    self.previous = self.current;
+    self.current = buttons;
+    self.now = Some(now);
+
+    for bit in 0..BUTTON_BITS {
+      let mask = 1 << bit;
+      let was_down = self.previous & mask != 0;
+      let is_down = self.current & mask != 0;
+
+      self.released_after[bit] = None;
+      if is_down && !was_down {
+        self.pressed_at[bit] = Some(now);
+      } else if !is_down && was_down {
+        self.released_after[bit] = self.pressed_at[bit].take().map(|pressed_at| now.saturating_sub(pressed_at));
+      }
+    }
+  }
+
+  /// The time of the latest poll.
+  pub fn now(&self) -> Option<Duration> {
+    self.now
+  }
+
+  /// The buttons from the previous poll.
+  pub fn previous_buttons(&self) -> u16 {
+    self.previous
+  }
+
+  /// The button went down on this poll.
+  pub fn just_pressed(&self, bit_mask: u16) -> bool {
+    self.current & bit_mask != 0 && self.previous & bit_mask == 0
+  }
+
+  /// The button went up on this poll.
+  pub fn just_released(&self, bit_mask: u16) -> bool {
+    self.current & bit_mask == 0 && self.previous & bit_mask != 0
+  }
+
+  /// How long the button has been held, or `None` if it is up.
+  pub fn held_duration(&self, bit_mask: u16) -> Option<Duration> {
+    let pressed_at = self.pressed_at[bit_index(bit_mask)]?;
+    self.now.map(|now| now.saturating_sub(pressed_at))
+  }
+
+  /// The button is down and has been for at least `duration`.
+  pub fn held_for(&self, bit_mask: u16, duration: Duration) -> bool {
+    self.held_duration(bit_mask).is_some_and(|held| held >= duration)
+  }
+
+  /// The button went up on this poll after being held for at least `duration`.
+  pub fn released_after(&self, bit_mask: u16, duration: Duration) -> bool {
+    self.released_after[bit_index(bit_mask)].is_some_and(|held| held >= duration)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::xinput_state::{XINPUT_GAMEPAD_A, XINPUT_GAMEPAD_B};
+
+  const A: u16 = XINPUT_GAMEPAD_A;
+  const B: u16 = XINPUT_GAMEPAD_B;
+
+  fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+  }
+
+  #[test]
+  fn edges_last_a_single_poll() {
+    let mut history = ButtonHistory::default();
+    history.update(A, ms(0));
+    assert!(history.just_pressed(A) && !history.just_pressed(B));
+    assert_eq!(history.now(), Some(ms(0)));
+
+    history.update(A | B, ms(16));
+    assert!(!history.just_pressed(A) && history.just_pressed(B));
+    assert_eq!(history.previous_buttons(), A);
+
+    history.update(B, ms(32));
+    assert!(history.just_released(A) && !history.just_released(B));
+    history.update(B, ms(48));
+    assert!(!history.just_released(A));
+  }
+
+  #[test]
+  fn held_for_counts_from_the_press() {
+    let mut history = ButtonHistory::default();
+    history.update(A, ms(100));
+    assert_eq!(history.held_duration(A), Some(ms(0)));
+    history.update(A, ms(350));
+    assert_eq!(history.held_duration(A), Some(ms(250)));
+    assert!(history.held_for(A, ms(250)) && !history.held_for(A, ms(251)));
+    assert_eq!(history.held_duration(B), None);
+
+    history.update(0, ms(400));
+    assert_eq!(history.held_duration(A), None);
+    assert!(!history.held_for(A, Duration::ZERO));
+  }
+
+  #[test]
+  fn released_after_only_on_the_release_poll() {
+    let mut history = ButtonHistory::default();
+    history.update(A, ms(0));
+    history.update(0, ms(300));
+    assert!(history.released_after(A, ms(300)) && !history.released_after(A, ms(301)));
+    history.update(0, ms(316));
+    assert!(!history.released_after(A, Duration::ZERO));
+  }
+
+  #[test]
+  fn a_clock_going_backwards_counts_as_no_time() {
+    let mut history = ButtonHistory::default();
+    history.update(A, ms(500));
+    history.update(A, ms(200));
+    assert_eq!(history.held_duration(A), Some(Duration::ZERO));
+    history.update(0, ms(100));
+    assert!(history.released_after(A, Duration::ZERO) && !history.released_after(A, ms(1)));
+  }
+}
@@ -0,0 +1,105 @@
+// Time sources for the function scheduler and the button history. Times are
+// plain `Duration`s measured from a point the clock picks, so only the
+// difference between two readings of the same clock means anything.
+
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send {
+  /// The current time. It must never go backwards.
+  fn now(&self) -> Duration;
+
+  /// Called once per XInputGetState call, before the time is read.
+  fn on_poll(&mut self) {}
+}
+
+/// Real time from `Instant`, which unlike `SystemTime` is not affected by
+/// changes to the wall clock.
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicClock {
+  start: Instant,
+}
+
+impl MonotonicClock {
+  pub fn new() -> Self {
+    Self { start: Instant::now() }
+  }
+}
+
+impl Default for MonotonicClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Clock for MonotonicClock {
+  fn now(&self) -> Duration {
+    self.start.elapsed()
+  }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one handle and give the other to the code under test.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+  nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set(&self, now: Duration) {
+    self.nanos.store(now.as_nanos() as u64, Ordering::SeqCst);
+  }
+
+  pub fn advance(&self, by: Duration) {
+    self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Duration {
+    Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+  }
+}
+
+/// Counts polls instead of measuring time: every XInputGetState call moves
+/// the clock forward by exactly `frame_duration`. Macro steps then last a fixed
+/// number of polls no matter how unevenly the game calls XInputGetState.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameClock {
+  frames: u64,
+  frame_duration: Duration,
+}
+
+impl FrameClock {
+  /// The frame duration of a game that polls once per frame at 60fps.
+  pub const DEFAULT_FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+  pub fn new(frame_duration: Duration) -> Self {
+    Self { frames: 0, frame_duration }
+  }
+
+  /// How many polls the clock has seen.
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+}
+
+impl Default for FrameClock {
+  fn default() -> Self {
+    Self::new(Self::DEFAULT_FRAME_DURATION)
+  }
+}
+
+impl Clock for FrameClock {
+  fn now(&self) -> Duration {
+    Duration::from_nanos(self.frame_duration.as_nanos() as u64 * self.frames)
+  }
+
+  fn on_poll(&mut self) {
+    self.frames += 1;
+  }
+}
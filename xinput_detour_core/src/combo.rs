@@ -0,0 +1,352 @@
+// Combo rules: chords of buttons pressed together and sequences of buttons
+// pressed one after the other, each within a time window. A matched combo can
+// run macro steps or switch to another profile.
+//
+// With `suppress` the buttons of a chord are held back from the game (and the
+// other rules) until it is clear whether the chord is being pressed, which
+// delays them by up to the window. A button released before that is let
+// through as a one-poll tap. For sequences only the final button can be
+// hidden, since the earlier ones have already been seen by the time the
+// sequence matches.
+//
+// The rules after the combos, and the combos after this one, see a held back
+// button as pressed on the poll it is let through, so a latch, toggle or
+// macro on it reacts then rather than when the button physically went down.
+// A button that is part of a matched chord is never pressed for them.
+
+use std::{convert::TryFrom, path::PathBuf, time::Duration};
+use serde::Deserialize;
+use super::button::{Button, Buttons};
+use super::mutable_xinput_state::{ButtonState, MutableXInputState};
+use super::profile::MacroStep;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ComboKind {
+  /// Every button down, with the first and last press within the window.
+  Chord(Buttons),
+  /// The buttons pressed in this order, with no other presses in between, and
+  /// the first and last press within the window.
+  Sequence(Vec<Button>),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "ComboConfig")]
+pub struct Combo {
+  pub kind: ComboKind,
+  pub window: Duration,
+  /// Hide the buttons of the combo from the game.
+  pub suppress: bool,
+  /// Macro steps to run when the combo matches.
+  pub steps: Vec<MacroStep>,
+  /// A profile to switch the slot to when the combo matches, relative to the
+  /// current profile file.
+  pub profile: Option<PathBuf>,
+}
+
+// The profile format, checked before it becomes a Combo
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ComboConfig {
+  chord: Option<Vec<Button>>,
+  sequence: Option<Vec<Button>>,
+  window_ms: Option<u64>,
+  #[serde(default)]
+  suppress: bool,
+  #[serde(default)]
+  steps: Vec<MacroStep>,
+  profile: Option<PathBuf>,
+}
+
+impl TryFrom<ComboConfig> for Combo {
+  type Error = String;
+
+  fn try_from(config: ComboConfig) -> Result<Self, Self::Error> {
+    let (kind, default_window) = match (config.chord, config.sequence) {
+      (Some(_), Some(_)) => return Err("a combo is either a chord or a sequence, not both".to_owned()),
+      (None, None) => return Err("a combo needs a chord or a sequence".to_owned()),
+      (Some(chord), None) => {
+        let buttons: Buttons = chord.iter().collect();
+        if buttons.iter().count() < 2 {
+          return Err("a chord needs at least two different buttons".to_owned());
+        }
+        (ComboKind::Chord(buttons), Combo::DEFAULT_CHORD_WINDOW)
+      },
+      (None, Some(sequence)) => {
+        if sequence.len() < 2 {
+          return Err("a sequence needs at least two buttons".to_owned());
+        }
+        (ComboKind::Sequence(sequence), Combo::DEFAULT_SEQUENCE_WINDOW)
+      },
+    };
+    if config.steps.is_empty() && config.profile.is_none() {
+      return Err("a combo needs steps to run or a profile to switch to".to_owned());
+    }
+
+    Ok(Combo {
+      kind,
+      window: config.window_ms.map(Duration::from_millis).unwrap_or(default_window),
+      suppress: config.suppress,
+      steps: config.steps,
+      profile: config.profile,
+    })
+  }
+}
+
+/// What a combo remembers between polls.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ComboState {
+  // chord buttons held back until the chord is decided
+  pending: Buttons,
+  // buttons hidden until they are released
+  suppressed: Buttons,
+  // the latest presses and when they happened, for sequences
+  presses: Vec<(Button, Duration)>,
+}
+
+impl Combo {
+  pub const DEFAULT_CHORD_WINDOW: Duration = Duration::from_millis(50);
+  pub const DEFAULT_SEQUENCE_WINDOW: Duration = Duration::from_millis(300);
+
+  /// Looks for the combo in this poll and hides its buttons if it suppresses
+  /// them. Returns true on the poll the combo matches. This must run before
+  /// any other rule changes the buttons.
+  pub fn update(&self, controller_state: &MutableXInputState, state: &mut ComboState) -> bool {
+    let down = controller_state.buttons();
+    state.suppressed = state.suppressed & down;
+
+    let matched = match &self.kind {
+      ComboKind::Chord(chord) => self.update_chord(*chord, controller_state, state),
+      ComboKind::Sequence(sequence) => self.update_sequence(sequence, controller_state, state),
+    };
+
+    let hidden = state.pending | state.suppressed;
+    controller_state.set_buttons_raw(hidden.bits(), ButtonState::UP);
+    controller_state.hide_presses(hidden);
+    matched
+  }
+
+  fn update_chord(&self, chord: Buttons, controller_state: &MutableXInputState, state: &mut ComboState) -> bool {
+    let down = controller_state.buttons();
+    let history = controller_state.history();
+    let just_pressed = chord.iter().any(|button| controller_state.just_pressed(button));
+
+    // the chord completes on the poll its last button goes down
+    if down.contains_all(chord) && just_pressed {
+      let spread = chord.iter().filter_map(|button| history.held_duration(button.mask())).max().unwrap_or_default();
+      if spread <= self.window {
+        if self.suppress {
+          state.pending = Buttons::empty();
+          state.suppressed |= chord;
+        }
+        return true;
+      }
+    }
+
+    if !self.suppress {
+      return false;
+    }
+
+    for button in chord.iter().filter(|button| controller_state.just_pressed(*button)) {
+      state.pending.insert(button);
+    }
+    for button in state.pending.iter() {
+      if !down.contains(button) {
+        // released before the chord was decided: let the game see the tap now
+        state.pending.remove(button);
+        controller_state.set(button, ButtonState::DOWN);
+        controller_state.press_late(button);
+      } else if history.held_duration(button.mask()).is_some_and(|held| held > self.window) {
+        // too late to become a chord, so it is just a button press
+        state.pending.remove(button);
+        controller_state.press_late(button);
+      }
+    }
+    false
+  }
+
+  fn update_sequence(&self, sequence: &[Button], controller_state: &MutableXInputState, state: &mut ComboState) -> bool {
+    let now = controller_state.now();
+    for button in Button::ALL.into_iter().filter(|button| controller_state.just_pressed(*button)) {
+      state.presses.push((button, now));
+      if state.presses.len() > sequence.len() {
+        state.presses.remove(0);
+      }
+    }
+
+    let matched = state.presses.len() == sequence.len()
+      && state.presses.iter().map(|(button, _)| button).eq(sequence.iter())
+      && now.saturating_sub(state.presses[0].1) <= self.window;
+    if matched {
+      state.presses.clear();
+      if self.suppress {
+        state.suppressed.insert(sequence[sequence.len() - 1]);
+      }
+    }
+    matched
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::profile::Profile;
+  use crate::simulation::{Frame, Simulation};
+
+  fn simulation(combo: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("[[combo]]\n{}\nsteps = [{{ duration_ms = 100, press = [\"Y\"] }}]", combo)).unwrap())
+  }
+
+  fn at(ms: u64, buttons: Buttons) -> Frame {
+    Frame::buttons(Duration::from_millis(ms), buttons)
+  }
+
+  // Whether the combo's macro is pressing Y in each output frame
+  fn y_pressed(output: Vec<Frame>) -> Vec<bool> {
+    output.iter().map(|frame| frame.pressed().contains(Button::Y)).collect()
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<Combo>(toml).unwrap_err().message().to_owned()
+  }
+
+  #[test]
+  fn chord_matches_within_the_window() {
+    let mut simulation = simulation("chord = [\"LEFT_SHOULDER\", \"RIGHT_SHOULDER\"]");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(40, Button::LeftShoulder | Button::RightShoulder),
+      at(60, Button::LeftShoulder | Button::RightShoulder),
+    ]);
+    assert_eq!(y_pressed(output), [false, true, true]);
+  }
+
+  #[test]
+  fn chord_spread_over_the_window_does_not_match() {
+    let mut simulation = simulation("chord = [\"LEFT_SHOULDER\", \"RIGHT_SHOULDER\"]\nwindow_ms = 50");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(51, Button::LeftShoulder | Button::RightShoulder),
+      at(70, Button::LeftShoulder | Button::RightShoulder),
+    ]);
+    assert_eq!(y_pressed(output), [false, false, false]);
+  }
+
+  #[test]
+  fn other_rules_see_held_back_presses_when_they_are_let_through() {
+    let mut simulation = Simulation::new(Profile::from_toml_str(
+      r#"
+        [[combo]]
+        chord = ["LEFT_SHOULDER", "RIGHT_SHOULDER", "BACK"]
+        suppress = true
+        steps = [{ duration_ms = 10, press = ["START"] }]
+
+        [[latch]]
+        on = "LEFT_SHOULDER"
+        press = "A"
+
+        [[turbo]]
+        on = "RIGHT_SHOULDER"
+        target = "B"
+        toggle = true
+
+        [[layer]]
+        on = "BACK"
+        toggle = true
+
+        [[layer.remap]]
+        from = "X"
+        to = "DPAD_UP"
+      "#,
+    ).unwrap());
+    let output = simulation.run([
+      at(0, Button::LeftShoulder | Button::RightShoulder | Button::Back),
+      at(16, Buttons::empty()),
+      at(32, Button::X.into()),
+      // held past the window, then let through and latched
+      at(48, Button::LeftShoulder.into()),
+      at(112, Button::LeftShoulder.into()),
+      at(128, Buttons::empty()),
+      // let through as a tap, which toggles turbo on
+      at(160, Button::RightShoulder.into()),
+      at(176, Buttons::empty()),
+    ]);
+    let pressed: Vec<Buttons> = output.iter().map(|frame| frame.pressed()).collect();
+    // the chord itself toggles nothing
+    assert_eq!(pressed[..3], [Button::Start.into(), Buttons::empty(), Button::X.into()]);
+    assert_eq!(pressed[3..], [
+      Buttons::empty(),
+      Button::LeftShoulder | Button::A,
+      Button::A.into(),
+      Button::A.into(),
+      Button::A | Button::RightShoulder | Button::B,
+    ]);
+  }
+
+  #[test]
+  fn suppressed_chord_buttons_are_held_back_until_decided() {
+    let mut simulation = simulation("chord = [\"LEFT_SHOULDER\", \"RIGHT_SHOULDER\"]\nsuppress = true");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(30, Button::LeftShoulder.into()),
+      at(60, Button::LeftShoulder.into()),
+      at(100, Buttons::empty()),
+      // a tap released before the window is over still reaches the game
+      at(200, Button::RightShoulder.into()),
+      at(216, Buttons::empty()),
+    ]);
+    let pressed: Vec<Buttons> = output.iter().map(|frame| frame.pressed()).collect();
+    assert_eq!(pressed, [
+      Buttons::empty(),
+      Buttons::empty(),
+      Button::LeftShoulder.into(),
+      Buttons::empty(),
+      Buttons::empty(),
+      Button::RightShoulder.into(),
+    ]);
+  }
+
+  #[test]
+  fn sequence_matches_in_order_within_the_window() {
+    let mut simulation = simulation("sequence = [\"DPAD_DOWN\", \"DPAD_RIGHT\", \"A\"]");
+    let output = simulation.run([
+      at(0, Button::DpadDown.into()),
+      at(100, Button::DpadRight.into()),
+      at(200, Button::A.into()),
+      at(250, Buttons::empty()),
+    ]);
+    assert_eq!(y_pressed(output), [false, false, true, true]);
+  }
+
+  #[test]
+  fn sequence_times_out() {
+    let mut simulation = simulation("sequence = [\"DPAD_DOWN\", \"DPAD_RIGHT\", \"A\"]\nwindow_ms = 300");
+    let output = simulation.run([
+      at(0, Button::DpadDown.into()),
+      at(200, Button::DpadRight.into()),
+      at(301, Button::A.into()),
+    ]);
+    assert_eq!(y_pressed(output), [false, false, false]);
+  }
+
+  #[test]
+  fn sequence_is_broken_by_other_presses() {
+    let mut simulation = simulation("sequence = [\"DPAD_DOWN\", \"DPAD_RIGHT\", \"A\"]");
+    let output = simulation.run([
+      at(0, Button::DpadDown.into()),
+      at(50, Button::B.into()),
+      at(100, Button::DpadRight.into()),
+      at(150, Button::A.into()),
+    ]);
+    assert_eq!(y_pressed(output), [false, false, false, false]);
+  }
+
+  #[test]
+  fn rejects_bad_combos() {
+    let steps = "steps = [{ duration_ms = 100, press = [\"Y\"] }]";
+    assert_eq!(error(&format!("chord = [\"A\", \"B\"]\nsequence = [\"A\", \"B\"]\n{}", steps)), "a combo is either a chord or a sequence, not both");
+    assert_eq!(error(steps), "a combo needs a chord or a sequence");
+    assert_eq!(error(&format!("chord = [\"A\", \"A\"]\n{}", steps)), "a chord needs at least two different buttons");
+    assert_eq!(error(&format!("sequence = [\"A\"]\n{}", steps)), "a sequence needs at least two buttons");
+    assert_eq!(error("chord = [\"A\", \"B\"]"), "a combo needs steps to run or a profile to switch to");
+  }
+}
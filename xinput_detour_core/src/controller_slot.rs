@@ -0,0 +1,83 @@
+// Everything the hook keeps between polls for one controller slot.
+
+use std::{path::PathBuf, sync::Arc};
+use super::button_history::ButtonHistory;
+use super::clock::{Clock, MonotonicClock};
+use super::combo::ComboState;
+use super::filter::FilterState;
+use super::function_scheduler::ScheduledFunctionStack;
+use super::latch::LatchState;
+use super::layer::LayerState;
+use super::profile::Profile;
+use super::rumble::RumbleState;
+use super::stick::StickToButtonsState;
+use super::turbo::TurboState;
+use super::virtual_pad::VirtualPad;
+
+/// What the profile rules remember between polls, one entry per rule.
+#[derive(Debug, Default)]
+pub struct RuleState {
+  pub filters: FilterState,
+  pub latches: Vec<LatchState>,
+  pub turbos: Vec<TurboState>,
+  pub stick_to_buttons: Vec<StickToButtonsState>,
+  pub combos: Vec<ComboState>,
+  pub layers: Vec<LayerState>,
+  /// A profile a combo asked to switch to, as written in the profile. The
+  /// hook takes it after the poll since switching needs the profile store.
+  pub profile_switch: Option<PathBuf>,
+}
+
+impl RuleState {
+  pub fn take_profile_switch(&mut self) -> Option<PathBuf> {
+    self.profile_switch.take()
+  }
+}
+
+pub struct ControllerSlot {
+  /// Macros and other functions scheduled on this controller only.
+  pub scheduled_functions: Vec<ScheduledFunctionStack>,
+  /// The buttons of the previous polls, used for the edge queries.
+  pub history: ButtonHistory,
+  /// The time source for the scheduled functions and the history.
+  pub clock: Box<dyn Clock>,
+  pub rule_state: RuleState,
+  // the profile rule_state belongs to
+  rule_state_profile: Option<Arc<Profile>>,
+  /// The game's rumble and the rumble cues of the latest poll.
+  pub rumble: RumbleState,
+  /// The pad the game sees while no controller is connected to the slot.
+  pub virtual_pad: VirtualPad,
+}
+
+impl ControllerSlot {
+  pub fn new(clock: Box<dyn Clock>) -> Self {
+    Self {
+      scheduled_functions: vec![],
+      history: ButtonHistory::default(),
+      clock,
+      rule_state: RuleState::default(),
+      rule_state_profile: None,
+      rumble: RumbleState::default(),
+      virtual_pad: VirtualPad::default(),
+    }
+  }
+
+  /// Starts the rule state over when the profile is not the one it was kept
+  /// for. Rule state is stored by rule position, so after a reload or a switch
+  /// it could belong to another rule. Switching single rules on or off keeps
+  /// it, Profile::apply resets the state of a rule that is switched off.
+  pub fn use_profile(&mut self, profile: &Arc<Profile>) {
+    if !self.rule_state_profile.as_ref().is_some_and(|current| current.has_same_rules(profile)) {
+      self.rule_state = RuleState::default();
+    }
+    self.rule_state_profile = Some(Arc::clone(profile));
+  }
+}
+
+impl Default for ControllerSlot {
+  /// A slot running on real time.
+  fn default() -> Self {
+    Self::new(Box::new(MonotonicClock::new()))
+  }
+}
@@ -0,0 +1,227 @@
+// Device spoofing: what XInputGetCapabilities and XInputGetBatteryInformation
+// report to the game. Games read these to pick button prompts and features,
+// e.g. a racing game may only offer wheel settings to a wheel, and some games
+// never send rumble to a device without force feedback. The profile's
+// `[device]` options change the answers without touching the pad itself, and
+// can put a virtual pad on an empty slot (see virtual_pad.rs).
+
+use serde::Deserialize;
+use super::xinput_state::*;
+
+/// The `XINPUT_DEVSUBTYPE_*` values, by the name used in profiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSubType {
+  Unknown,
+  Gamepad,
+  Wheel,
+  ArcadeStick,
+  FlightStick,
+  DancePad,
+  Guitar,
+  GuitarAlternate,
+  DrumKit,
+  GuitarBass,
+  ArcadePad,
+}
+
+impl DeviceSubType {
+  pub const fn value(self) -> u8 {
+    match self {
+      DeviceSubType::Unknown => XINPUT_DEVSUBTYPE_UNKNOWN,
+      DeviceSubType::Gamepad => XINPUT_DEVSUBTYPE_GAMEPAD,
+      DeviceSubType::Wheel => XINPUT_DEVSUBTYPE_WHEEL,
+      DeviceSubType::ArcadeStick => XINPUT_DEVSUBTYPE_ARCADE_STICK,
+      DeviceSubType::FlightStick => XINPUT_DEVSUBTYPE_FLIGHT_STICK,
+      DeviceSubType::DancePad => XINPUT_DEVSUBTYPE_DANCE_PAD,
+      DeviceSubType::Guitar => XINPUT_DEVSUBTYPE_GUITAR,
+      DeviceSubType::GuitarAlternate => XINPUT_DEVSUBTYPE_GUITAR_ALTERNATE,
+      DeviceSubType::DrumKit => XINPUT_DEVSUBTYPE_DRUM_KIT,
+      DeviceSubType::GuitarBass => XINPUT_DEVSUBTYPE_GUITAR_BASS,
+      DeviceSubType::ArcadePad => XINPUT_DEVSUBTYPE_ARCADE_PAD,
+    }
+  }
+}
+
+/// The `BATTERY_LEVEL_*` values, by the name used in profiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryLevel {
+  Empty,
+  Low,
+  Medium,
+  Full,
+}
+
+impl BatteryLevel {
+  pub const fn value(self) -> u8 {
+    match self {
+      BatteryLevel::Empty => BATTERY_LEVEL_EMPTY,
+      BatteryLevel::Low => BATTERY_LEVEL_LOW,
+      BatteryLevel::Medium => BATTERY_LEVEL_MEDIUM,
+      BatteryLevel::Full => BATTERY_LEVEL_FULL,
+    }
+  }
+}
+
+/// What a wired Xbox 360 pad reports, for slots faked as connected and for
+/// virtual pads.
+pub const STANDARD_GAMEPAD: XInputCapabilities = XInputCapabilities {
+  device_type: XINPUT_DEVTYPE_GAMEPAD,
+  sub_type: XINPUT_DEVSUBTYPE_GAMEPAD,
+  flags: XINPUT_CAPS_FFB_SUPPORTED,
+  gamepad: XInputGamepad {
+    buttons: 0xF3FF,
+    left_trigger: u8::MAX,
+    right_trigger: u8::MAX,
+    thumb_lx: -64,
+    thumb_ly: -64,
+    thumb_rx: -64,
+    thumb_ry: -64,
+  },
+  vibration: XInputVibration { left_motor_speed: 0xFF, right_motor_speed: 0xFF },
+};
+
+/// What the battery of a slot faked as connected or of a virtual pad reports.
+pub const VIRTUAL_PAD_BATTERY: XInputBatteryInformation =
+  XInputBatteryInformation { battery_type: BATTERY_TYPE_WIRED, battery_level: BATTERY_LEVEL_FULL };
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceOptions {
+  /// The subtype to report, e.g. a wheel or an arcade stick.
+  pub sub_type: Option<DeviceSubType>,
+  /// Report a device without force feedback.
+  #[serde(default)]
+  pub hide_force_feedback: bool,
+  /// Report a wireless (true) or wired (false) device.
+  pub wireless: Option<bool>,
+  /// Report a wired gamepad while no device is connected to the slot. Only
+  /// the capability and battery queries see it, XInputGetState still says
+  /// the slot is empty unless `virtual_pad` is set.
+  #[serde(default)]
+  pub connected: bool,
+  /// The battery level to report.
+  pub battery: Option<BatteryLevel>,
+  /// Put a virtual pad on the slot while no device is connected to it. The
+  /// game sees it as a connected wired gamepad.
+  #[serde(default, rename = "virtual")]
+  pub virtual_pad: bool,
+}
+
+impl DeviceOptions {
+  /// The capabilities to report, given what the device reported (None when
+  /// no device is connected). None keeps the slot disconnected.
+  pub fn capabilities(&self, reported: Option<XInputCapabilities>) -> Option<XInputCapabilities> {
+    let mut capabilities = match reported {
+      Some(capabilities) => capabilities,
+      None if self.connected => STANDARD_GAMEPAD,
+      None => return None,
+    };
+
+    if let Some(sub_type) = self.sub_type {
+      capabilities.sub_type = sub_type.value();
+    }
+    if self.hide_force_feedback {
+      capabilities.flags &= !XINPUT_CAPS_FFB_SUPPORTED;
+      capabilities.vibration = XInputVibration::default();
+    }
+    match self.wireless {
+      Some(true) => capabilities.flags |= XINPUT_CAPS_WIRELESS,
+      Some(false) => capabilities.flags &= !XINPUT_CAPS_WIRELESS,
+      None => {},
+    }
+    Some(capabilities)
+  }
+
+  /// The battery of the gamepad to report, given what it reported (None or a
+  /// `BATTERY_TYPE_DISCONNECTED` battery when no device is connected). The
+  /// battery type follows `wireless`.
+  pub fn battery_information(&self, reported: Option<XInputBatteryInformation>) -> Option<XInputBatteryInformation> {
+    let mut battery = match reported {
+      Some(battery) if battery.battery_type != BATTERY_TYPE_DISCONNECTED => battery,
+      _ if self.connected => VIRTUAL_PAD_BATTERY,
+      _ => return reported,
+    };
+
+    match self.wireless {
+      Some(true) if battery.battery_type == BATTERY_TYPE_WIRED => battery.battery_type = BATTERY_TYPE_NIMH,
+      Some(false) => battery.battery_type = BATTERY_TYPE_WIRED,
+      _ => {},
+    }
+    if let Some(level) = self.battery {
+      battery.battery_level = level.value();
+    }
+    Some(battery)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options(toml: &str) -> DeviceOptions {
+    toml::from_str(toml).unwrap()
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<DeviceOptions>(toml).unwrap_err().message().to_owned()
+  }
+
+  fn wireless_pad() -> XInputCapabilities {
+    XInputCapabilities { flags: XINPUT_CAPS_FFB_SUPPORTED | XINPUT_CAPS_WIRELESS, ..STANDARD_GAMEPAD }
+  }
+
+  fn battery(battery_type: u8, battery_level: u8) -> XInputBatteryInformation {
+    XInputBatteryInformation { battery_type, battery_level }
+  }
+
+  #[test]
+  fn no_options_report_the_device_as_it_is() {
+    let options = DeviceOptions::default();
+    assert_eq!(options.capabilities(Some(wireless_pad())), Some(wireless_pad()));
+    assert_eq!(options.capabilities(None), None);
+    assert_eq!(options.battery_information(Some(battery(BATTERY_TYPE_NIMH, BATTERY_LEVEL_LOW))), Some(battery(BATTERY_TYPE_NIMH, BATTERY_LEVEL_LOW)));
+  }
+
+  #[test]
+  fn spoofs_the_capabilities() {
+    let capabilities = options("sub_type = \"wheel\"\nhide_force_feedback = true\nwireless = false").capabilities(Some(wireless_pad())).unwrap();
+    assert_eq!(capabilities.sub_type, XINPUT_DEVSUBTYPE_WHEEL);
+    assert_eq!(capabilities.flags, 0);
+    assert_eq!(capabilities.vibration, XInputVibration::default());
+    assert_eq!(capabilities.gamepad, STANDARD_GAMEPAD.gamepad);
+
+    let capabilities = options("wireless = true").capabilities(Some(STANDARD_GAMEPAD)).unwrap();
+    assert_eq!(capabilities.flags, XINPUT_CAPS_FFB_SUPPORTED | XINPUT_CAPS_WIRELESS);
+  }
+
+  #[test]
+  fn connected_reports_a_pad_on_an_empty_slot() {
+    let options = options("connected = true\nsub_type = \"arcade_stick\"");
+    let capabilities = options.capabilities(None).unwrap();
+    assert_eq!(capabilities, XInputCapabilities { sub_type: XINPUT_DEVSUBTYPE_ARCADE_STICK, ..STANDARD_GAMEPAD });
+    assert_eq!(options.battery_information(Some(battery(BATTERY_TYPE_DISCONNECTED, 0))), Some(VIRTUAL_PAD_BATTERY));
+    assert_eq!(options.battery_information(None), Some(VIRTUAL_PAD_BATTERY));
+  }
+
+  #[test]
+  fn spoofs_the_battery() {
+    let wireless = options("wireless = true\nbattery = \"low\"");
+    assert_eq!(wireless.battery_information(Some(battery(BATTERY_TYPE_WIRED, BATTERY_LEVEL_FULL))), Some(battery(BATTERY_TYPE_NIMH, BATTERY_LEVEL_LOW)));
+    // a real battery type is kept
+    assert_eq!(wireless.battery_information(Some(battery(BATTERY_TYPE_ALKALINE, BATTERY_LEVEL_FULL))), Some(battery(BATTERY_TYPE_ALKALINE, BATTERY_LEVEL_LOW)));
+    // an empty slot stays empty
+    assert_eq!(wireless.battery_information(None), None);
+
+    let wired = options("wireless = false");
+    assert_eq!(wired.battery_information(Some(battery(BATTERY_TYPE_NIMH, BATTERY_LEVEL_MEDIUM))), Some(battery(BATTERY_TYPE_WIRED, BATTERY_LEVEL_MEDIUM)));
+  }
+
+  #[test]
+  fn rejects_unknown_settings() {
+    assert!(error("sub_type = \"keyboard\"").starts_with("unknown variant `keyboard`"));
+    assert!(error("battery = \"half\"").starts_with("unknown variant `half`"));
+    assert!(error("wireles = true").starts_with("unknown field `wireles`"));
+  }
+}
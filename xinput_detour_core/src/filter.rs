@@ -0,0 +1,290 @@
+// Smoothing filters for noisy stick and trigger axes, e.g. worn sticks that
+// jitter around a position. They run on the pad before any other rule, so
+// deadzones and mappings see the smoothed values.
+//
+// Values are in the axis' raw units: -32,768 to 32,767 for a stick axis and
+// 0 to 255 for a trigger.
+
+use std::{convert::TryFrom, f32::consts::TAU, time::Duration};
+use serde::Deserialize;
+use super::mutable_xinput_state::MutableXInputState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "AxisFilterConfig")]
+pub enum AxisFilter {
+  /// Exponential moving average: every poll moves `alpha` (0 to 1) of the
+  /// way to the new value. Lower is smoother but lags more.
+  Ema { alpha: f32 },
+  /// The 1€ filter: smooths heavily while the axis is still (`min_cutoff` in
+  /// Hz, lower is smoother) and less the faster it moves (`beta`), so quick
+  /// movements do not lag. `d_cutoff` smooths the speed estimate.
+  OneEuro { min_cutoff: f32, beta: f32, d_cutoff: f32 },
+  /// Ignores changes smaller than `threshold`.
+  Hysteresis { threshold: f32 },
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FilterKind {
+  Ema,
+  OneEuro,
+  Hysteresis,
+}
+
+// The profile format, checked before it becomes an AxisFilter
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AxisFilterConfig {
+  filter: FilterKind,
+  alpha: Option<f32>,
+  min_cutoff: Option<f32>,
+  beta: Option<f32>,
+  d_cutoff: Option<f32>,
+  threshold: Option<f32>,
+}
+
+impl TryFrom<AxisFilterConfig> for AxisFilter {
+  type Error = String;
+
+  fn try_from(config: AxisFilterConfig) -> Result<Self, Self::Error> {
+    let one_euro_fields = config.min_cutoff.is_some() || config.beta.is_some() || config.d_cutoff.is_some();
+    let filter = match config.filter {
+      FilterKind::Ema if one_euro_fields || config.threshold.is_some() => return Err("an ema filter only takes alpha".to_owned()),
+      FilterKind::Ema => match config.alpha {
+        Some(alpha) if alpha > 0.0 && alpha <= 1.0 => AxisFilter::Ema { alpha },
+        Some(alpha) => return Err(format!("alpha must be above 0 and at most 1, got {}", alpha)),
+        None => return Err("an ema filter needs alpha".to_owned()),
+      },
+      FilterKind::OneEuro if config.alpha.is_some() || config.threshold.is_some() => {
+        return Err("a one_euro filter only takes min_cutoff, beta and d_cutoff".to_owned());
+      },
+      FilterKind::OneEuro => {
+        let min_cutoff = config.min_cutoff.unwrap_or(1.0);
+        let beta = config.beta.unwrap_or(0.0);
+        let d_cutoff = config.d_cutoff.unwrap_or(1.0);
+        if !(min_cutoff > 0.0 && d_cutoff > 0.0 && beta >= 0.0) {
+          return Err(format!(
+            "min_cutoff and d_cutoff must be above 0 and beta at least 0, got {}, {} and {}",
+            min_cutoff, d_cutoff, beta
+          ));
+        }
+        AxisFilter::OneEuro { min_cutoff, beta, d_cutoff }
+      },
+      FilterKind::Hysteresis if one_euro_fields || config.alpha.is_some() => return Err("a hysteresis filter only takes threshold".to_owned()),
+      FilterKind::Hysteresis => match config.threshold {
+        Some(threshold) if threshold > 0.0 => AxisFilter::Hysteresis { threshold },
+        Some(threshold) => return Err(format!("threshold must be above 0, got {}", threshold)),
+        None => return Err("a hysteresis filter needs a threshold".to_owned()),
+      },
+    };
+    Ok(filter)
+  }
+}
+
+/// What the filter of one axis remembers between polls.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AxisFilterState {
+  // the last output and when it was produced
+  value: Option<f32>,
+  at: Duration,
+  // the smoothed speed, for the 1€ filter
+  speed: f32,
+}
+
+impl AxisFilter {
+  /// Filters the axis value of the poll at `now`.
+  pub fn apply(&self, value: f32, now: Duration, state: &mut AxisFilterState) -> f32 {
+    let Some(previous) = state.value else {
+      *state = AxisFilterState { value: Some(value), at: now, speed: 0.0 };
+      return value;
+    };
+    let elapsed = now.saturating_sub(state.at).as_secs_f32();
+
+    let filtered = match *self {
+      AxisFilter::Ema { alpha } => previous + alpha * (value - previous),
+      AxisFilter::Hysteresis { threshold } => if (value - previous).abs() >= threshold { value } else { previous },
+      AxisFilter::OneEuro { .. } if elapsed <= 0.0 => previous,
+      AxisFilter::OneEuro { min_cutoff, beta, d_cutoff } => {
+        let smoothing = |cutoff: f32| 1.0 / (1.0 + 1.0 / (TAU * cutoff * elapsed));
+        let speed = (value - previous) / elapsed;
+        state.speed += smoothing(d_cutoff) * (speed - state.speed);
+        let cutoff = min_cutoff + beta * state.speed.abs();
+        previous + smoothing(cutoff) * (value - previous)
+      },
+    };
+
+    state.value = Some(filtered);
+    state.at = now;
+    filtered
+  }
+}
+
+/// The filter of every axis. `left_stick` and `right_stick` apply to both
+/// axes of a stick unless the axis has its own filter.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisFilters {
+  pub left_stick: Option<AxisFilter>,
+  pub right_stick: Option<AxisFilter>,
+  pub left_x: Option<AxisFilter>,
+  pub left_y: Option<AxisFilter>,
+  pub right_x: Option<AxisFilter>,
+  pub right_y: Option<AxisFilter>,
+  pub left_trigger: Option<AxisFilter>,
+  pub right_trigger: Option<AxisFilter>,
+}
+
+/// What the filters remember between polls, one entry per axis in the order
+/// left x, left y, right x, right y, left trigger and right trigger.
+pub type FilterState = [AxisFilterState; 6];
+
+impl AxisFilters {
+  pub fn is_empty(&self) -> bool {
+    self.axes().iter().all(Option::is_none)
+  }
+
+  fn axes(&self) -> [Option<&AxisFilter>; 6] {
+    [
+      self.left_x.as_ref().or(self.left_stick.as_ref()),
+      self.left_y.as_ref().or(self.left_stick.as_ref()),
+      self.right_x.as_ref().or(self.right_stick.as_ref()),
+      self.right_y.as_ref().or(self.right_stick.as_ref()),
+      self.left_trigger.as_ref(),
+      self.right_trigger.as_ref(),
+    ]
+  }
+
+  /// Filters every axis that has a filter.
+  pub fn apply(&self, controller_state: &MutableXInputState, state: &mut FilterState) {
+    let now = controller_state.now();
+    let (left_x, left_y) = controller_state.left_stick_raw();
+    let (right_x, right_y) = controller_state.right_stick_raw();
+    let values = [
+      left_x as f32,
+      left_y as f32,
+      right_x as f32,
+      right_y as f32,
+      controller_state.left_trigger() as f32,
+      controller_state.right_trigger() as f32,
+    ];
+
+    let mut filtered = values;
+    for (((filter, value), state), filtered) in self.axes().into_iter().zip(values).zip(state.iter_mut()).zip(filtered.iter_mut()) {
+      match filter {
+        Some(filter) => *filtered = filter.apply(value, now, state),
+        None => *state = AxisFilterState::default(),
+      }
+    }
+
+    let stick = |value: f32| value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    let trigger = |value: f32| value.round().clamp(0.0, u8::MAX as f32) as u8;
+    controller_state.set_left_stick_raw((stick(filtered[0]), stick(filtered[1])));
+    controller_state.set_right_stick_raw((stick(filtered[2]), stick(filtered[3])));
+    controller_state.set_left_trigger(trigger(filtered[4]));
+    controller_state.set_right_trigger(trigger(filtered[5]));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::profile::{Profile, RuleId, RuleKind};
+  use crate::simulation::{Frame, Simulation};
+  use crate::xinput_state::XInputGamepad;
+
+  const POLL: Duration = Duration::from_millis(16);
+
+  // Feeds the values one poll apart and returns the filtered values
+  fn run(filter: AxisFilter, values: &[f32]) -> Vec<f32> {
+    let mut state = AxisFilterState::default();
+    values.iter().enumerate().map(|(i, &value)| filter.apply(value, POLL * i as u32, &mut state)).collect()
+  }
+
+  #[test]
+  fn ema_moves_alpha_of_the_way_every_poll() {
+    assert_eq!(run(AxisFilter::Ema { alpha: 0.25 }, &[0.0, 1024.0, 1024.0, 1024.0]), [0.0, 256.0, 448.0, 592.0]);
+    assert_eq!(run(AxisFilter::Ema { alpha: 1.0 }, &[0.0, 1024.0, -5.0]), [0.0, 1024.0, -5.0]);
+  }
+
+  #[test]
+  fn hysteresis_holds_until_the_change_reaches_the_threshold() {
+    let filter = AxisFilter::Hysteresis { threshold: 100.0 };
+    assert_eq!(run(filter, &[0.0, 99.0, -99.0, 100.0, 150.0, 0.0]), [0.0, 0.0, 0.0, 100.0, 100.0, 0.0]);
+  }
+
+  #[test]
+  fn one_euro_smooths_jitter_but_follows_quick_moves() {
+    let filter = AxisFilter::OneEuro { min_cutoff: 1.0, beta: 0.01, d_cutoff: 1.0 };
+    let jitter: Vec<f32> = (0..20).map(|i| if i % 2 == 0 { 1000.0 } else { 1040.0 }).collect();
+    let smoothed = run(filter, &jitter);
+    // every poll moves a small part of the 40 the input jumps by
+    assert!(smoothed.windows(2).all(|pair| (pair[1] - pair[0]).abs() < 10.0), "{:?}", smoothed);
+
+    let flick = run(filter, &[0.0, 20000.0]);
+    assert!(flick[1] > 19000.0, "{:?}", flick);
+    // without the speed term it lags far behind
+    let lagging = run(AxisFilter::OneEuro { min_cutoff: 1.0, beta: 0.0, d_cutoff: 1.0 }, &[0.0, 20000.0]);
+    assert!(lagging[1] < 2000.0, "{:?}", lagging);
+  }
+
+  fn simulation(filters: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("[filters]\n{}", filters)).unwrap())
+  }
+
+  fn pad(at: u32, thumb_lx: i16, thumb_ly: i16, left_trigger: u8) -> Frame {
+    Frame::new(POLL * at, XInputGamepad { thumb_lx, thumb_ly, left_trigger, ..Default::default() })
+  }
+
+  #[test]
+  fn an_axis_filter_replaces_the_stick_filter() {
+    let mut simulation = simulation(
+      "left_stick = { filter = \"hysteresis\", threshold = 1000.0 }\nleft_x = { filter = \"ema\", alpha = 0.5 }",
+    );
+    let output = simulation.run([pad(0, 0, 0, 0), pad(1, 500, 500, 0)]);
+    assert_eq!((output[1].gamepad.thumb_lx, output[1].gamepad.thumb_ly), (250, 0));
+  }
+
+  #[test]
+  fn switching_the_filters_off_forgets_their_state() {
+    let mut simulation = simulation("left_trigger = { filter = \"ema\", alpha = 0.5 }");
+    let output = simulation.run([pad(0, 0, 0, 0), pad(1, 0, 0, 200)]);
+    assert_eq!(output[1].gamepad.left_trigger, 100);
+
+    let filters = RuleId::new(RuleKind::Filters, 0);
+    let mut off = simulation.profile().clone();
+    off.set_rule_enabled(filters, false);
+    simulation.set_profile(off);
+    assert_eq!(simulation.step(pad(2, 0, 0, 200)).gamepad.left_trigger, 200);
+
+    // back on, the filter starts from the current value instead of 100
+    let mut on = simulation.profile().clone();
+    on.set_rule_enabled(filters, true);
+    simulation.set_profile(on);
+    assert_eq!(simulation.step(pad(3, 0, 0, 255)).gamepad.left_trigger, 255);
+    assert_eq!(simulation.step(pad(4, 0, 0, 55)).gamepad.left_trigger, 155);
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<AxisFilter>(toml).unwrap_err().message().to_owned()
+  }
+
+  #[test]
+  fn rejects_fields_of_other_filters() {
+    assert_eq!(error("filter = \"ema\"\nalpha = 0.5\nbeta = 1.0"), "an ema filter only takes alpha");
+    assert_eq!(error("filter = \"one_euro\"\nthreshold = 100.0"), "a one_euro filter only takes min_cutoff, beta and d_cutoff");
+    assert_eq!(error("filter = \"hysteresis\"\nthreshold = 100.0\nalpha = 0.5"), "a hysteresis filter only takes threshold");
+  }
+
+  #[test]
+  fn rejects_out_of_range_settings() {
+    assert_eq!(error("filter = \"ema\""), "an ema filter needs alpha");
+    assert_eq!(error("filter = \"ema\"\nalpha = 0.0"), "alpha must be above 0 and at most 1, got 0");
+    assert_eq!(error("filter = \"ema\"\nalpha = 1.5"), "alpha must be above 0 and at most 1, got 1.5");
+    assert_eq!(
+      error("filter = \"one_euro\"\nmin_cutoff = 0.0"),
+      "min_cutoff and d_cutoff must be above 0 and beta at least 0, got 0, 1 and 0"
+    );
+    assert_eq!(error("filter = \"hysteresis\""), "a hysteresis filter needs a threshold");
+    assert_eq!(error("filter = \"hysteresis\"\nthreshold = -1.0"), "threshold must be above 0, got -1");
+  }
+}
@@ -1,13 +1,14 @@
+use super::clock::Clock;
 use super::mutable_xinput_state::{MutableXInputState};
-use std::time::{SystemTime, Duration};
+use std::time::Duration;
 
 pub struct ScheduledFunction {
   duration: std::time::Duration, 
-  func: Box<dyn Fn(&MutableXInputState) -> ()>,
+  func: Box<dyn Fn(&MutableXInputState)>,
 }
 
 impl ScheduledFunction {
-    pub fn new(duration: Duration, func: Box<dyn Fn(&MutableXInputState) -> ()>) -> Self {
+    pub fn new(duration: Duration, func: Box<dyn Fn(&MutableXInputState)>) -> Self {
       Self {
         duration, 
         func,
@@ -17,45 +18,49 @@ impl ScheduledFunction {
 
 impl ::std::fmt::Debug for ScheduledFunction {
   fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-    write!(f, "ScheduledFunction: Box<dyn Fn(&MutableXInputState) -> ()>")
+    write!(f, "ScheduledFunction: Box<dyn Fn(&MutableXInputState)>")
   }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ScheduledFunctionState {
-  Ongoing { next_end: SystemTime },
+  Ongoing { next_end: Duration },
   Completed,
 }
 
 #[derive(Debug)]
 pub struct ScheduledFunctionStack {
-  state: ScheduledFunctionState,
+  // None until the first poll, which is when the first function starts
+  state: Option<ScheduledFunctionState>,
   functions: Vec<ScheduledFunction>,
 }
 
 impl ScheduledFunctionStack {
   pub fn new(functions: Vec<ScheduledFunction>) -> Self {
     Self {
-      state: ScheduledFunctionState::Ongoing { next_end: SystemTime::now() + functions[0].duration },
+      state: None,
       functions,
     }
   }
 
-  pub fn poll(&mut self, controller_state: &MutableXInputState) -> ScheduledFunctionState {
-    match self.state {
+  // each function runs for its duration as measured by `clock`
+  pub fn poll(&mut self, controller_state: &MutableXInputState, clock: &(impl Clock + ?Sized)) -> ScheduledFunctionState {
+    let now = clock.now();
+    let state = *self.state.get_or_insert(ScheduledFunctionState::Ongoing { next_end: now + self.functions[0].duration });
+    match state {
       ScheduledFunctionState::Ongoing { next_end } => {
-        if SystemTime::now() < next_end {
+        if now < next_end {
           (self.functions[0].func)(controller_state);
           ScheduledFunctionState::Ongoing { next_end }
         } else {
           self.functions.remove(0);
 
           if self.functions.is_empty() {
-            self.state = ScheduledFunctionState::Completed;
+            self.state = Some(ScheduledFunctionState::Completed);
             ScheduledFunctionState::Completed
           } else {
             let next_end = next_end + self.functions[0].duration;
-            self.state = ScheduledFunctionState::Ongoing { next_end };
+            self.state = Some(ScheduledFunctionState::Ongoing { next_end });
             ScheduledFunctionState::Ongoing { next_end }
           }
         }
@@ -81,8 +86,8 @@ unsafe impl Sync for ScheduledFunctionStack {}
 #[macro_export]
 macro_rules! scheduled_function_stack {
   ($($time_ms:literal => $func:expr),+ $(,)+) => {
-    ScheduledFunctionStack::new(vec![
-      $(ScheduledFunction::new(::std::time::Duration::from_millis($time_ms), Box::new($func))),+
+    $crate::function_scheduler::ScheduledFunctionStack::new(vec![
+      $($crate::function_scheduler::ScheduledFunction::new(::std::time::Duration::from_millis($time_ms), Box::new($func))),+
     ])
   };
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+use super::mutable_xinput_state::*;
+use super::function_scheduler::*;
+
+// a user-defined function that modifies the controller state based on the current state
+// user_index is the XInput slot (0-3) the state belongs to and scheduled_functions is
+// that slot's own queue, so rules can be limited to a single player
+// it runs after the profile rules and does nothing by default, most remaps belong in the
+// profile (see profiles\default.toml) so they can be changed without recompiling
+// some examples are included below, uncomment them (and `use super::button::Button;`) to try them out
+// (the examples push to scheduled_functions, so it stays a Vec)
+#[allow(unused_variables, clippy::ptr_arg)]
+pub fn handle_controller_state(user_index: u32, controller_state: &MutableXInputState, scheduled_functions: &mut Vec<ScheduledFunctionStack>) {
+  // Example 1: 
+  // When the B button is pressed, press the X button
+  // if controller_state.east_button() {
+  //   controller_state.set_west_button(ButtonState::DOWN);
+  // }
+  //
+  // Example 2:
+  // When the D-Pad Up button is pressed, press the A button for
+  // 2 seconds, then release it for 2 seconds, then repeat
+  // just_pressed starts the sequence once per press instead of on every poll
+  // if controller_state.just_pressed(Button::DpadUp) {
+  //   scheduled_functions.push(
+  //     scheduled_function_stack!(
+  //       2000 => |cs| { cs.set_south_button(ButtonState::DOWN) },
+  //       2000 => |cs| { cs.set_south_button(ButtonState::UP) },
+  //       2000 => |cs| { cs.set_south_button(ButtonState::DOWN) },
+  //       2000 => |cs| { cs.set_south_button(ButtonState::UP) },
+  //       2000 => |cs| { cs.set_south_button(ButtonState::DOWN) },
+  //       2000 => |cs| { cs.set_south_button(ButtonState::UP) },
+  //     )
+  //   );
+  // }
+  //
+  // Example 3:
+  // When the D-Pad Down button is pressed, interupt the D-Pad Down button,
+  // the press the right trigger for 2 seconds, then release it for 2 seconds,
+  // then repeat
+  // if controller_state.arrow_down() {
+  //   // interupt the D-Pad Down button
+  //   controller_state.set_arrow_down(ButtonState::UP);
+  //
+  //   // schedule the right trigger to be pressed and released, once per press
+  //   if controller_state.just_pressed(Button::DpadDown) {
+  //     scheduled_functions.push(
+  //       scheduled_function_stack!(
+  //         2000 => |cs| { cs.set_right_trigger(u8::MAX) },
+  //         2000 => |cs| { cs.set_right_trigger(u8::MIN) },
+  //         2000 => |cs| { cs.set_right_trigger(u8::MAX) },
+  //         2000 => |cs| { cs.set_right_trigger(u8::MIN) },
+  //         2000 => |cs| { cs.set_right_trigger(u8::MAX) },
+  //         2000 => |cs| { cs.set_right_trigger(u8::MIN) },
+  //       )
+  //     );
+  //   }
+  // }
+  //
+  // Example 4:
+  // Swap the left and right sticks, but only for the first player
+  // if user_index == 0 {
+  //   let left_stick = controller_state.left_stick_raw();
+  //   let right_stick = controller_state.right_stick_raw();
+  //   controller_state.set_left_stick_raw(right_stick);
+  //   controller_state.set_right_stick_raw(left_stick);
+  // }
+}
@@ -0,0 +1,463 @@
+// The control channel protocol between the injector and the injected DLL.
+//
+// Every frame is one JSON object with a "type" field. The client (injector)
+// opens with `Request::Hello` and the server (DLL) answers with its own
+// `Message::Hello`; requests are only accepted once both sides agree on
+// `PROTOCOL_VERSION`. Replies are sent in request order, interleaved with any
+// logs or controller states the client subscribed to.
+//
+// Requests with an optional `user_index` apply to every controller slot when
+// it is left out.
+
+use std::{io, marker::PhantomData, time::Duration};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use super::ipc_transport::Transport;
+use super::playback::{PlaybackOptions, PlaybackState};
+use super::profile::RuleId;
+use super::xinput_state::{XInputGamepad, XInputState, XUSER_MAX_COUNT};
+
+/// Bump this whenever `Request` or `Message` change incompatibly.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// The named pipe the DLL listens on.
+pub const PIPE_NAME: &str = r"\\.\pipe\rusty_xinput_detour";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+  Hello { version: u32 },
+  Status,
+  ReloadProfile {
+    #[serde(default)]
+    user_index: Option<u32>,
+  },
+  SwitchProfile {
+    path: String,
+    #[serde(default)]
+    user_index: Option<u32>,
+  },
+  SetRuleEnabled {
+    rule: RuleId,
+    enabled: bool,
+    #[serde(default)]
+    user_index: Option<u32>,
+  },
+  /// Runs a macro from the slot's profile on that slot.
+  TriggerMacro { index: usize, user_index: u32 },
+  Subscribe { logs: bool, controller_state: bool },
+  /// Records every poll to a file in the DLL's file system (see recording.rs).
+  StartRecording { path: String },
+  StopRecording,
+  /// Plays a recording from the DLL's file system, replacing any playback
+  /// already running (see playback.rs).
+  StartPlayback {
+    path: String,
+    #[serde(default)]
+    options: PlaybackOptions,
+  },
+  PausePlayback { paused: bool },
+  SeekPlayback { position_ms: u64 },
+  StopPlayback,
+  /// Plugs a virtual pad into the slot or unplugs it (see virtual_pad.rs).
+  SetVirtualPad { user_index: u32, plugged_in: bool },
+  /// Sets the buttons and axes of the slot's virtual pad. The rules run on
+  /// them like on a real pad's.
+  SetVirtualPadState { user_index: u32, gamepad: XInputGamepad },
+  /// Ends the session. The server answers with `Message::Goodbye`.
+  Goodbye,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlotStatus {
+  pub user_index: u32,
+  pub profile_name: String,
+  pub profile_path: String,
+  pub scheduled_functions: usize,
+  pub disabled_rules: Vec<RuleId>,
+  /// Whether the game sees a virtual pad on the slot.
+  #[serde(default)]
+  pub virtual_pad: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackStatus {
+  pub path: String,
+  pub state: PlaybackState,
+  pub position_ms: u64,
+  pub duration_ms: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HookStatus {
+  pub hooked: bool,
+  /// How many times the game has called XInputGetState.
+  pub polls: u64,
+  pub slots: Vec<SlotStatus>,
+  /// The file being recorded to, if any.
+  #[serde(default)]
+  pub recording: Option<String>,
+  #[serde(default)]
+  pub playback: Option<PlaybackStatus>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+  Hello { version: u32 },
+  Status { status: HookStatus },
+  ProfileLoaded { name: String },
+  /// The request succeeded and has nothing else to report.
+  Done,
+  Error { message: String },
+  Log { message: String },
+  ControllerState { user_index: u32, state: XInputState },
+  RecordingStopped { path: String, frames: u64 },
+  Goodbye,
+}
+
+/// Sends `S` and receives `R` over a transport.
+pub struct Endpoint<T, S, R> {
+  transport: T,
+  messages: PhantomData<fn(S) -> R>,
+}
+
+impl<T: Transport, S: Serialize, R: DeserializeOwned> Endpoint<T, S, R> {
+  pub fn new(transport: T) -> Self {
+    Self { transport, messages: PhantomData }
+  }
+
+  pub fn send(&mut self, message: &S) -> io::Result<()> {
+    let frame = serde_json::to_string(message)?;
+    self.transport.send(&frame)
+  }
+
+  /// Returns the next message if one has arrived. A frame that is not a
+  /// valid message is reported as an `InvalidData` error.
+  pub fn try_recv(&mut self) -> io::Result<Option<R>> {
+    match self.transport.try_recv()? {
+      Some(frame) => serde_json::from_str(&frame)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+      None => Ok(None),
+    }
+  }
+}
+
+/// Everything the control channel can do to the running hook. The DLL
+/// implements this on top of its global state.
+pub trait ControlHandler {
+  fn status(&self) -> HookStatus;
+  /// Returns the name of the reloaded profile.
+  fn reload_profile(&self, user_index: Option<u32>) -> Result<String, String>;
+  /// Returns the name of the new profile.
+  fn switch_profile(&self, path: &str, user_index: Option<u32>) -> Result<String, String>;
+  fn set_rule_enabled(&self, rule: RuleId, enabled: bool, user_index: Option<u32>) -> Result<(), String>;
+  fn trigger_macro(&self, index: usize, user_index: u32) -> Result<(), String>;
+  fn start_recording(&self, path: &str) -> Result<(), String>;
+  /// Returns the path of the finished recording and how many frames it has.
+  fn stop_recording(&self) -> Result<(String, u64), String>;
+  fn start_playback(&self, path: &str, options: PlaybackOptions) -> Result<(), String>;
+  fn pause_playback(&self, paused: bool) -> Result<(), String>;
+  fn seek_playback(&self, position: Duration) -> Result<(), String>;
+  fn stop_playback(&self) -> Result<(), String>;
+  fn set_virtual_pad(&self, user_index: u32, plugged_in: bool) -> Result<(), String>;
+  fn set_virtual_pad_state(&self, user_index: u32, gamepad: XInputGamepad) -> Result<(), String>;
+  /// Takes the log messages produced since the last call.
+  fn take_logs(&self) -> Vec<String>;
+  /// The latest state handed to the game for each connected controller.
+  fn controller_states(&self) -> Vec<(u32, XInputState)>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+  Open,
+  Closed,
+}
+
+/// The server side of one connected client.
+pub struct ControlSession<T> {
+  endpoint: Endpoint<T, Message, Request>,
+  greeted: bool,
+  logs: bool,
+  controller_state: bool,
+  sent_states: Vec<(u32, XInputState)>,
+}
+
+impl<T: Transport> ControlSession<T> {
+  pub fn new(transport: T) -> Self {
+    Self {
+      endpoint: Endpoint::new(transport),
+      greeted: false,
+      logs: false,
+      controller_state: false,
+      sent_states: vec![],
+    }
+  }
+
+  /// Answers every pending request and sends any subscribed logs and
+  /// controller states. Returns `Closed` once the session is over; an error
+  /// means the client went away.
+  pub fn poll(&mut self, handler: &impl ControlHandler) -> io::Result<SessionState> {
+    loop {
+      let request = match self.endpoint.try_recv() {
+        Ok(Some(request)) => request,
+        Ok(None) => break,
+        Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+          self.endpoint.send(&Message::Error { message: format!("invalid request: {}", error) })?;
+          continue;
+        },
+        Err(error) => return Err(error),
+      };
+
+      if self.handle_request(request, handler)? == SessionState::Closed {
+        return Ok(SessionState::Closed);
+      }
+    }
+
+    if self.logs {
+      for message in handler.take_logs() {
+        self.endpoint.send(&Message::Log { message })?;
+      }
+    }
+
+    if self.controller_state {
+      let states = handler.controller_states();
+      for (user_index, state) in states.iter().filter(|state| !self.sent_states.contains(*state)) {
+        self.endpoint.send(&Message::ControllerState { user_index: *user_index, state: *state })?;
+      }
+      self.sent_states = states;
+    }
+
+    Ok(SessionState::Open)
+  }
+
+  fn handle_request(&mut self, request: Request, handler: &impl ControlHandler) -> io::Result<SessionState> {
+    let reply = match request {
+      Request::Hello { version } if version == PROTOCOL_VERSION => {
+        self.greeted = true;
+        Message::Hello { version: PROTOCOL_VERSION }
+      },
+      Request::Hello { version } => {
+        self.endpoint.send(&Message::Error {
+          message: format!("protocol version {} is not supported, the DLL speaks version {}", version, PROTOCOL_VERSION),
+        })?;
+        return Ok(SessionState::Closed);
+      },
+      _ if !self.greeted => Message::Error { message: "send hello before any other request".to_owned() },
+      Request::Status => Message::Status { status: handler.status() },
+      Request::ReloadProfile { user_index: Some(user_index) } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::ReloadProfile { user_index } => match handler.reload_profile(user_index) {
+        Ok(name) => Message::ProfileLoaded { name },
+        Err(message) => Message::Error { message },
+      },
+      Request::SwitchProfile { user_index: Some(user_index), .. } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::SwitchProfile { path, user_index } => match handler.switch_profile(&path, user_index) {
+        Ok(name) => Message::ProfileLoaded { name },
+        Err(message) => Message::Error { message },
+      },
+      Request::SetRuleEnabled { user_index: Some(user_index), .. } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::SetRuleEnabled { rule, enabled, user_index } => done_or_error(handler.set_rule_enabled(rule, enabled, user_index)),
+      Request::TriggerMacro { user_index, .. } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::TriggerMacro { index, user_index } => done_or_error(handler.trigger_macro(index, user_index)),
+      Request::StartRecording { path } => done_or_error(handler.start_recording(&path)),
+      Request::StopRecording => match handler.stop_recording() {
+        Ok((path, frames)) => Message::RecordingStopped { path, frames },
+        Err(message) => Message::Error { message },
+      },
+      Request::StartPlayback { path, options } => done_or_error(handler.start_playback(&path, options)),
+      Request::PausePlayback { paused } => done_or_error(handler.pause_playback(paused)),
+      Request::SeekPlayback { position_ms } => done_or_error(handler.seek_playback(Duration::from_millis(position_ms))),
+      Request::StopPlayback => done_or_error(handler.stop_playback()),
+      Request::SetVirtualPad { user_index, .. } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::SetVirtualPad { user_index, plugged_in } => done_or_error(handler.set_virtual_pad(user_index, plugged_in)),
+      Request::SetVirtualPadState { user_index, .. } if user_index >= XUSER_MAX_COUNT => invalid_slot(user_index),
+      Request::SetVirtualPadState { user_index, gamepad } => done_or_error(handler.set_virtual_pad_state(user_index, gamepad)),
+      Request::Subscribe { logs, controller_state } => {
+        self.logs = logs;
+        self.controller_state = controller_state;
+        self.sent_states.clear();
+        Message::Done
+      },
+      Request::Goodbye => {
+        self.endpoint.send(&Message::Goodbye)?;
+        return Ok(SessionState::Closed);
+      },
+    };
+
+    self.endpoint.send(&reply)?;
+    Ok(SessionState::Open)
+  }
+}
+
+fn invalid_slot(user_index: u32) -> Message {
+  Message::Error { message: format!("user_index {} is out of range, XInput has {} slots", user_index, XUSER_MAX_COUNT) }
+}
+
+fn done_or_error(result: Result<(), String>) -> Message {
+  match result {
+    Ok(()) => Message::Done,
+    Err(message) => Message::Error { message },
+  }
+}
+
+/// The injector side of the control channel.
+pub struct ControlClient<T> {
+  endpoint: Endpoint<T, Request, Message>,
+}
+
+impl<T: Transport> ControlClient<T> {
+  /// Wraps a connected transport and sends the hello request. The server's
+  /// hello (or a version error) is the first message `try_recv` returns.
+  pub fn new(transport: T) -> io::Result<Self> {
+    let mut endpoint = Endpoint::new(transport);
+    endpoint.send(&Request::Hello { version: PROTOCOL_VERSION })?;
+    Ok(Self { endpoint })
+  }
+
+  pub fn send(&mut self, request: &Request) -> io::Result<()> {
+    self.endpoint.send(request)
+  }
+
+  pub fn try_recv(&mut self) -> io::Result<Option<Message>> {
+    self.endpoint.try_recv()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+  use super::*;
+  use crate::ipc_transport::{loopback, LoopbackTransport};
+  use crate::profile::RuleKind;
+
+  // Remembers the rule changes it was asked for and accepts everything else
+  #[derive(Default)]
+  struct FakeHandler {
+    rule_changes: RefCell<Vec<(RuleId, bool, Option<u32>)>>,
+  }
+
+  impl ControlHandler for FakeHandler {
+    fn status(&self) -> HookStatus {
+      HookStatus { hooked: true, polls: 0, slots: vec![], recording: None, playback: None }
+    }
+    fn reload_profile(&self, _user_index: Option<u32>) -> Result<String, String> {
+      Ok("default".to_owned())
+    }
+    fn switch_profile(&self, _path: &str, _user_index: Option<u32>) -> Result<String, String> {
+      Ok("other".to_owned())
+    }
+    fn set_rule_enabled(&self, rule: RuleId, enabled: bool, user_index: Option<u32>) -> Result<(), String> {
+      self.rule_changes.borrow_mut().push((rule, enabled, user_index));
+      Ok(())
+    }
+    fn trigger_macro(&self, _index: usize, _user_index: u32) -> Result<(), String> {
+      Ok(())
+    }
+    fn start_recording(&self, _path: &str) -> Result<(), String> {
+      Ok(())
+    }
+    fn stop_recording(&self) -> Result<(String, u64), String> {
+      Err("not recording".to_owned())
+    }
+    fn start_playback(&self, _path: &str, _options: PlaybackOptions) -> Result<(), String> {
+      Ok(())
+    }
+    fn pause_playback(&self, _paused: bool) -> Result<(), String> {
+      Ok(())
+    }
+    fn seek_playback(&self, _position: Duration) -> Result<(), String> {
+      Ok(())
+    }
+    fn stop_playback(&self) -> Result<(), String> {
+      Ok(())
+    }
+    fn set_virtual_pad(&self, _user_index: u32, _plugged_in: bool) -> Result<(), String> {
+      Ok(())
+    }
+    fn set_virtual_pad_state(&self, _user_index: u32, _gamepad: XInputGamepad) -> Result<(), String> {
+      Ok(())
+    }
+    fn take_logs(&self) -> Vec<String> {
+      vec![]
+    }
+    fn controller_states(&self) -> Vec<(u32, XInputState)> {
+      vec![]
+    }
+  }
+
+  fn connect() -> (ControlClient<LoopbackTransport>, ControlSession<LoopbackTransport>) {
+    let (client, server) = loopback();
+    (ControlClient::new(client).unwrap(), ControlSession::new(server))
+  }
+
+  fn messages(client: &mut ControlClient<LoopbackTransport>) -> Vec<Message> {
+    std::iter::from_fn(|| client.try_recv().unwrap()).collect()
+  }
+
+  #[test]
+  fn hello_opens_the_session() {
+    let (mut client, mut session) = connect();
+    let handler = FakeHandler::default();
+    client.send(&Request::Status).unwrap();
+    assert_eq!(session.poll(&handler).unwrap(), SessionState::Open);
+    assert_eq!(messages(&mut client), [Message::Hello { version: PROTOCOL_VERSION }, Message::Status { status: handler.status() }]);
+  }
+
+  #[test]
+  fn requests_before_hello_are_refused() {
+    let (client, server) = loopback();
+    let mut client = Endpoint::<_, Request, Message>::new(client);
+    let mut session = ControlSession::new(server);
+    client.send(&Request::Status).unwrap();
+    session.poll(&FakeHandler::default()).unwrap();
+    assert_eq!(client.try_recv().unwrap(), Some(Message::Error { message: "send hello before any other request".to_owned() }));
+  }
+
+  #[test]
+  fn another_protocol_version_closes_the_session() {
+    let (client, server) = loopback();
+    let mut client = Endpoint::<_, Request, Message>::new(client);
+    let mut session = ControlSession::new(server);
+    client.send(&Request::Hello { version: PROTOCOL_VERSION + 1 }).unwrap();
+    assert_eq!(session.poll(&FakeHandler::default()).unwrap(), SessionState::Closed);
+    let message = format!("protocol version {} is not supported, the DLL speaks version {}", PROTOCOL_VERSION + 1, PROTOCOL_VERSION);
+    assert_eq!(client.try_recv().unwrap(), Some(Message::Error { message }));
+  }
+
+  #[test]
+  fn user_index_must_be_an_xinput_slot() {
+    let (mut client, mut session) = connect();
+    let handler = FakeHandler::default();
+    let rule = RuleId::new(RuleKind::Remap, 0);
+    client.send(&Request::SetRuleEnabled { rule, enabled: false, user_index: Some(XUSER_MAX_COUNT) }).unwrap();
+    client.send(&Request::TriggerMacro { index: 0, user_index: 7 }).unwrap();
+    session.poll(&handler).unwrap();
+    assert_eq!(messages(&mut client)[1..], [invalid_slot(XUSER_MAX_COUNT), invalid_slot(7)]);
+    assert!(handler.rule_changes.borrow().is_empty());
+  }
+
+  #[test]
+  fn set_rule_enabled_reaches_the_handler() {
+    let (mut client, mut session) = connect();
+    let handler = FakeHandler::default();
+    let rule = RuleId::new(RuleKind::Turbo, 1);
+    client.send(&Request::SetRuleEnabled { rule, enabled: false, user_index: Some(3) }).unwrap();
+    client.send(&Request::SetRuleEnabled { rule, enabled: true, user_index: None }).unwrap();
+    session.poll(&handler).unwrap();
+    assert_eq!(messages(&mut client)[1..], [Message::Done, Message::Done]);
+    assert_eq!(*handler.rule_changes.borrow(), [(rule, false, Some(3)), (rule, true, None)]);
+  }
+
+  #[test]
+  fn goodbye_closes_the_session() {
+    let (mut client, mut session) = connect();
+    client.send(&Request::Goodbye).unwrap();
+    client.send(&Request::Status).unwrap();
+    assert_eq!(session.poll(&FakeHandler::default()).unwrap(), SessionState::Closed);
+    // requests after the goodbye are left unanswered
+    assert_eq!(messages(&mut client), [Message::Hello { version: PROTOCOL_VERSION }, Message::Goodbye]);
+
+    drop(session);
+    assert_eq!(client.try_recv().unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+  }
+}
@@ -0,0 +1,141 @@
+// Transports for the injector-to-DLL control channel. A transport moves whole
+// frames (one JSON message each) and never blocks waiting for input, so both
+// sides can poll it from a loop that also does other work.
+//
+// * `LoopbackTransport` connects two endpoints inside one process (tests).
+// * `StreamTransport` frames any non-blocking byte stream with newlines. It is
+//   used for Unix sockets and for Windows named pipes (see named_pipe.rs).
+
+use std::{io::{self, Read, Write}, sync::mpsc, thread, time::Duration};
+
+pub trait Transport: Send {
+  /// Sends a single frame.
+  fn send(&mut self, frame: &str) -> io::Result<()>;
+
+  /// Returns the next frame if one has arrived. Returns an error of kind
+  /// `ConnectionAborted` once the other side has gone away.
+  fn try_recv(&mut self) -> io::Result<Option<String>>;
+}
+
+fn disconnected() -> io::Error {
+  io::Error::new(io::ErrorKind::ConnectionAborted, "the other end of the control channel disconnected")
+}
+
+pub struct LoopbackTransport {
+  sender: mpsc::Sender<String>,
+  receiver: mpsc::Receiver<String>,
+}
+
+/// Creates two connected in-process transports.
+pub fn loopback() -> (LoopbackTransport, LoopbackTransport) {
+  let (a_sender, b_receiver) = mpsc::channel();
+  let (b_sender, a_receiver) = mpsc::channel();
+  (
+    LoopbackTransport { sender: a_sender, receiver: a_receiver },
+    LoopbackTransport { sender: b_sender, receiver: b_receiver },
+  )
+}
+
+impl Transport for LoopbackTransport {
+  fn send(&mut self, frame: &str) -> io::Result<()> {
+    self.sender.send(frame.to_owned()).map_err(|_| disconnected())
+  }
+
+  fn try_recv(&mut self) -> io::Result<Option<String>> {
+    match self.receiver.try_recv() {
+      Ok(frame) => Ok(Some(frame)),
+      Err(mpsc::TryRecvError::Empty) => Ok(None),
+      Err(mpsc::TryRecvError::Disconnected) => Err(disconnected()),
+    }
+  }
+}
+
+/// Newline framing over a byte stream whose reads return `WouldBlock` when no
+/// data is available.
+pub struct StreamTransport<S> {
+  stream: S,
+  buffer: Vec<u8>,
+}
+
+impl<S: Read + Write + Send> StreamTransport<S> {
+  pub fn new(stream: S) -> Self {
+    Self { stream, buffer: vec![] }
+  }
+
+  // Removes the first complete line from the buffer
+  fn take_line(&mut self) -> io::Result<Option<String>> {
+    match self.buffer.iter().position(|byte| *byte == b'\n') {
+      Some(end) => {
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        String::from_utf8(line[..end].to_vec())
+          .map(Some)
+          .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+      },
+      None => Ok(None),
+    }
+  }
+}
+
+impl<S: Read + Write + Send> Transport for StreamTransport<S> {
+  fn send(&mut self, frame: &str) -> io::Result<()> {
+    let mut bytes = frame.as_bytes().to_vec();
+    bytes.push(b'\n');
+
+    // the stream is non-blocking, so keep retrying until the whole frame is out
+    let mut written = 0;
+    while written < bytes.len() {
+      match self.stream.write(&bytes[written..]) {
+        Ok(0) => return Err(disconnected()),
+        Ok(n) => written += n,
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+        Err(error) => return Err(error),
+      }
+    }
+    self.stream.flush()
+  }
+
+  fn try_recv(&mut self) -> io::Result<Option<String>> {
+    if let Some(line) = self.take_line()? {
+      return Ok(Some(line));
+    }
+
+    let mut chunk = [0u8; 4096];
+    loop {
+      match self.stream.read(&mut chunk) {
+        Ok(0) => return Err(disconnected()),
+        Ok(n) => {
+          self.buffer.extend_from_slice(&chunk[..n]);
+          if let Some(line) = self.take_line()? {
+            return Ok(Some(line));
+          }
+        },
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+        Err(error) => return Err(error),
+      }
+    }
+  }
+}
+
+/// Connects to a control channel listening on a Unix socket.
+#[cfg(unix)]
+pub fn connect_unix_socket(path: impl AsRef<std::path::Path>) -> io::Result<StreamTransport<std::os::unix::net::UnixStream>> {
+  let stream = std::os::unix::net::UnixStream::connect(path)?;
+  stream.set_nonblocking(true)?;
+  Ok(StreamTransport::new(stream))
+}
+
+/// Accepts the next client from a Unix socket listener, if one is waiting.
+/// The listener must have been put into non-blocking mode.
+#[cfg(unix)]
+pub fn accept_unix_socket(listener: &std::os::unix::net::UnixListener) -> io::Result<Option<StreamTransport<std::os::unix::net::UnixStream>>> {
+  match listener.accept() {
+    Ok((stream, _)) => {
+      stream.set_nonblocking(true)?;
+      Ok(Some(StreamTransport::new(stream)))
+    },
+    Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+    Err(error) => Err(error),
+  }
+}
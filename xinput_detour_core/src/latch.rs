@@ -0,0 +1,210 @@
+// Toggle and latch rules: a press of a momentary button holds a target button
+// or trigger down without the button having to be held, e.g. to keep boost or
+// throttle pressed. Without a duration the target is held until the next
+// press; with one it is released on its own once the time is up.
+
+use std::{convert::TryFrom, time::Duration};
+use serde::Deserialize;
+use super::button::Button;
+use super::mutable_xinput_state::{ButtonState, MutableXInputState};
+use super::profile::TriggerSide;
+
+/// What a latch holds down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatchTarget {
+  Button(Button),
+  Trigger { side: TriggerSide, value: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "LatchConfig")]
+pub struct Latch {
+  pub on: Button,
+  pub target: LatchTarget,
+  /// How long a press holds the target. `None` holds it until `on` is
+  /// pressed again.
+  pub duration: Option<Duration>,
+  /// Hide the `on` button from the game.
+  pub suppress: bool,
+}
+
+// The profile format, checked before it becomes a Latch
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LatchConfig {
+  on: Button,
+  press: Option<Button>,
+  trigger: Option<TriggerSide>,
+  #[serde(default = "default_trigger_value")]
+  value: u8,
+  duration_ms: Option<u64>,
+  #[serde(default)]
+  suppress: bool,
+}
+
+fn default_trigger_value() -> u8 {
+  u8::MAX
+}
+
+impl TryFrom<LatchConfig> for Latch {
+  type Error = String;
+
+  fn try_from(config: LatchConfig) -> Result<Self, Self::Error> {
+    let target = match (config.press, config.trigger) {
+      (Some(_), Some(_)) => return Err("a latch holds either a button (press) or a trigger, not both".to_owned()),
+      (Some(button), None) => LatchTarget::Button(button),
+      (None, Some(side)) => LatchTarget::Trigger { side, value: config.value },
+      (None, None) => LatchTarget::Button(config.on),
+    };
+    if config.duration_ms == Some(0) {
+      return Err("duration_ms must be above 0, leave it out to hold until the next press".to_owned());
+    }
+
+    Ok(Latch {
+      on: config.on,
+      target,
+      duration: config.duration_ms.map(Duration::from_millis),
+      suppress: config.suppress,
+    })
+  }
+}
+
+/// What a latch remembers between polls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatchState {
+  latched: bool,
+  // when a timed latch lets go
+  until: Option<Duration>,
+}
+
+impl LatchState {
+  pub fn is_latched(&self) -> bool {
+    self.latched
+  }
+}
+
+impl Latch {
+  /// A latch that toggles `on` itself.
+  pub fn toggle(on: Button) -> Self {
+    Self { on, target: LatchTarget::Button(on), duration: None, suppress: false }
+  }
+
+  /// Latches or lets go on each press of `on` and holds the target while
+  /// latched. `held` tells whether `on` is down, which the caller may read
+  /// before other rules changed it.
+  pub fn apply(&self, controller_state: &MutableXInputState, state: &mut LatchState, held: bool) {
+    let now = controller_state.now();
+
+    if controller_state.just_pressed(self.on) {
+      // a press while latched lets go early, even for a timed latch
+      state.latched = !state.latched;
+      state.until = self.duration.filter(|_| state.latched).map(|duration| now + duration);
+    }
+    if state.until.is_some_and(|until| now >= until) {
+      *state = LatchState::default();
+    }
+
+    if held && self.suppress {
+      controller_state.set(self.on, ButtonState::UP);
+    }
+
+    if state.latched {
+      match self.target {
+        LatchTarget::Button(button) => controller_state.set(button, ButtonState::DOWN),
+        LatchTarget::Trigger { side: TriggerSide::Left, value } => controller_state.set_left_trigger(value),
+        LatchTarget::Trigger { side: TriggerSide::Right, value } => controller_state.set_right_trigger(value),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::button::Buttons;
+  use crate::profile::{Profile, RuleId, RuleKind};
+  use crate::simulation::{Frame, Simulation};
+
+  fn simulation(latch: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("[[latch]]\n{}", latch)).unwrap())
+  }
+
+  fn at(ms: u64, buttons: Buttons) -> Frame {
+    Frame::buttons(Duration::from_millis(ms), buttons)
+  }
+
+  // Whether A reaches the game on each frame
+  fn a_pressed(output: Vec<Frame>) -> Vec<bool> {
+    output.iter().map(|frame| frame.pressed().contains(Button::A)).collect()
+  }
+
+  #[test]
+  fn toggle_holds_until_the_next_press() {
+    let mut simulation = simulation("on = \"LEFT_SHOULDER\"\npress = \"A\"\nsuppress = true");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(16, Buttons::empty()),
+      at(1000, Buttons::empty()),
+      at(1016, Button::LeftShoulder.into()),
+      at(1032, Buttons::empty()),
+    ]);
+    assert!(output.iter().all(|frame| !frame.pressed().contains(Button::LeftShoulder)));
+    assert_eq!(a_pressed(output), [true, true, true, false, false]);
+  }
+
+  #[test]
+  fn timed_latch_lets_go_on_its_own() {
+    let mut simulation = simulation("on = \"LEFT_SHOULDER\"\npress = \"A\"\nduration_ms = 100");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(16, Buttons::empty()),
+      at(99, Buttons::empty()),
+      at(100, Buttons::empty()),
+    ]);
+    assert_eq!(a_pressed(output), [true, true, true, false]);
+  }
+
+  #[test]
+  fn pressing_again_lets_a_timed_latch_go_early() {
+    let mut simulation = simulation("on = \"LEFT_SHOULDER\"\npress = \"A\"\nduration_ms = 1000");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(16, Buttons::empty()),
+      at(32, Button::LeftShoulder.into()),
+      at(48, Buttons::empty()),
+    ]);
+    assert_eq!(a_pressed(output), [true, true, false, false]);
+  }
+
+  #[test]
+  fn switching_another_rule_off_keeps_the_latch() {
+    let profile = "[[remap]]\nfrom = \"B\"\nto = \"X\"\n\n[[latch]]\non = \"LEFT_SHOULDER\"\npress = \"A\"";
+    let mut simulation = Simulation::new(Profile::from_toml_str(profile).unwrap());
+    simulation.run([at(0, Button::LeftShoulder.into()), at(16, Buttons::empty())]);
+
+    let mut toggled = simulation.profile().clone();
+    toggled.set_rule_enabled(RuleId::new(RuleKind::Remap, 0), false);
+    simulation.set_profile(toggled);
+    assert!(simulation.step(at(32, Buttons::empty())).pressed().contains(Button::A));
+
+    // switching the latch itself off lets go
+    let mut toggled = simulation.profile().clone();
+    toggled.set_rule_enabled(RuleId::new(RuleKind::Latch, 0), false);
+    simulation.set_profile(toggled);
+    assert!(!simulation.step(at(48, Buttons::empty())).pressed().contains(Button::A));
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<Latch>(toml).unwrap_err().message().to_owned()
+  }
+
+  #[test]
+  fn rejects_a_button_and_a_trigger() {
+    assert_eq!(error("on = \"A\"\npress = \"B\"\ntrigger = \"left\""), "a latch holds either a button (press) or a trigger, not both");
+  }
+
+  #[test]
+  fn rejects_a_zero_duration() {
+    assert_eq!(error("on = \"A\"\nduration_ms = 0"), "duration_ms must be above 0, leave it out to hold until the next press");
+  }
+}
@@ -0,0 +1,161 @@
+// Layers, like the layers of keyboard firmware: while a layer's `on` button is
+// held (or after it toggled the layer on) the layer's rules take over the
+// buttons they use. E.g. holding BACK can turn the face buttons into a second
+// set of macros.
+//
+// Precedence: layers sit on top of the base profile, and a later `[[layer]]`
+// sits on top of an earlier one. A button used as the input of a rule in an
+// active layer (remap `from`, latch, turbo and macro `on`, trigger `when`) is
+// taken over by that layer, and the rules on it in the layers below and in
+// the base profile are paused. Every other button falls through to the rules
+// below. Layer buttons are always hidden from the game and from every rule
+// except combos. handle_controller_state runs after all of this and sees the
+// result.
+
+use serde::Deserialize;
+use super::button::{Button, Buttons};
+use super::latch::{Latch, LatchState};
+use super::mutable_xinput_state::MutableXInputState;
+use super::profile::{ButtonRemap, Macro, TriggerOverride};
+use super::turbo::{Turbo, TurboState};
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+  #[serde(default)]
+  pub name: String,
+  pub on: Button,
+  /// Each press of `on` switches the layer on or off instead of it being
+  /// active only while `on` is held.
+  #[serde(default)]
+  pub toggle: bool,
+  #[serde(default, rename = "remap")]
+  pub remaps: Vec<ButtonRemap>,
+  #[serde(default, rename = "trigger")]
+  pub triggers: Vec<TriggerOverride>,
+  #[serde(default, rename = "latch")]
+  pub latches: Vec<Latch>,
+  #[serde(default, rename = "turbo")]
+  pub turbos: Vec<Turbo>,
+  #[serde(default, rename = "macro")]
+  pub macros: Vec<Macro>,
+}
+
+/// What a layer and its rules remember between polls.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerState {
+  active: bool,
+  toggled_on: bool,
+  pub latches: Vec<LatchState>,
+  pub turbos: Vec<TurboState>,
+}
+
+impl LayerState {
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+}
+
+impl Layer {
+  /// The buttons the layer takes over from the rules below it while active.
+  pub fn inputs(&self) -> Buttons {
+    let remaps = self.remaps.iter().map(|remap| remap.from);
+    let triggers = self.triggers.iter().filter_map(|trigger| trigger.when);
+    let latches = self.latches.iter().map(|latch| latch.on);
+    let turbos = self.turbos.iter().map(|turbo| turbo.on);
+    let macros = self.macros.iter().map(|macro_rule| macro_rule.on);
+    remaps.chain(triggers).chain(latches).chain(turbos).chain(macros).collect()
+  }
+
+  /// Works out whether the layer is active on this poll. `held` tells whether
+  /// `on` is down. The rules of an inactive layer forget their state, so a
+  /// latch in a layer lets go when the layer does.
+  pub fn update(&self, controller_state: &MutableXInputState, state: &mut LayerState, held: bool) {
+    state.active = if self.toggle {
+      if controller_state.just_pressed(self.on) {
+        state.toggled_on = !state.toggled_on;
+      }
+      state.toggled_on
+    } else {
+      held
+    };
+
+    if state.active {
+      state.latches.resize_with(self.latches.len(), LatchState::default);
+      state.turbos.resize_with(self.turbos.len(), TurboState::default);
+    } else {
+      state.latches.clear();
+      state.turbos.clear();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use super::*;
+  use crate::profile::Profile;
+  use crate::simulation::{Frame, Simulation};
+
+  const BASE: &str = "[[remap]]\nfrom = \"A\"\nto = \"X\"\n\n[[remap]]\nfrom = \"B\"\nto = \"RIGHT_SHOULDER\"\n\n";
+
+  fn simulation(layer: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("{}[[layer]]\non = \"BACK\"\n{}", BASE, layer)).unwrap())
+  }
+
+  fn at(ms: u64, buttons: &[Button]) -> Frame {
+    Frame::buttons(Duration::from_millis(ms), buttons.iter().copied().collect())
+  }
+
+  fn buttons(buttons: &[Button]) -> Buttons {
+    buttons.iter().copied().collect()
+  }
+
+  #[test]
+  fn held_layer_takes_over_its_buttons() {
+    let mut simulation = simulation("[[layer.remap]]\nfrom = \"A\"\nto = \"Y\"");
+    let output = simulation.run([
+      at(0, &[Button::A]),
+      at(16, &[Button::Back, Button::A]),
+      at(32, &[Button::Back, Button::B]),
+      at(48, &[Button::Back]),
+      at(64, &[Button::A]),
+    ]);
+    let pressed: Vec<Buttons> = output.iter().map(Frame::pressed).collect();
+    // B falls through to the base profile, BACK never reaches the game
+    assert_eq!(pressed, [
+      buttons(&[Button::X]),
+      buttons(&[Button::Y]),
+      buttons(&[Button::RightShoulder]),
+      Buttons::empty(),
+      buttons(&[Button::X]),
+    ]);
+  }
+
+  #[test]
+  fn toggle_layer_stays_on_until_the_next_press() {
+    let mut simulation = simulation("toggle = true\n\n[[layer.remap]]\nfrom = \"A\"\nto = \"Y\"");
+    let output = simulation.run([
+      at(0, &[Button::Back]),
+      at(16, &[]),
+      at(32, &[Button::A]),
+      at(48, &[Button::Back]),
+      at(64, &[Button::A]),
+    ]);
+    let pressed: Vec<Buttons> = output.iter().map(Frame::pressed).collect();
+    assert_eq!(pressed, [Buttons::empty(), Buttons::empty(), buttons(&[Button::Y]), Buttons::empty(), buttons(&[Button::X])]);
+  }
+
+  #[test]
+  fn layer_latches_let_go_with_the_layer() {
+    let mut simulation = simulation("[[layer.latch]]\non = \"A\"\npress = \"Y\"");
+    let output = simulation.run([
+      at(0, &[Button::Back, Button::A]),
+      at(16, &[Button::Back]),
+      at(32, &[]),
+      at(48, &[Button::Back]),
+    ]);
+    let latched: Vec<bool> = output.iter().map(|frame| frame.pressed().contains(Button::Y)).collect();
+    assert_eq!(latched, [true, true, false, false]);
+  }
+}
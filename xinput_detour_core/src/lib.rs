@@ -0,0 +1,35 @@
+// Platform-independent controller logic. Everything in here works on the plain
+// `XInputState` mirror so it can be built and tested without the Windows
+// headers; the detour DLL converts to and from `XINPUT_STATE` at the hook.
+
+#[macro_use]
+pub mod function_scheduler;
+
+pub mod button;
+pub mod button_history;
+pub mod clock;
+pub mod combo;
+pub mod controller_slot;
+pub mod device;
+pub mod filter;
+pub mod handle_controller_state;
+pub mod ipc;
+pub mod ipc_transport;
+pub mod latch;
+pub mod layer;
+pub mod mutable_xinput_state;
+#[cfg(windows)]
+pub mod named_pipe;
+pub mod pipeline;
+pub mod playback;
+pub mod profile;
+pub mod profile_reloader;
+pub mod recording;
+pub mod routing;
+pub mod rumble;
+pub mod simulation;
+pub mod stick;
+pub mod trigger;
+pub mod turbo;
+pub mod virtual_pad;
+pub mod xinput_state;
@@ -0,0 +1,566 @@
+// Code here is a modified and expanded form of rusty-xinput's XInputState
+// (https://github.com/Lokathor/rusty-xinput)
+
+use std::{cell::Cell, time::Duration};
+use super::button::{Button, Buttons};
+use super::button_history::ButtonHistory;
+use super::xinput_state::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonState {
+  UP,
+  DOWN,
+}
+
+pub struct MutableXInputState {
+    /// The plain state we're wrapping.
+    state: Cell<XInputState>,
+    /// How the buttons changed since the previous poll.
+    history: ButtonHistory,
+    /// The rumble asked for on this poll, on top of the game's.
+    rumble: Cell<Option<XInputVibration>>,
+    /// Presses held back from the rules on this poll, and held back presses
+    /// let through late.
+    hidden_presses: Cell<Buttons>,
+    late_presses: Cell<Buttons>,
+  }
+  
+  impl ::std::cmp::PartialEq for MutableXInputState {
+    /// Equality for `MutableXInputState` values is based _only_ on the
+    /// `packet_number` of the wrapped `XInputState` value. This is entirely
+    /// correct for values obtained from the xinput system, but if you make your
+    /// own `MutableXInputState` values for some reason you can confuse it.
+    fn eq(&self, other: &MutableXInputState) -> bool {
+      self.state.get().packet_number == other.state.get().packet_number
+    }
+  }
+  
+  impl ::std::cmp::Eq for MutableXInputState {}
+  
+  impl ::std::fmt::Debug for MutableXInputState {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+      write!(f, "MutableXInputState (_)")
+    }
+  }
+  
+  impl MutableXInputState {
+    /// Wraps a state without any history, so the edge queries such as
+    /// `just_pressed` always return false.
+    pub fn new(state: XInputState) -> Self {
+      Self::with_history(state, ButtonHistory::default())
+    }
+
+    /// Wraps a state together with the controller's button history, which
+    /// must already include this poll.
+    pub fn with_history(state: XInputState, history: ButtonHistory) -> Self {
+      Self {
+        state: Cell::new(state),
+        history,
+        rumble: Cell::new(None),
+        hidden_presses: Cell::new(Buttons::empty()),
+        late_presses: Cell::new(Buttons::empty()),
+      }
+    }
+
+    /// A copy of the wrapped state, including any modifications made so far.
+    #[inline]
+    pub fn state(&self) -> XInputState {
+      self.state.get()
+    }
+
+    /// Consumes the wrapper and returns the (possibly modified) state.
+    #[inline]
+    pub fn into_state(self) -> XInputState {
+      self.state.into_inner()
+    }
+
+    /// Runs the motors at least this fast on this poll, whatever the game asks
+    /// for. Scheduled functions call this on every poll of a step, so the
+    /// rumble lasts as long as the step. Several calls keep the fastest speed
+    /// of each motor.
+    pub fn set_rumble(&self, vibration: XInputVibration) {
+      let rumble = match self.rumble.get() {
+        Some(current) => XInputVibration {
+          left_motor_speed: current.left_motor_speed.max(vibration.left_motor_speed),
+          right_motor_speed: current.right_motor_speed.max(vibration.right_motor_speed),
+        },
+        None => vibration,
+      };
+      self.rumble.set(Some(rumble));
+    }
+
+    /// The rumble asked for on this poll with `set_rumble`, if any.
+    pub fn rumble(&self) -> Option<XInputVibration> {
+      self.rumble.get()
+    }
+
+    #[inline]
+    fn gamepad(&self) -> XInputGamepad {
+      self.state.get().gamepad
+    }
+
+    // Applies a modification to the wrapped gamepad
+    #[inline]
+    fn update_gamepad(&self, f: impl FnOnce(&mut XInputGamepad)) {
+      let mut state = self.state.get();
+      f(&mut state.gamepad);
+      self.state.set(state);
+    }
+
+    // Sets the given wButton on the controller up or down
+    fn set_button_bit(&self, bit_mask: u16, state: ButtonState) {
+      match state {
+        ButtonState::UP => self.update_gamepad(|gamepad| gamepad.buttons &= !bit_mask),
+        ButtonState::DOWN => self.update_gamepad(|gamepad| gamepad.buttons |= bit_mask),
+      }
+    }
+
+    /// All of the buttons as the raw `XINPUT_GAMEPAD_*` bit field.
+    #[inline]
+    pub fn buttons_raw(&self) -> u16 {
+      self.gamepad().buttons
+    }
+
+    /// Sets every button in `bit_mask` up or down, leaving the others alone.
+    #[inline]
+    pub fn set_buttons_raw(&self, bit_mask: u16, button_state: ButtonState) {
+      self.set_button_bit(bit_mask, button_state);
+    }
+
+    /// The buttons that are down.
+    #[inline]
+    pub fn buttons(&self) -> Buttons {
+      Buttons::from_bits(self.buttons_raw())
+    }
+
+    /// Replaces every button at once.
+    #[inline]
+    pub fn set_buttons(&self, buttons: Buttons) {
+      self.update_gamepad(|gamepad| gamepad.buttons = buttons.bits());
+    }
+
+    #[inline]
+    pub fn is_down(&self, button: Button) -> bool {
+      self.buttons().contains(button)
+    }
+
+    #[inline]
+    pub fn set(&self, button: Button, button_state: ButtonState) {
+      self.set_button_bit(button.mask(), button_state);
+    }
+
+    /// The buttons that are down, in bit order.
+    #[inline]
+    pub fn iter_pressed(&self) -> impl Iterator<Item = Button> {
+      self.buttons().iter()
+    }
+
+    /// The button history of this controller.
+    #[inline]
+    pub fn history(&self) -> &ButtonHistory {
+      &self.history
+    }
+
+    /// The time of this poll as read from the slot's clock, or zero for a
+    /// state without history.
+    #[inline]
+    pub fn now(&self) -> Duration {
+      self.history.now().unwrap_or_default()
+    }
+
+    /// The button went down on this poll.
+    ///
+    /// Like all of the edge queries this looks at the buttons as the
+    /// controller reported them, before any rule changed them. The one
+    /// exception are presses a combo holds back, see `hide_presses`.
+    #[inline]
+    pub fn just_pressed(&self, button: Button) -> bool {
+      let pressed = self.history.just_pressed(button.mask()) && !self.hidden_presses.get().contains(button);
+      pressed || self.late_presses.get().contains(button)
+    }
+
+    /// Hides the presses of these buttons on this poll from `just_pressed`,
+    /// for a rule that holds the buttons back from the game and the rules
+    /// after it.
+    pub fn hide_presses(&self, buttons: Buttons) {
+      self.hidden_presses.set(self.hidden_presses.get() | buttons);
+      self.late_presses.set(self.late_presses.get() - buttons);
+    }
+
+    /// Makes a button that was held back count as pressed on this poll, the
+    /// poll the game first sees it.
+    pub fn press_late(&self, button: Button) {
+      self.late_presses.set(self.late_presses.get() | button);
+    }
+
+    /// The button went up on this poll.
+    #[inline]
+    pub fn just_released(&self, button: Button) -> bool {
+      self.history.just_released(button.mask())
+    }
+
+    /// The button is down and has been for at least `duration`.
+    #[inline]
+    pub fn held_for(&self, button: Button, duration: Duration) -> bool {
+      self.history.held_for(button.mask(), duration)
+    }
+
+    /// The button went up on this poll after being held for at least `duration`.
+    #[inline]
+    pub fn released_after(&self, button: Button, duration: Duration) -> bool {
+      self.history.released_after(button.mask(), duration)
+    }
+
+    /// The north button of the action button group.
+    ///
+    /// * Nintendo: X
+    /// * Playstation: Triangle
+    /// * XBox: Y
+    #[inline]
+    pub fn north_button(&self) -> bool {
+      self.is_down(Button::Y)
+    }
+
+    #[inline]
+    pub fn set_north_button(&self, button_state: ButtonState) {
+      self.set(Button::Y, button_state);
+    }
+  
+    /// The south button of the action button group.
+    ///
+    /// * Nintendo: B
+    /// * Playstation: X
+    /// * XBox: A
+    #[inline]
+    pub fn south_button(&self) -> bool {
+      self.is_down(Button::A)
+    }
+
+    #[inline]
+    pub fn set_south_button(&self, button_state: ButtonState) {
+      self.set(Button::A, button_state);
+    }
+  
+    /// The east button of the action button group.
+    ///
+    /// * Nintendo: A
+    /// * Playstation: Circle
+    /// * XBox: B
+    #[inline]
+    pub fn east_button(&self) -> bool {
+      self.is_down(Button::B)
+    }
+
+    #[inline]
+    pub fn set_east_button(&self, button_state: ButtonState) {
+      self.set(Button::B, button_state);
+    }
+  
+    /// The west button of the action button group.
+    ///
+    /// * Nintendo: Y
+    /// * Playstation: Square
+    /// * XBox: X
+    #[inline]
+    pub fn west_button(&self) -> bool {
+      self.is_down(Button::X)
+    }
+
+    #[inline]
+    pub fn set_west_button(&self, button_state: ButtonState) {
+      self.set(Button::X, button_state);
+    }
+  
+    /// The up button on the directional pad.
+    #[inline]
+    pub fn arrow_up(&self) -> bool {
+      self.is_down(Button::DpadUp)
+    }
+
+    #[inline]
+    pub fn set_arrow_up(&self, button_state: ButtonState) {
+      self.set(Button::DpadUp, button_state);
+    }
+  
+    /// The down button on the directional pad.
+    #[inline]
+    pub fn arrow_down(&self) -> bool {
+      self.is_down(Button::DpadDown)
+    }
+
+    #[inline]
+    pub fn set_arrow_down(&self, button_state: ButtonState) {
+      self.set(Button::DpadDown, button_state);
+    }
+  
+    /// The left button on the directional pad.
+    #[inline]
+    pub fn arrow_left(&self) -> bool {
+      self.is_down(Button::DpadLeft)
+    }
+
+    #[inline]
+    pub fn set_arrow_left(&self, button_state: ButtonState) {
+      self.set(Button::DpadLeft, button_state);
+    }
+  
+    /// The right button on the directional pad.
+    #[inline]
+    pub fn arrow_right(&self) -> bool {
+      self.is_down(Button::DpadRight)
+    }
+
+    #[inline]
+    pub fn set_arrow_right(&self, button_state: ButtonState) {
+      self.set(Button::DpadRight, button_state);
+    }
+  
+    /// The "start" button.
+    ///
+    /// * Nintendo: Start (NES / SNES), '+' (Pro Controller)
+    /// * Playstation: Start
+    /// * XBox: Start
+    #[inline]
+    pub fn start_button(&self) -> bool {
+      self.is_down(Button::Start)
+    }
+
+    #[inline]
+    pub fn set_start_button(&self, button_state: ButtonState) {
+      self.set(Button::Start, button_state);
+    }
+  
+    /// The "not start" button.
+    ///
+    /// * Nintendo: Select (NES / NES), '-' (Pro Controller)
+    /// * Playstation: Select
+    /// * XBox: Back
+    #[inline]
+    pub fn select_button(&self) -> bool {
+      self.is_down(Button::Back)
+    }
+
+    #[inline]
+    pub fn set_select_button(&self, button_state: ButtonState) {
+      self.set(Button::Back, button_state);
+    }
+  
+    /// The button in the middle of the pad.
+    ///
+    /// * Nintendo: Home
+    /// * Playstation: PS
+    /// * XBox: Guide (only seen by games that call XInputGetStateEx)
+    #[inline]
+    pub fn guide_button(&self) -> bool {
+      self.is_down(Button::Guide)
+    }
+
+    #[inline]
+    pub fn set_guide_button(&self, button_state: ButtonState) {
+      self.set(Button::Guide, button_state);
+    }
+  
+    /// The upper left shoulder button.
+    ///
+    /// * Nintendo: L
+    /// * Playstation: L1
+    /// * XBox: LB
+    #[inline]
+    pub fn left_shoulder(&self) -> bool {
+      self.is_down(Button::LeftShoulder)
+    }
+
+    #[inline]
+    pub fn set_left_shoulder(&self, button_state: ButtonState) {
+      self.set(Button::LeftShoulder, button_state);
+    }
+  
+    /// The upper right shoulder button.
+    ///
+    /// * Nintendo: R
+    /// * Playstation: R1
+    /// * XBox: RB
+    #[inline]
+    pub fn right_shoulder(&self) -> bool {
+      self.is_down(Button::RightShoulder)
+    }
+
+    #[inline]
+    pub fn set_right_shoulder(&self, button_state: ButtonState) {
+      self.set(Button::RightShoulder, button_state);
+    }
+  
+    /// The default threshold to count a trigger as being "pressed".
+    pub const TRIGGER_THRESHOLD: u8 = XINPUT_GAMEPAD_TRIGGER_THRESHOLD;
+  
+    /// The lower left shoulder trigger. If you want to use this as a simple
+    /// boolean it is suggested that you compare it to the `TRIGGER_THRESHOLD`
+    /// constant.
+    ///
+    /// * Nintendo: ZL
+    /// * Playstation: L2
+    /// * XBox: LT
+    #[inline]
+    pub fn left_trigger(&self) -> u8 {
+      self.gamepad().left_trigger
+    }
+
+    #[inline]
+    pub fn set_left_trigger(&self, value: u8) {
+      self.update_gamepad(|gamepad| gamepad.left_trigger = value);
+    }
+  
+    /// The lower right shoulder trigger. If you want to use this as a simple
+    /// boolean it is suggested that you compare it to the `TRIGGER_THRESHOLD`
+    /// constant.
+    ///
+    /// * Nintendo: ZR
+    /// * Playstation: R2
+    /// * XBox: RT
+    #[inline]
+    pub fn right_trigger(&self) -> u8 {
+      self.gamepad().right_trigger
+    }
+
+    #[inline]
+    pub fn set_right_trigger(&self, value: u8) {
+      self.update_gamepad(|gamepad| gamepad.right_trigger = value);
+    }
+  
+    /// The lower left shoulder trigger as a bool using the default threshold.
+    ///
+    /// * Nintendo: ZL
+    /// * Playstation: L2
+    /// * XBox: LT
+    #[inline]
+    pub fn left_trigger_bool(&self) -> bool {
+      self.left_trigger() >= MutableXInputState::TRIGGER_THRESHOLD
+    }
+  
+    /// The lower right shoulder trigger as a bool using the default threshold.
+    ///
+    /// * Nintendo: ZR
+    /// * Playstation: R2
+    /// * XBox: RT
+    #[inline]
+    pub fn right_trigger_bool(&self) -> bool {
+      self.right_trigger() >= MutableXInputState::TRIGGER_THRESHOLD
+    }
+  
+    /// The left thumb stick being pressed inward.
+    ///
+    /// * Nintendo: (L)
+    /// * Playstation: L3
+    /// * XBox: (L)
+    #[inline]
+    pub fn left_thumb_button(&self) -> bool {
+      self.is_down(Button::LeftThumb)
+    }
+
+    #[inline]
+    pub fn set_left_thumb_button(&self, button_state: ButtonState) {
+      self.set(Button::LeftThumb, button_state);
+    }
+  
+    /// The right thumb stick being pressed inward.
+    ///
+    /// * Nintendo: (R)
+    /// * Playstation: R3
+    /// * XBox: (R)
+    #[inline]
+    pub fn right_thumb_button(&self) -> bool {
+      self.is_down(Button::RightThumb)
+    }
+
+    #[inline]
+    pub fn set_right_thumb_button(&self, button_state: ButtonState) {
+      self.set(Button::RightThumb, button_state);
+    }
+  
+    /// The suggested default deadzone for use with the left thumb stick.
+    pub const LEFT_STICK_DEADZONE: i16 = XINPUT_GAMEPAD_LEFT_THUMB_DEADZONE;
+  
+    /// The suggested default deadzone for use with the right thumb stick.
+    pub const RIGHT_STICK_DEADZONE: i16 = XINPUT_GAMEPAD_RIGHT_THUMB_DEADZONE;
+  
+    /// The left stick raw value.
+    ///
+    /// Positive values are to the right (X-axis) or up (Y-axis).
+    #[inline]
+    pub fn left_stick_raw(&self) -> (i16, i16) {
+      let gamepad = self.gamepad();
+      (gamepad.thumb_lx, gamepad.thumb_ly)
+    }
+
+    #[inline]
+    pub fn set_left_stick_raw(&self, values: (i16, i16)) {
+      self.update_gamepad(|gamepad| {
+        gamepad.thumb_lx = values.0;
+        gamepad.thumb_ly = values.1;
+      });
+    }
+  
+    /// The right stick raw value.
+    ///
+    /// Positive values are to the right (X-axis) or up (Y-axis).
+    #[inline]
+    pub fn right_stick_raw(&self) -> (i16, i16) {
+      let gamepad = self.gamepad();
+      (gamepad.thumb_rx, gamepad.thumb_ry)
+    }
+
+    #[inline]
+    pub fn set_right_stick_raw(&self, values: (i16, i16)) {
+      self.update_gamepad(|gamepad| {
+        gamepad.thumb_rx = values.0;
+        gamepad.thumb_ry = values.1;
+      });
+    }
+  
+    /// The left stick value normalized with the default dead-zone.
+    ///
+    /// See `normalize_raw_stick_value` for more.
+    #[inline]
+    pub fn left_stick_normalized(&self) -> (f32, f32) {
+      MutableXInputState::normalize_raw_stick_value(self.left_stick_raw(), MutableXInputState::LEFT_STICK_DEADZONE)
+    }
+  
+    /// The right stick value normalized with the default dead-zone.
+    ///
+    /// See `normalize_raw_stick_value` for more.
+    #[inline]
+    pub fn right_stick_normalized(&self) -> (f32, f32) {
+      MutableXInputState::normalize_raw_stick_value(
+        self.right_stick_raw(),
+        MutableXInputState::RIGHT_STICK_DEADZONE,
+      )
+    }
+  
+    /// This helper normalizes a raw stick value using the given deadzone.
+    ///
+    /// If the raw value's 2d length is less than the deadzone the result will be
+    /// `(0.0,0.0)`, otherwise the result is normalized across the range from the
+    /// deadzone point to the maximum value.
+    ///
+    /// The `deadzone` value is clamped to the range 0 to 32,766 (inclusive)
+    /// before use. Negative inputs or maximum value inputs make the normalization
+    /// just work improperly.
+    ///
+    /// This only reads the stick. To change what the game sees use
+    /// `stick::StickShaping`.
+    #[inline]
+    pub fn normalize_raw_stick_value(raw_stick: (i16, i16), deadzone: i16) -> (f32, f32) {
+      let deadzone_float = deadzone.clamp(0, i16::MAX - 1) as f32;
+      let raw_float = (raw_stick.0 as f32, raw_stick.1 as f32);
+      let length = (raw_float.0 * raw_float.0 + raw_float.1 * raw_float.1).sqrt();
+      let normalized = (raw_float.0 / length, raw_float.1 / length);
+      if length > deadzone_float {
+        // clip our value to the expected maximum length.
+        let length = length.min(32_767.0);
+        let scale = (length - deadzone_float) / (32_767.0 - deadzone_float);
+        (normalized.0 * scale, normalized.1 * scale)
+      } else {
+        (0.0, 0.0)
+      }
+    }
+  }
\ No newline at end of file
@@ -0,0 +1,155 @@
+// A minimal non-blocking Windows named pipe for the control channel. Both ends
+// use PIPE_NOWAIT so reads return `WouldBlock` instead of waiting for data,
+// which is what `StreamTransport` expects.
+
+use std::{io::{self, Read, Write}, iter, ptr};
+use winapi::shared::minwindef::{DWORD, LPCVOID, LPVOID};
+use winapi::shared::winerror::*;
+use winapi::um::fileapi::{CreateFileW, ReadFile, WriteFile, OPEN_EXISTING};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, SetNamedPipeHandleState};
+use winapi::um::winbase::{PIPE_ACCESS_DUPLEX, PIPE_NOWAIT, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE};
+use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE, HANDLE};
+
+const BUFFER_SIZE: DWORD = 64 * 1024;
+
+pub struct NamedPipe {
+  handle: HANDLE,
+}
+
+// the handle is only ever used by whoever owns the NamedPipe
+unsafe impl Send for NamedPipe {}
+
+fn to_wide(text: &str) -> Vec<u16> {
+  text.encode_utf16().chain(iter::once(0)).collect()
+}
+
+fn last_error_code(error: &io::Error) -> Option<DWORD> {
+  error.raw_os_error().map(|code| code as DWORD)
+}
+
+impl NamedPipe {
+  /// Creates the server end of a pipe, e.g. `\\.\pipe\name`. Call
+  /// `try_accept` until a client connects.
+  pub fn create(name: &str) -> io::Result<Self> {
+    let name = to_wide(name);
+    let handle = unsafe {
+      CreateNamedPipeW(
+        name.as_ptr(),
+        PIPE_ACCESS_DUPLEX,
+        PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT | PIPE_REJECT_REMOTE_CLIENTS,
+        1,
+        BUFFER_SIZE,
+        BUFFER_SIZE,
+        0,
+        ptr::null_mut(),
+      )
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+      Err(io::Error::last_os_error())
+    } else {
+      Ok(Self { handle })
+    }
+  }
+
+  /// Returns true once a client is connected to a pipe from `create`.
+  pub fn try_accept(&self) -> io::Result<bool> {
+    if unsafe { ConnectNamedPipe(self.handle, ptr::null_mut()) } != 0 {
+      return Ok(true);
+    }
+
+    let error = io::Error::last_os_error();
+    match last_error_code(&error) {
+      Some(ERROR_PIPE_CONNECTED) => Ok(true),
+      Some(ERROR_PIPE_LISTENING) => Ok(false),
+      // a client connected and closed its end again before we noticed
+      Some(ERROR_NO_DATA) => {
+        unsafe { DisconnectNamedPipe(self.handle) };
+        Ok(false)
+      },
+      _ => Err(error),
+    }
+  }
+
+  /// Opens the client end of a pipe created with `create`.
+  pub fn connect(name: &str) -> io::Result<Self> {
+    let name = to_wide(name);
+    let handle = unsafe {
+      CreateFileW(
+        name.as_ptr(),
+        GENERIC_READ | GENERIC_WRITE,
+        0,
+        ptr::null_mut(),
+        OPEN_EXISTING,
+        0,
+        ptr::null_mut(),
+      )
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+      return Err(io::Error::last_os_error());
+    }
+
+    // wrap the handle first so it is closed if switching modes fails
+    let pipe = Self { handle };
+    let mut mode = PIPE_READMODE_BYTE | PIPE_NOWAIT;
+    if unsafe { SetNamedPipeHandleState(pipe.handle, &mut mode, ptr::null_mut(), ptr::null_mut()) } == 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(pipe)
+  }
+}
+
+impl Read for NamedPipe {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read: DWORD = 0;
+    let len = buf.len().min(BUFFER_SIZE as usize) as DWORD;
+    if unsafe { ReadFile(self.handle, buf.as_mut_ptr() as LPVOID, len, &mut read, ptr::null_mut()) } != 0 {
+      return match read {
+        0 => Err(io::ErrorKind::WouldBlock.into()),
+        n => Ok(n as usize),
+      };
+    }
+
+    let error = io::Error::last_os_error();
+    match last_error_code(&error) {
+      Some(ERROR_NO_DATA) | Some(ERROR_PIPE_LISTENING) => Err(io::ErrorKind::WouldBlock.into()),
+      Some(ERROR_BROKEN_PIPE) | Some(ERROR_PIPE_NOT_CONNECTED) => Ok(0),
+      _ => Err(error),
+    }
+  }
+}
+
+impl Write for NamedPipe {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut written: DWORD = 0;
+    let len = buf.len().min(BUFFER_SIZE as usize) as DWORD;
+    if unsafe { WriteFile(self.handle, buf.as_ptr() as LPCVOID, len, &mut written, ptr::null_mut()) } != 0 {
+      // a PIPE_NOWAIT write succeeds without writing anything when the buffer is full
+      return match written {
+        0 => Err(io::ErrorKind::WouldBlock.into()),
+        n => Ok(n as usize),
+      };
+    }
+
+    let error = io::Error::last_os_error();
+    match last_error_code(&error) {
+      Some(ERROR_NO_DATA) | Some(ERROR_BROKEN_PIPE) | Some(ERROR_PIPE_NOT_CONNECTED) => Ok(0),
+      _ => Err(error),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Drop for NamedPipe {
+  fn drop(&mut self) {
+    unsafe {
+      CloseHandle(self.handle);
+    }
+  }
+}
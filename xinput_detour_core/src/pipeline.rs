@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use super::controller_slot::ControllerSlot;
+use super::mutable_xinput_state::MutableXInputState;
+use super::function_scheduler::*;
+use super::handle_controller_state::*;
+use super::profile::Profile;
+use super::xinput_state::XInputState;
+
+// Runs the profile rules, the user-defined rules and the scheduled functions
+// against a single controller state and returns the state to hand to the game.
+// This is everything the detour does after the original XInputGetState has
+// filled in the state. The profile and the slot belong to the controller slot
+// `user_index`, and the slot's clock sees this call as one poll.
+pub fn process_controller_state(user_index: u32, state: XInputState, profile: &Arc<Profile>, slot: &mut ControllerSlot) -> XInputState {
+  slot.clock.on_poll();
+  slot.use_profile(profile);
+
+  // Record the buttons as the controller reported them, before any rule runs
+  slot.history.update(state.gamepad.buttons, slot.clock.now());
+  let controller_state = MutableXInputState::with_history(state, slot.history);
+  let scheduled_functions = &mut slot.scheduled_functions;
+
+  // Apply the rules from the loaded profile
+  profile.apply(&controller_state, scheduled_functions, &mut slot.rule_state);
+
+  // Call handle_controller_state to modify the controller state
+  handle_controller_state(user_index, &controller_state, scheduled_functions);
+
+  // If there are any scheduled functions, poll them and clean up the finished ones
+  if !scheduled_functions.is_empty() {
+    let mut completed: Vec<usize> = vec![];
+
+    // Poll each of the scheduled functions
+    for (i, scheduled_function) in scheduled_functions.iter_mut().enumerate() {
+      let function_state = scheduled_function.poll(&controller_state, slot.clock.as_ref());
+      if function_state == ScheduledFunctionState::Completed {
+        completed.push(i);
+      }
+    }
+
+    // Clean up all of the finished functions
+    completed.reverse();
+    for index in completed {
+      scheduled_functions.remove(index);
+    }
+  }
+
+  // Rumble cues only last for the poll they were asked for on
+  slot.rumble.set_cue(controller_state.rumble());
+
+  controller_state.into_state()
+}
@@ -0,0 +1,469 @@
+// Plays a recording (see recording.rs) back into the hook. Every recorded slot
+// is played on the live slot with the same user_index.
+//
+// The hook calls `before_rules` with the live state and `after_rules` with the
+// state the rules produced. Depending on `PlaybackSource` the recorded input is
+// fed through the current rules, which reproduces a session with today's
+// mappings, or the recorded output is handed straight to the game, which
+// reproduces exactly what the game saw.
+
+use std::{collections::BTreeMap, io, path::Path, time::Duration};
+use serde::{Deserialize, Serialize};
+use super::clock::Clock;
+use super::recording::{RecordedFrame, RecordingReader};
+use super::xinput_state::*;
+
+/// How recorded frames are combined with the physical pad.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackBlend {
+  /// Ignore the pad and use the recorded state.
+  #[default]
+  Replace,
+  /// Press the recorded buttons on top of the pad, and use whichever trigger
+  /// or stick is pushed further.
+  Overlay,
+}
+
+/// Which recorded frame is played on a poll.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackAlign {
+  /// The frame recorded at the same time since the start.
+  #[default]
+  Timestamp,
+  /// The next frame of the slot on every poll, whatever the timing.
+  PollCount,
+}
+
+/// Which half of the recorded frames is played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackSource {
+  /// The recorded pad, run through the current rules.
+  #[default]
+  Input,
+  /// The recorded result, handed to the game without running the rules on it.
+  Output,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackOptions {
+  pub blend: PlaybackBlend,
+  pub align: PlaybackAlign,
+  pub source: PlaybackSource,
+  /// Start over at the end instead of finishing.
+  pub looping: bool,
+  /// Stop as soon as the physical pad is used on a slot being played.
+  pub release_on_input: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+  Playing,
+  Paused,
+  /// Reached the end of the recording.
+  Finished,
+  /// Handed control back to the physical pad.
+  Released,
+}
+
+// The frames of one recorded slot and where playback is in them
+struct Track {
+  frames: Vec<RecordedFrame>,
+  // the next frame to play when aligned by poll count
+  next: usize,
+  // every frame was played when aligned by poll count
+  done: bool,
+  // the frame picked for the current poll
+  current: Option<RecordedFrame>,
+  // the last state handed to the game, to tell when the packet number must change
+  last_output: Option<XInputGamepad>,
+  packet_offset: u32,
+}
+
+pub struct Playback {
+  tracks: BTreeMap<u32, Track>,
+  options: PlaybackOptions,
+  clock: Box<dyn Clock>,
+  state: PlaybackState,
+  position: Duration,
+  duration: Duration,
+  last_now: Option<Duration>,
+}
+
+impl Playback {
+  /// Plays `frames`, which must be in the order they were recorded.
+  pub fn new(frames: impl IntoIterator<Item = RecordedFrame>, options: PlaybackOptions, clock: Box<dyn Clock>) -> Self {
+    let mut tracks: BTreeMap<u32, Track> = BTreeMap::new();
+    for frame in frames {
+      tracks
+        .entry(frame.user_index)
+        .or_insert_with(|| Track { frames: vec![], next: 0, done: false, current: None, last_output: None, packet_offset: 0 })
+        .frames
+        .push(frame);
+    }
+
+    let duration = tracks.values().filter_map(|track| track.frames.last()).map(|frame| frame.at).max().unwrap_or_default();
+    let state = if tracks.is_empty() { PlaybackState::Finished } else { PlaybackState::Playing };
+    Self { tracks, options, clock, state, position: Duration::ZERO, duration, last_now: None }
+  }
+
+  /// Loads a binary recording.
+  pub fn open(path: impl AsRef<Path>, options: PlaybackOptions, clock: Box<dyn Clock>) -> io::Result<Self> {
+    let frames = RecordingReader::open(path)?.collect::<io::Result<Vec<_>>>()?;
+    Ok(Self::new(frames, options, clock))
+  }
+
+  pub fn options(&self) -> PlaybackOptions {
+    self.options
+  }
+
+  pub fn state(&self) -> PlaybackState {
+    self.state
+  }
+
+  /// True once playback has finished or released the pad.
+  pub fn is_over(&self) -> bool {
+    matches!(self.state, PlaybackState::Finished | PlaybackState::Released)
+  }
+
+  /// The recording time being played.
+  pub fn position(&self) -> Duration {
+    self.position
+  }
+
+  /// The time of the last recorded frame.
+  pub fn duration(&self) -> Duration {
+    self.duration
+  }
+
+  /// The slots the recording has frames for.
+  pub fn user_indexes(&self) -> impl Iterator<Item = u32> + '_ {
+    self.tracks.keys().copied()
+  }
+
+  pub fn set_paused(&mut self, paused: bool) {
+    self.state = match (self.state, paused) {
+      (PlaybackState::Playing, true) => PlaybackState::Paused,
+      (PlaybackState::Paused, false) => PlaybackState::Playing,
+      (state, _) => state,
+    };
+  }
+
+  /// Jumps to a recording time. Seeking a finished playback starts it again.
+  pub fn seek(&mut self, position: Duration) {
+    self.position = position.min(self.duration);
+    for track in self.tracks.values_mut() {
+      track.next = track.frames.partition_point(|frame| frame.at < self.position);
+      track.done = false;
+    }
+    if self.state == PlaybackState::Finished {
+      self.state = PlaybackState::Playing;
+    }
+  }
+
+  /// Picks the frame for this poll and returns the state to run the rules on.
+  pub fn before_rules(&mut self, user_index: u32, live: XInputState) -> XInputState {
+    // only the polls of recorded slots move the clock forward, so the time
+    // between them is never lost to a poll of another slot
+    if !self.tracks.contains_key(&user_index) {
+      return live;
+    }
+    let now = self.clock.now();
+    let elapsed = self.last_now.map(|last_now| now.saturating_sub(last_now)).unwrap_or_default();
+    self.last_now = Some(now);
+
+    if self.is_over() {
+      return live;
+    }
+
+    if self.options.release_on_input && has_real_input(&live.gamepad) {
+      self.state = PlaybackState::Released;
+      return live;
+    }
+
+    if self.state == PlaybackState::Playing {
+      match self.options.align {
+        PlaybackAlign::Timestamp => self.advance_by_time(elapsed),
+        PlaybackAlign::PollCount => self.advance_by_poll(user_index),
+      }
+    }
+
+    let current = match self.state {
+      PlaybackState::Playing | PlaybackState::Paused => self.pick_frame(user_index),
+      _ => None,
+    };
+    let track = self.tracks.get_mut(&user_index).unwrap();
+    track.current = current;
+
+    match (current, self.options.source) {
+      (Some(frame), PlaybackSource::Input) => blend(self.options.blend, live, frame.input),
+      _ => live,
+    }
+  }
+
+  /// Returns the state to hand to the game.
+  pub fn after_rules(&mut self, user_index: u32, state: XInputState) -> XInputState {
+    let options = self.options;
+    let over = self.is_over();
+    let Some(track) = self.tracks.get_mut(&user_index) else {
+      return state;
+    };
+    let Some(frame) = track.current.filter(|_| !over) else {
+      return state;
+    };
+
+    let mut state = match options.source {
+      PlaybackSource::Input => state,
+      PlaybackSource::Output => blend(options.blend, state, frame.output),
+    };
+
+    // games skip states whose packet number did not change, so bump it
+    // whenever playback changes what the game sees
+    if track.last_output != Some(state.gamepad) {
+      track.packet_offset = track.packet_offset.wrapping_add(1);
+      track.last_output = Some(state.gamepad);
+    }
+    state.packet_number = state.packet_number.wrapping_add(track.packet_offset);
+    state
+  }
+
+  fn advance_by_time(&mut self, elapsed: Duration) {
+    self.position += elapsed;
+    if self.position <= self.duration {
+      return;
+    }
+
+    if !self.options.looping {
+      self.state = PlaybackState::Finished;
+    } else if self.duration.is_zero() {
+      self.position = Duration::ZERO;
+    } else {
+      let nanos = self.position.as_nanos() % self.duration.as_nanos();
+      self.position = Duration::from_nanos(nanos as u64);
+    }
+  }
+
+  fn advance_by_poll(&mut self, user_index: u32) {
+    let looping = self.options.looping;
+    let track = self.tracks.get_mut(&user_index).unwrap();
+    if track.next >= track.frames.len() {
+      if !looping {
+        track.done = true;
+        if self.tracks.values().all(|track| track.done) {
+          self.state = PlaybackState::Finished;
+        }
+        return;
+      }
+      track.next = 0;
+    }
+
+    self.position = track.frames[track.next].at;
+    track.next += 1;
+  }
+
+  fn pick_frame(&self, user_index: u32) -> Option<RecordedFrame> {
+    let track = &self.tracks[&user_index];
+    match self.options.align {
+      // the last frame recorded at or before the position
+      PlaybackAlign::Timestamp => {
+        let next = track.frames.partition_point(|frame| frame.at <= self.position);
+        next.checked_sub(1).map(|index| track.frames[index])
+      },
+      PlaybackAlign::PollCount if track.done => None,
+      PlaybackAlign::PollCount => track.next.checked_sub(1).map(|index| track.frames[index]),
+    }
+  }
+}
+
+// Anything beyond the resting noise of a physical pad
+fn has_real_input(gamepad: &XInputGamepad) -> bool {
+  let outside = |x: i16, y: i16, deadzone: i16| {
+    let (x, y) = (x as f32, y as f32);
+    (x * x + y * y).sqrt() > deadzone as f32
+  };
+
+  gamepad.buttons != 0
+    || gamepad.left_trigger > XINPUT_GAMEPAD_TRIGGER_THRESHOLD
+    || gamepad.right_trigger > XINPUT_GAMEPAD_TRIGGER_THRESHOLD
+    || outside(gamepad.thumb_lx, gamepad.thumb_ly, XINPUT_GAMEPAD_LEFT_THUMB_DEADZONE)
+    || outside(gamepad.thumb_rx, gamepad.thumb_ry, XINPUT_GAMEPAD_RIGHT_THUMB_DEADZONE)
+}
+
+// Combines the live state with a recorded one, keeping the live packet number
+fn blend(mode: PlaybackBlend, live: XInputState, recorded: XInputState) -> XInputState {
+  let gamepad = match mode {
+    PlaybackBlend::Replace => recorded.gamepad,
+    PlaybackBlend::Overlay => {
+      let (live, recorded) = (live.gamepad, recorded.gamepad);
+      let further = |a: (i16, i16), b: (i16, i16)| {
+        let length = |(x, y): (i16, i16)| (x as i32).pow(2) + (y as i32).pow(2);
+        if length(b) > length(a) { b } else { a }
+      };
+      let (thumb_lx, thumb_ly) = further((live.thumb_lx, live.thumb_ly), (recorded.thumb_lx, recorded.thumb_ly));
+      let (thumb_rx, thumb_ry) = further((live.thumb_rx, live.thumb_ry), (recorded.thumb_rx, recorded.thumb_ry));
+      XInputGamepad {
+        buttons: live.buttons | recorded.buttons,
+        left_trigger: live.left_trigger.max(recorded.left_trigger),
+        right_trigger: live.right_trigger.max(recorded.right_trigger),
+        thumb_lx,
+        thumb_ly,
+        thumb_rx,
+        thumb_ry,
+      }
+    },
+  };
+
+  XInputState { packet_number: live.packet_number, gamepad }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clock::ManualClock;
+
+  const A: u16 = XINPUT_GAMEPAD_A;
+  const B: u16 = XINPUT_GAMEPAD_B;
+  const X: u16 = XINPUT_GAMEPAD_X;
+
+  fn state(packet_number: u32, buttons: u16) -> XInputState {
+    XInputState { packet_number, gamepad: XInputGamepad { buttons, ..Default::default() } }
+  }
+
+  fn frame(at_ms: u64, user_index: u32, input: u16, output: u16) -> RecordedFrame {
+    RecordedFrame { at: Duration::from_millis(at_ms), user_index, input: state(1, input), output: state(1, output) }
+  }
+
+  // A, B and X on slot 0, 100ms apart
+  fn abx(options: PlaybackOptions) -> (Playback, ManualClock) {
+    let clock = ManualClock::new();
+    let frames = [frame(0, 0, A, 0), frame(100, 0, B, 0), frame(200, 0, X, 0)];
+    (Playback::new(frames, options, Box::new(clock.clone())), clock)
+  }
+
+  // The buttons played on slot 0 at each time
+  fn play_at(playback: &mut Playback, clock: &ManualClock, times_ms: &[u64]) -> Vec<u16> {
+    times_ms
+      .iter()
+      .map(|at| {
+        clock.set(Duration::from_millis(*at));
+        playback.before_rules(0, state(1, 0)).gamepad.buttons
+      })
+      .collect()
+  }
+
+  #[test]
+  fn timestamps_pick_the_frame_recorded_at_the_same_time() {
+    let (mut playback, clock) = abx(PlaybackOptions::default());
+    assert_eq!(play_at(&mut playback, &clock, &[0, 50, 100, 150, 200]), [A, A, B, B, X]);
+    assert_eq!(play_at(&mut playback, &clock, &[201]), [0]);
+    assert_eq!(playback.state(), PlaybackState::Finished);
+  }
+
+  #[test]
+  fn polls_of_unrecorded_slots_keep_the_time() {
+    let (mut playback, clock) = abx(PlaybackOptions::default());
+    for at in [0, 8, 100, 108, 200, 208] {
+      clock.set(Duration::from_millis(at));
+      playback.before_rules(if at % 100 == 0 { 0 } else { 1 }, state(1, 0));
+    }
+    assert_eq!(playback.position(), Duration::from_millis(200));
+  }
+
+  #[test]
+  fn poll_count_plays_one_frame_per_poll() {
+    let (mut playback, clock) = abx(PlaybackOptions { align: PlaybackAlign::PollCount, ..Default::default() });
+    assert_eq!(play_at(&mut playback, &clock, &[0, 1, 2, 5000]), [A, B, X, 0]);
+    assert_eq!(playback.state(), PlaybackState::Finished);
+  }
+
+  #[test]
+  fn looping_starts_over_at_the_end() {
+    let (mut playback, clock) = abx(PlaybackOptions { looping: true, ..Default::default() });
+    assert_eq!(play_at(&mut playback, &clock, &[0, 150, 250, 320]), [A, B, A, B]);
+
+    let (mut playback, clock) = abx(PlaybackOptions { align: PlaybackAlign::PollCount, looping: true, ..Default::default() });
+    assert_eq!(play_at(&mut playback, &clock, &[0, 0, 0, 0, 0]), [A, B, X, A, B]);
+    assert_eq!(playback.state(), PlaybackState::Playing);
+  }
+
+  #[test]
+  fn seek_jumps_and_restarts_a_finished_playback() {
+    let (mut playback, clock) = abx(PlaybackOptions::default());
+    playback.seek(Duration::from_millis(150));
+    assert_eq!(play_at(&mut playback, &clock, &[0, 50]), [B, X]);
+    assert_eq!(play_at(&mut playback, &clock, &[300]), [0]);
+    assert!(playback.is_over());
+
+    playback.seek(Duration::ZERO);
+    assert_eq!(playback.state(), PlaybackState::Playing);
+    assert_eq!(play_at(&mut playback, &clock, &[300, 400]), [A, B]);
+  }
+
+  #[test]
+  fn pausing_holds_the_position() {
+    let (mut playback, clock) = abx(PlaybackOptions::default());
+    assert_eq!(play_at(&mut playback, &clock, &[0, 50]), [A, A]);
+    playback.set_paused(true);
+    assert_eq!(play_at(&mut playback, &clock, &[500, 1000]), [A, A]);
+    playback.set_paused(false);
+    assert_eq!(play_at(&mut playback, &clock, &[1060, 1150]), [B, X]);
+  }
+
+  #[test]
+  fn using_the_pad_releases_it() {
+    let (mut playback, clock) = abx(PlaybackOptions { release_on_input: true, ..Default::default() });
+    assert_eq!(play_at(&mut playback, &clock, &[0]), [A]);
+    // resting stick noise does not count as input
+    let noise = XInputState { packet_number: 2, gamepad: XInputGamepad { thumb_lx: 500, ..Default::default() } };
+    assert_eq!(playback.before_rules(0, noise).gamepad.buttons, A);
+    assert_eq!(playback.before_rules(0, state(3, B)).gamepad.buttons, B);
+    assert_eq!(playback.state(), PlaybackState::Released);
+    assert_eq!(play_at(&mut playback, &clock, &[100]), [0]);
+  }
+
+  #[test]
+  fn overlay_adds_the_recording_to_the_pad() {
+    let clock = ManualClock::new();
+    let recorded = XInputState {
+      packet_number: 9,
+      gamepad: XInputGamepad { buttons: A, left_trigger: 50, right_trigger: 200, thumb_lx: 10000, thumb_rx: 1000, ..Default::default() },
+    };
+    let frames = [RecordedFrame { at: Duration::ZERO, user_index: 0, input: recorded, output: recorded }];
+    let options = PlaybackOptions { blend: PlaybackBlend::Overlay, ..Default::default() };
+    let mut playback = Playback::new(frames, options, Box::new(clock));
+
+    let live = XInputState {
+      packet_number: 4,
+      gamepad: XInputGamepad { buttons: B, left_trigger: 100, right_trigger: 0, thumb_lx: -20000, thumb_rx: 0, thumb_ry: 500, ..Default::default() },
+    };
+    let blended = playback.before_rules(0, live);
+    assert_eq!(blended.packet_number, 4);
+    assert_eq!(blended.gamepad.buttons, A | B);
+    assert_eq!((blended.gamepad.left_trigger, blended.gamepad.right_trigger), (100, 200));
+    assert_eq!((blended.gamepad.thumb_lx, blended.gamepad.thumb_ly), (-20000, 0));
+    assert_eq!((blended.gamepad.thumb_rx, blended.gamepad.thumb_ry), (1000, 0));
+  }
+
+  #[test]
+  fn output_source_bumps_the_packet_number_when_the_output_changes() {
+    let clock = ManualClock::new();
+    let frames = [frame(0, 0, 0, A), frame(100, 0, 0, A), frame(200, 0, 0, B)];
+    let options = PlaybackOptions { source: PlaybackSource::Output, ..Default::default() };
+    let mut playback = Playback::new(frames, options, Box::new(clock.clone()));
+
+    let mut played = vec![];
+    for at in [0, 100, 200] {
+      clock.set(Duration::from_millis(at));
+      // the recorded output skips the rules, so they see the live pad
+      let before = playback.before_rules(0, state(5, X));
+      assert_eq!(before.gamepad.buttons, X);
+      let after = playback.after_rules(0, before);
+      played.push((after.packet_number, after.gamepad.buttons));
+    }
+    assert_eq!(played, [(6, A), (6, A), (7, B)]);
+  }
+}
@@ -0,0 +1,651 @@
+// Declarative remap profiles. A profile is a TOML file describing button
+// remaps, smoothing filters, stick swaps, shaping and mappings, trigger
+// shaping, mappings and overrides, latches, turbos, macros, combos, layers,
+// rumble, the device the game is told about and the pads feeding the slot, so
+// mappings can be changed without touching handle_controller_state. See
+// profiles/default.toml for the format.
+
+use std::{collections::HashSet, fmt, fs, io, path::Path, path::PathBuf, sync::Arc, time::Duration};
+use serde::{Deserialize, Deserializer, Serialize};
+use super::button::{Button, Buttons};
+use super::combo::{Combo, ComboState};
+use super::controller_slot::RuleState;
+use super::device::DeviceOptions;
+use super::filter::{AxisFilters, FilterState};
+use super::mutable_xinput_state::*;
+use super::function_scheduler::*;
+use super::latch::{Latch, LatchState};
+use super::layer::{Layer, LayerState};
+use super::routing::{Routing, StickMerge};
+use super::rumble::RumbleOptions;
+use super::stick::{AxisSwizzle, ButtonsToStick, StickShaping, StickToButtons, StickToButtonsState};
+use super::trigger::{ButtonToTrigger, TriggerShaping, TriggerToButton};
+use super::turbo::{Turbo, TurboState};
+use super::xinput_state::{XInputBatteryInformation, XInputCapabilities, XInputVibration};
+
+/// The sections of a profile that hold rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+  Remap,
+  Filters,
+  Sticks,
+  StickToButtons,
+  ButtonsToStick,
+  Triggers,
+  TriggerToButton,
+  ButtonToTrigger,
+  Trigger,
+  Latch,
+  Turbo,
+  Macro,
+  Combo,
+  Layer,
+  Rumble,
+  Device,
+  Routing,
+}
+
+/// Identifies a single rule by its section and its position in that section,
+/// e.g. the second `[[remap]]` is `RuleId { kind: RuleKind::Remap, index: 1 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RuleId {
+  pub kind: RuleKind,
+  pub index: usize,
+}
+
+impl RuleId {
+  pub fn new(kind: RuleKind, index: usize) -> Self {
+    Self { kind, index }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerSide {
+  Left,
+  Right,
+}
+
+/// Presses `to` whenever `from` is pressed. Unless `keep_source` is set the
+/// `from` button is hidden from the game, so two remaps can swap buttons.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonRemap {
+  pub from: Button,
+  pub to: Button,
+  #[serde(default)]
+  pub keep_source: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StickOptions {
+  /// Swap the left and right sticks.
+  #[serde(default)]
+  pub swap: bool,
+  /// Where each axis takes its value from, after `swap`.
+  #[serde(default)]
+  pub axes: Option<AxisSwizzle>,
+  /// Deadzone, response curve and transforms of the left stick the game
+  /// sees, after `swap` and `axes`.
+  #[serde(default)]
+  pub left: Option<StickShaping>,
+  #[serde(default)]
+  pub right: Option<StickShaping>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerOptions {
+  /// Deadzones and response curve, or hair trigger, of the left trigger.
+  #[serde(default)]
+  pub left: Option<TriggerShaping>,
+  #[serde(default)]
+  pub right: Option<TriggerShaping>,
+}
+
+/// Forces a trigger to `value`, either always or only while `when` is held.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerOverride {
+  pub side: TriggerSide,
+  pub value: u8,
+  #[serde(default)]
+  pub when: Option<Button>,
+}
+
+/// One step of a macro. The step is applied on every poll for `duration_ms`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacroStep {
+  pub duration_ms: u64,
+  #[serde(default)]
+  pub press: Vec<Button>,
+  #[serde(default)]
+  pub release: Vec<Button>,
+  #[serde(default)]
+  pub left_trigger: Option<u8>,
+  #[serde(default)]
+  pub right_trigger: Option<u8>,
+  /// Left and right motor speeds, 0 to 65535, on top of the game's rumble.
+  #[serde(default)]
+  pub rumble: Option<(u16, u16)>,
+}
+
+/// Runs `steps` through the function scheduler each time `on` is pressed.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Macro {
+  pub on: Button,
+  /// Hide the `on` button from the game.
+  #[serde(default)]
+  pub suppress: bool,
+  #[serde(deserialize_with = "deserialize_steps")]
+  pub steps: Vec<MacroStep>,
+}
+
+// ScheduledFunctionStack needs at least one function to run
+fn deserialize_steps<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<MacroStep>, D::Error> {
+  let steps = Vec::<MacroStep>::deserialize(deserializer)?;
+  if steps.is_empty() {
+    return Err(serde::de::Error::custom("a macro needs at least one step"));
+  }
+  Ok(steps)
+}
+
+impl Macro {
+  /// Builds a fresh ScheduledFunctionStack that plays back the steps.
+  pub fn to_scheduled_function_stack(&self) -> ScheduledFunctionStack {
+    steps_to_scheduled_function_stack(&self.steps)
+  }
+}
+
+/// Builds a ScheduledFunctionStack that plays back macro steps. There must be
+/// at least one step.
+pub fn steps_to_scheduled_function_stack(steps: &[MacroStep]) -> ScheduledFunctionStack {
+  let functions = steps.iter().map(|step| {
+    let press: Buttons = step.press.iter().collect();
+    let release: Buttons = step.release.iter().collect();
+    let left_trigger = step.left_trigger;
+    let right_trigger = step.right_trigger;
+    let rumble = step.rumble;
+
+    let func = move |cs: &MutableXInputState| {
+      cs.set_buttons_raw(press.bits(), ButtonState::DOWN);
+      cs.set_buttons_raw(release.bits(), ButtonState::UP);
+      if let Some(value) = left_trigger {
+        cs.set_left_trigger(value);
+      }
+      if let Some(value) = right_trigger {
+        cs.set_right_trigger(value);
+      }
+      if let Some((left_motor_speed, right_motor_speed)) = rumble {
+        cs.set_rumble(XInputVibration { left_motor_speed, right_motor_speed });
+      }
+    };
+
+    ScheduledFunction::new(Duration::from_millis(step.duration_ms), Box::new(func))
+  });
+
+  ScheduledFunctionStack::new(functions.collect())
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+  #[serde(default)]
+  pub name: String,
+  #[serde(default, rename = "remap")]
+  pub remaps: Vec<ButtonRemap>,
+  #[serde(default)]
+  pub filters: AxisFilters,
+  #[serde(default)]
+  pub sticks: StickOptions,
+  #[serde(default, rename = "stick_to_buttons")]
+  pub stick_to_buttons: Vec<StickToButtons>,
+  #[serde(default, rename = "buttons_to_stick")]
+  pub buttons_to_sticks: Vec<ButtonsToStick>,
+  #[serde(default, rename = "triggers")]
+  pub trigger_options: TriggerOptions,
+  #[serde(default, rename = "trigger_to_button")]
+  pub trigger_to_buttons: Vec<TriggerToButton>,
+  #[serde(default, rename = "button_to_trigger")]
+  pub button_to_triggers: Vec<ButtonToTrigger>,
+  #[serde(default, rename = "trigger")]
+  pub triggers: Vec<TriggerOverride>,
+  #[serde(default, rename = "latch")]
+  pub latches: Vec<Latch>,
+  #[serde(default, rename = "turbo")]
+  pub turbos: Vec<Turbo>,
+  #[serde(default, rename = "macro")]
+  pub macros: Vec<Macro>,
+  #[serde(default, rename = "combo")]
+  pub combos: Vec<Combo>,
+  #[serde(default, rename = "layer")]
+  pub layers: Vec<Layer>,
+  #[serde(default)]
+  pub rumble: RumbleOptions,
+  #[serde(default)]
+  pub device: DeviceOptions,
+  #[serde(default)]
+  pub routing: Routing,
+  /// Rules switched off at runtime. This is not part of the file format.
+  #[serde(skip)]
+  pub disabled_rules: HashSet<RuleId>,
+  // new for every parse and shared by clones, so it tells the same rules
+  // apart from a reloaded file even when some were switched on or off
+  #[serde(skip)]
+  rules_id: Arc<()>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+  Io { path: PathBuf, error: io::Error },
+  Parse { path: Option<PathBuf>, error: toml::de::Error },
+}
+
+impl fmt::Display for ProfileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProfileError::Io { path, error } => write!(f, "could not read profile {}: {}", path.display(), error),
+      // toml's message already includes the line, column and a snippet of the offending line
+      ProfileError::Parse { path: Some(path), error } => write!(f, "invalid profile {}\n{}", path.display(), error),
+      ProfileError::Parse { path: None, error } => write!(f, "invalid profile\n{}", error),
+    }
+  }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl Profile {
+  pub fn from_toml_str(source: &str) -> Result<Self, ProfileError> {
+    toml::from_str(source).map_err(|error| ProfileError::Parse { path: None, error })
+  }
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)
+      .map_err(|error| ProfileError::Io { path: path.to_owned(), error })?;
+    toml::from_str(&source).map_err(|error| ProfileError::Parse { path: Some(path.to_owned()), error })
+  }
+
+  pub fn is_rule_enabled(&self, rule: RuleId) -> bool {
+    !self.disabled_rules.contains(&rule)
+  }
+
+  /// Switches a single rule on or off. Returns false if there is no such rule.
+  pub fn set_rule_enabled(&mut self, rule: RuleId, enabled: bool) -> bool {
+    if !self.has_rule(rule) {
+      return false;
+    }
+    if enabled {
+      self.disabled_rules.remove(&rule);
+    } else {
+      self.disabled_rules.insert(rule);
+    }
+    true
+  }
+
+  /// Whether both profiles come from the same parse of a file, so rule state
+  /// kept by position still belongs to the same rules. Switching rules on or
+  /// off does not change this.
+  pub fn has_same_rules(&self, other: &Profile) -> bool {
+    Arc::ptr_eq(&self.rules_id, &other.rules_id)
+  }
+
+  /// Whether the profile has a rule with the given id.
+  pub fn has_rule(&self, rule: RuleId) -> bool {
+    rule.index < self.rule_count(rule.kind)
+  }
+
+  /// How many rules a section has. Sections that are a single table count
+  /// as one rule, even when the table is left out.
+  pub fn rule_count(&self, kind: RuleKind) -> usize {
+    match kind {
+      RuleKind::Remap => self.remaps.len(),
+      RuleKind::StickToButtons => self.stick_to_buttons.len(),
+      RuleKind::ButtonsToStick => self.buttons_to_sticks.len(),
+      RuleKind::TriggerToButton => self.trigger_to_buttons.len(),
+      RuleKind::ButtonToTrigger => self.button_to_triggers.len(),
+      RuleKind::Trigger => self.triggers.len(),
+      RuleKind::Latch => self.latches.len(),
+      RuleKind::Turbo => self.turbos.len(),
+      RuleKind::Macro => self.macros.len(),
+      RuleKind::Combo => self.combos.len(),
+      RuleKind::Layer => self.layers.len(),
+      RuleKind::Filters | RuleKind::Sticks | RuleKind::Triggers | RuleKind::Rumble | RuleKind::Device | RuleKind::Routing => 1,
+    }
+  }
+
+  /// Finds the rule `rule` of `other` in this profile, e.g. in a reloaded
+  /// file: at the same position if it is still there, otherwise the first
+  /// rule of the section with the same settings.
+  pub fn find_rule(&self, other: &Profile, rule: RuleId) -> Option<RuleId> {
+    if !other.has_rule(rule) {
+      return None;
+    }
+    let same = |index: usize| self.is_same_rule(index, other, rule);
+    if same(rule.index) {
+      return Some(rule);
+    }
+    (0..self.rule_count(rule.kind)).find(|index| same(*index)).map(|index| RuleId::new(rule.kind, index))
+  }
+
+  // Whether rule `index` of this profile has the same settings as `rule` of
+  // `other`, in the same section
+  fn is_same_rule(&self, index: usize, other: &Profile, rule: RuleId) -> bool {
+    fn same<T: PartialEq>(rules: &[T], index: usize, other_rules: &[T], other_index: usize) -> bool {
+      rules.get(index).is_some_and(|rule| other_rules.get(other_index) == Some(rule))
+    }
+    let i = rule.index;
+    match rule.kind {
+      RuleKind::Remap => same(&self.remaps, index, &other.remaps, i),
+      RuleKind::StickToButtons => same(&self.stick_to_buttons, index, &other.stick_to_buttons, i),
+      RuleKind::ButtonsToStick => same(&self.buttons_to_sticks, index, &other.buttons_to_sticks, i),
+      RuleKind::TriggerToButton => same(&self.trigger_to_buttons, index, &other.trigger_to_buttons, i),
+      RuleKind::ButtonToTrigger => same(&self.button_to_triggers, index, &other.button_to_triggers, i),
+      RuleKind::Trigger => same(&self.triggers, index, &other.triggers, i),
+      RuleKind::Latch => same(&self.latches, index, &other.latches, i),
+      RuleKind::Turbo => same(&self.turbos, index, &other.turbos, i),
+      RuleKind::Macro => same(&self.macros, index, &other.macros, i),
+      RuleKind::Combo => same(&self.combos, index, &other.combos, i),
+      RuleKind::Layer => same(&self.layers, index, &other.layers, i),
+      RuleKind::Filters => index == 0 && self.filters == other.filters,
+      RuleKind::Sticks => index == 0 && self.sticks == other.sticks,
+      RuleKind::Triggers => index == 0 && self.trigger_options == other.trigger_options,
+      RuleKind::Rumble => index == 0 && self.rumble == other.rumble,
+      RuleKind::Device => index == 0 && self.device == other.device,
+      RuleKind::Routing => index == 0 && self.routing == other.routing,
+    }
+  }
+
+  /// Reshapes the vibration the game asks for with the `[rumble]` options,
+  /// unless they are switched off.
+  pub fn apply_rumble(&self, vibration: XInputVibration) -> XInputVibration {
+    if self.is_rule_enabled(RuleId::new(RuleKind::Rumble, 0)) {
+      self.rumble.apply(vibration)
+    } else {
+      vibration
+    }
+  }
+
+  /// The capabilities to report with the `[device]` options, unless they are
+  /// switched off. See DeviceOptions::capabilities.
+  pub fn apply_capabilities(&self, reported: Option<XInputCapabilities>) -> Option<XInputCapabilities> {
+    if self.is_rule_enabled(RuleId::new(RuleKind::Device, 0)) {
+      self.device.capabilities(reported)
+    } else {
+      reported
+    }
+  }
+
+  /// Whether the `[device]` options put a virtual pad on the slot while it is
+  /// empty, unless they are switched off.
+  pub fn wants_virtual_pad(&self) -> bool {
+    self.device.virtual_pad && self.is_rule_enabled(RuleId::new(RuleKind::Device, 0))
+  }
+
+  /// The gamepad battery to report with the `[device]` options, unless they
+  /// are switched off. See DeviceOptions::battery_information.
+  pub fn apply_battery_information(&self, reported: Option<XInputBatteryInformation>) -> Option<XInputBatteryInformation> {
+    if self.is_rule_enabled(RuleId::new(RuleKind::Device, 0)) {
+      self.device.battery_information(reported)
+    } else {
+      reported
+    }
+  }
+
+  /// The `[routing]` options, or no routing if they are switched off. See
+  /// routing::routing_table for the pads each slot ends up reading.
+  pub fn active_routing(&self) -> &Routing {
+    static NO_ROUTING: Routing = Routing { pads: Vec::new(), sticks: StickMerge::Largest };
+    if self.is_rule_enabled(RuleId::new(RuleKind::Routing, 0)) {
+      &self.routing
+    } else {
+      &NO_ROUTING
+    }
+  }
+
+  // Applies every enabled rule in the profile to the controller state. Rules
+  // read the buttons as they were before any remapping, so the order of rules
+  // within a section does not matter.
+  // `rule_state` holds what the rules remember between polls of this slot.
+  pub fn apply(&self, controller_state: &MutableXInputState, scheduled_functions: &mut Vec<ScheduledFunctionStack>, rule_state: &mut RuleState) {
+    let enabled = |kind: RuleKind, index: usize| self.is_rule_enabled(RuleId::new(kind, index));
+
+    // Smoothing filters see the pad before anything else
+    if enabled(RuleKind::Filters, 0) && !self.filters.is_empty() {
+      self.filters.apply(controller_state, &mut rule_state.filters);
+    } else {
+      rule_state.filters = FilterState::default();
+    }
+
+    // Combos go first so the buttons they suppress are hidden from every
+    // other rule
+    rule_state.combos.resize_with(self.combos.len(), ComboState::default);
+    for (i, (combo, state)) in self.combos.iter().zip(rule_state.combos.iter_mut()).enumerate() {
+      if !enabled(RuleKind::Combo, i) {
+        *state = ComboState::default();
+        continue;
+      }
+
+      if combo.update(controller_state, state) {
+        if !combo.steps.is_empty() {
+          scheduled_functions.push(steps_to_scheduled_function_stack(&combo.steps));
+        }
+        if let Some(profile) = &combo.profile {
+          rule_state.profile_switch = Some(profile.clone());
+        }
+      }
+    }
+
+    // Layers: work out which are active, then hide their buttons from the
+    // game and from the other rules
+    let mut layer_buttons = Buttons::empty();
+    rule_state.layers.resize_with(self.layers.len(), LayerState::default);
+    for (i, (layer, state)) in self.layers.iter().zip(rule_state.layers.iter_mut()).enumerate() {
+      if enabled(RuleKind::Layer, i) {
+        layer.update(controller_state, state, controller_state.is_down(layer.on));
+        layer_buttons.insert(layer.on);
+      } else {
+        *state = LayerState::default();
+      }
+    }
+    controller_state.set_buttons_raw(layer_buttons.bits(), ButtonState::UP);
+
+    let input_buttons = controller_state.buttons();
+    let is_down = |button: Button| input_buttons.contains(button);
+
+    // Work out which buttons each active layer takes over from the rules
+    // below it, starting from the top layer
+    let mut taken = layer_buttons;
+    let mut taken_above = vec![Buttons::empty(); self.layers.len()];
+    for (i, (layer, state)) in self.layers.iter().zip(rule_state.layers.iter()).enumerate().rev() {
+      if state.is_active() {
+        taken_above[i] = taken;
+        taken |= layer.inputs();
+      }
+    }
+
+    // Collect the rules to run, from the base profile up through the active
+    // layers, so a trigger value set by a higher layer wins
+    let free = |taken: Buttons, button: Button| !taken.contains(button);
+    let mut remaps: Vec<&ButtonRemap> = vec![];
+    let mut triggers: Vec<&TriggerOverride> = vec![];
+    let mut latches: Vec<(&Latch, &mut LatchState)> = vec![];
+    let mut turbos: Vec<(&Turbo, &mut TurboState)> = vec![];
+    let mut macros: Vec<&Macro> = vec![];
+
+    remaps.extend(self.remaps.iter()
+      .enumerate()
+      .filter(|(i, remap)| enabled(RuleKind::Remap, *i) && free(taken, remap.from))
+      .map(|(_, remap)| remap));
+    triggers.extend(self.triggers.iter()
+      .enumerate()
+      .filter(|(i, trigger)| enabled(RuleKind::Trigger, *i) && trigger.when.is_none_or(|button| free(taken, button)))
+      .map(|(_, trigger)| trigger));
+    rule_state.latches.resize_with(self.latches.len(), LatchState::default);
+    for (i, (latch, state)) in self.latches.iter().zip(rule_state.latches.iter_mut()).enumerate() {
+      if !enabled(RuleKind::Latch, i) {
+        *state = LatchState::default();
+      } else if free(taken, latch.on) {
+        latches.push((latch, state));
+      }
+    }
+    rule_state.turbos.resize_with(self.turbos.len(), TurboState::default);
+    for (i, (turbo, state)) in self.turbos.iter().zip(rule_state.turbos.iter_mut()).enumerate() {
+      if !enabled(RuleKind::Turbo, i) {
+        *state = TurboState::default();
+      } else if free(taken, turbo.on) {
+        turbos.push((turbo, state));
+      }
+    }
+    macros.extend(self.macros.iter()
+      .enumerate()
+      .filter(|(i, macro_rule)| enabled(RuleKind::Macro, *i) && free(taken, macro_rule.on))
+      .map(|(_, macro_rule)| macro_rule));
+
+    for ((layer, state), taken) in self.layers.iter().zip(rule_state.layers.iter_mut()).zip(taken_above) {
+      if !state.is_active() {
+        continue;
+      }
+      remaps.extend(layer.remaps.iter().filter(|remap| free(taken, remap.from)));
+      triggers.extend(layer.triggers.iter().filter(|trigger| trigger.when.is_none_or(|button| free(taken, button))));
+      latches.extend(layer.latches.iter().zip(state.latches.iter_mut()).filter(|(latch, _)| free(taken, latch.on)));
+      turbos.extend(layer.turbos.iter().zip(state.turbos.iter_mut()).filter(|(turbo, _)| free(taken, turbo.on)));
+      macros.extend(layer.macros.iter().filter(|macro_rule| free(taken, macro_rule.on)));
+    }
+
+    // Button remaps: first release every hidden source, then press the targets
+    for remap in remaps.iter().filter(|remap| !remap.keep_source) {
+      controller_state.set(remap.from, ButtonState::UP);
+    }
+    for remap in remaps.iter().filter(|remap| is_down(remap.from)) {
+      controller_state.set(remap.to, ButtonState::DOWN);
+    }
+
+    // Stick swap and axis swizzle, then deadzones, response curves and
+    // transforms
+    if enabled(RuleKind::Sticks, 0) {
+      if self.sticks.swap {
+        let left_stick = controller_state.left_stick_raw();
+        let right_stick = controller_state.right_stick_raw();
+        controller_state.set_left_stick_raw(right_stick);
+        controller_state.set_right_stick_raw(left_stick);
+      }
+      if let Some(axes) = &self.sticks.axes {
+        axes.apply(controller_state);
+      }
+      if let Some(shaping) = &self.sticks.left {
+        controller_state.set_left_stick_raw(shaping.apply(controller_state.left_stick_raw()));
+      }
+      if let Some(shaping) = &self.sticks.right {
+        controller_state.set_right_stick_raw(shaping.apply(controller_state.right_stick_raw()));
+      }
+    }
+
+    // Sticks pressing buttons and buttons pushing sticks
+    rule_state.stick_to_buttons.resize_with(self.stick_to_buttons.len(), StickToButtonsState::default);
+    for (i, (rule, state)) in self.stick_to_buttons.iter().zip(rule_state.stick_to_buttons.iter_mut()).enumerate() {
+      if enabled(RuleKind::StickToButtons, i) {
+        rule.apply(controller_state, state);
+      } else {
+        *state = StickToButtonsState::default();
+      }
+    }
+    for (i, rule) in self.buttons_to_sticks.iter().enumerate() {
+      if enabled(RuleKind::ButtonsToStick, i) {
+        rule.apply(controller_state, |button| is_down(button) && free(taken, button));
+      }
+    }
+
+    // Trigger deadzones and curves, then triggers pressing buttons and
+    // buttons pushing triggers
+    if enabled(RuleKind::Triggers, 0) {
+      if let Some(shaping) = &self.trigger_options.left {
+        controller_state.set_left_trigger(shaping.apply(controller_state.left_trigger()));
+      }
+      if let Some(shaping) = &self.trigger_options.right {
+        controller_state.set_right_trigger(shaping.apply(controller_state.right_trigger()));
+      }
+    }
+    let (left_trigger, right_trigger) = (controller_state.left_trigger(), controller_state.right_trigger());
+    for (i, rule) in self.trigger_to_buttons.iter().enumerate() {
+      if enabled(RuleKind::TriggerToButton, i) {
+        let value = match rule.side {
+          TriggerSide::Left => left_trigger,
+          TriggerSide::Right => right_trigger,
+        };
+        rule.apply(controller_state, value);
+      }
+    }
+    for (i, rule) in self.button_to_triggers.iter().enumerate() {
+      if enabled(RuleKind::ButtonToTrigger, i) && free(taken, rule.from) {
+        rule.apply(controller_state, is_down(rule.from));
+      }
+    }
+
+    // Trigger overrides
+    for trigger in triggers.iter().filter(|trigger| trigger.when.is_none_or(is_down)) {
+      match trigger.side {
+        TriggerSide::Left => controller_state.set_left_trigger(trigger.value),
+        TriggerSide::Right => controller_state.set_right_trigger(trigger.value),
+      }
+    }
+
+    // Toggles and latches
+    for (latch, state) in latches {
+      latch.apply(controller_state, state, is_down(latch.on));
+    }
+
+    // Turbos
+    for (turbo, state) in turbos {
+      turbo.apply(controller_state, state, is_down(turbo.on));
+    }
+
+    // Macros start once per press, but a suppressed button stays hidden for as
+    // long as it is held
+    for macro_rule in macros.into_iter().filter(|macro_rule| is_down(macro_rule.on)) {
+      if macro_rule.suppress {
+        controller_state.set(macro_rule.on, ButtonState::UP);
+      }
+      if controller_state.just_pressed(macro_rule.on) {
+        scheduled_functions.push(macro_rule.to_scheduled_function_stack());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use super::*;
+  use crate::clock::ManualClock;
+  use crate::controller_slot::ControllerSlot;
+  use crate::pipeline::process_controller_state;
+  use crate::xinput_state::{XInputGamepad, XInputState, XINPUT_GAMEPAD_DPAD_UP};
+
+  #[test]
+  fn holding_the_on_button_starts_a_macro_once() {
+    let profile = Arc::new(
+      Profile::from_toml_str(
+        r#"
+          [[macro]]
+          on = "DPAD_UP"
+          steps = [{ duration_ms = 1000, press = ["A"] }]
+        "#,
+      )
+      .unwrap(),
+    );
+    let clock = ManualClock::new();
+    let mut slot = ControllerSlot::new(Box::new(clock.clone()));
+    let held = XInputState { packet_number: 1, gamepad: XInputGamepad { buttons: XINPUT_GAMEPAD_DPAD_UP, ..Default::default() } };
+
+    for poll in 0..10 {
+      clock.set(Duration::from_millis(poll * 16));
+      process_controller_state(0, held, &profile, &mut slot);
+    }
+    assert_eq!(slot.scheduled_functions.len(), 1);
+  }
+}
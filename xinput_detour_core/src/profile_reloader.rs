@@ -0,0 +1,225 @@
+// Hot reloading for remap profiles. The reloader owns the active profile and
+// watches its file for changes; a new profile only replaces the active one
+// once it has parsed successfully, so a broken edit never leaves the game
+// without a rule set.
+
+use std::{fs, path::Path, path::PathBuf, sync::Arc, time::{Duration, Instant, SystemTime}};
+use super::profile::{Profile, ProfileError, RuleId};
+
+pub struct ProfileReloader {
+  path: PathBuf,
+  profile: Arc<Profile>,
+  modified: Option<SystemTime>,
+  last_check: Option<Instant>,
+  reports: Vec<String>,
+}
+
+impl ProfileReloader {
+  /// How often `check_for_changes` actually looks at the file.
+  pub const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+  /// Creates a reloader with an empty profile. Call `reload` or
+  /// `check_for_changes` to load the file.
+  pub fn new(path: impl AsRef<Path>) -> Self {
+    Self {
+      path: path.as_ref().to_owned(),
+      profile: Arc::new(Profile::default()),
+      modified: None,
+      last_check: None,
+      reports: vec![],
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// The active profile. Callers keep using the returned `Arc` for the rest
+  /// of the poll, so a reload never swaps the rules out halfway through.
+  pub fn profile(&self) -> Arc<Profile> {
+    Arc::clone(&self.profile)
+  }
+
+  /// Loads the profile file and makes it active. On failure the previous
+  /// profile stays active.
+  pub fn reload(&mut self) -> Result<Arc<Profile>, ProfileError> {
+    // remember the modification time even if parsing fails so a broken file
+    // is only retried once it changes again
+    self.modified = modified_time(&self.path);
+    let mut profile = Profile::load(&self.path)?;
+
+    // rules switched off at runtime stay off as long as the file still has
+    // them, wherever they moved to; an edited rule comes back on
+    let disabled: Vec<RuleId> = self.profile.disabled_rules.iter()
+      .filter_map(|rule| profile.find_rule(&self.profile, *rule))
+      .collect();
+    for rule in disabled {
+      profile.set_rule_enabled(rule, false);
+    }
+
+    let profile = Arc::new(profile);
+    self.profile = Arc::clone(&profile);
+    Ok(profile)
+  }
+
+  /// Loads a different profile file and watches it from now on. On failure
+  /// the current file and profile stay active.
+  pub fn switch_to(&mut self, path: impl AsRef<Path>) -> Result<Arc<Profile>, ProfileError> {
+    let path = path.as_ref();
+    let profile = Arc::new(Profile::load(path)?);
+    self.path = path.to_owned();
+    self.modified = modified_time(path);
+    self.profile = Arc::clone(&profile);
+    Ok(profile)
+  }
+
+  /// Switches a rule of the active profile on or off. Returns false if the
+  /// profile has no such rule.
+  pub fn set_rule_enabled(&mut self, rule: RuleId, enabled: bool) -> bool {
+    let mut profile = (*self.profile).clone();
+    if !profile.set_rule_enabled(rule, enabled) {
+      return false;
+    }
+    self.profile = Arc::new(profile);
+    true
+  }
+
+  /// Reloads the profile if the file changed since it was last loaded, at most
+  /// once per `CHECK_INTERVAL`. The outcome is queued for `take_reports`.
+  pub fn check_for_changes(&mut self) {
+    let now = Instant::now();
+    let first_check = match self.last_check {
+      Some(last_check) if now - last_check < ProfileReloader::CHECK_INTERVAL => return,
+      Some(_) => false,
+      None => true,
+    };
+    self.last_check = Some(now);
+
+    if !first_check && modified_time(&self.path) == self.modified {
+      return;
+    }
+
+    let report = match self.reload() {
+      Ok(profile) => format!("loaded profile '{}' from {}", profile.name, self.path.display()),
+      Err(error) => format!("{}\nkeeping the previous profile", error),
+    };
+    self.reports.push(report);
+  }
+
+  /// Takes the messages from automatic reloads since the last call.
+  pub fn take_reports(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.reports)
+  }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::button::Button;
+  use crate::profile::RuleKind;
+
+  // A profile file in the temp directory, deleted when dropped
+  struct ProfileFile(PathBuf);
+
+  impl ProfileFile {
+    fn new(name: &str, source: &str) -> Self {
+      let path = std::env::temp_dir().join(format!("xinput_detour_{}_{}.toml", std::process::id(), name));
+      let file = ProfileFile(path);
+      file.write(source);
+      file
+    }
+
+    fn write(&self, source: &str) {
+      fs::write(&self.0, source).unwrap();
+    }
+  }
+
+  impl Drop for ProfileFile {
+    fn drop(&mut self) {
+      let _ = fs::remove_file(&self.0);
+    }
+  }
+
+  const A_TO_X: &str = "[[remap]]\nfrom = \"A\"\nto = \"X\"\n";
+  const B_TO_Y: &str = "[[remap]]\nfrom = \"B\"\nto = \"Y\"\n";
+  const START_TO_BACK: &str = "[[remap]]\nfrom = \"START\"\nto = \"BACK\"\n";
+
+  fn remap(index: usize) -> RuleId {
+    RuleId::new(RuleKind::Remap, index)
+  }
+
+  // The remaps of the active profile as (from, enabled)
+  fn remaps(reloader: &ProfileReloader) -> Vec<(Button, bool)> {
+    let profile = reloader.profile();
+    profile.remaps.iter().enumerate().map(|(i, rule)| (rule.from, profile.is_rule_enabled(remap(i)))).collect()
+  }
+
+  #[test]
+  fn a_broken_edit_keeps_the_previous_profile() {
+    let file = ProfileFile::new("broken_edit", &format!("name = \"first\"\n{}", A_TO_X));
+    let mut reloader = ProfileReloader::new(&file.0);
+    reloader.reload().unwrap();
+
+    file.write("name = \"second\"\n[[remap]]\nfrom = \"A\"");
+    let error = reloader.reload().unwrap_err();
+    assert!(matches!(error, ProfileError::Parse { .. }), "{}", error);
+    assert_eq!(reloader.profile().name, "first");
+    assert_eq!(remaps(&reloader), [(Button::A, true)]);
+  }
+
+  #[test]
+  fn switched_off_rules_stay_off_where_they_moved() {
+    let file = ProfileFile::new("moved_rules", &format!("{}{}", A_TO_X, B_TO_Y));
+    let mut reloader = ProfileReloader::new(&file.0);
+    reloader.reload().unwrap();
+    assert!(reloader.set_rule_enabled(remap(1), false));
+
+    // a rule added above moves B to Y down, and it stays off
+    file.write(&format!("{}{}{}", START_TO_BACK, A_TO_X, B_TO_Y));
+    reloader.reload().unwrap();
+    assert_eq!(remaps(&reloader), [(Button::Start, true), (Button::A, true), (Button::B, false)]);
+  }
+
+  #[test]
+  fn edited_or_removed_rules_come_back_on() {
+    let file = ProfileFile::new("edited_rules", &format!("{}{}", A_TO_X, B_TO_Y));
+    let mut reloader = ProfileReloader::new(&file.0);
+    reloader.reload().unwrap();
+    reloader.set_rule_enabled(remap(1), false);
+
+    // the rule now at the same position is a different one
+    file.write(&format!("{}{}", A_TO_X, START_TO_BACK));
+    reloader.reload().unwrap();
+    assert_eq!(remaps(&reloader), [(Button::A, true), (Button::Start, true)]);
+
+    // and removing it forgets it for good
+    reloader.set_rule_enabled(remap(1), false);
+    file.write(A_TO_X);
+    reloader.reload().unwrap();
+    file.write(&format!("{}{}", A_TO_X, START_TO_BACK));
+    reloader.reload().unwrap();
+    assert_eq!(remaps(&reloader), [(Button::A, true), (Button::Start, true)]);
+  }
+
+  #[test]
+  fn single_sections_stay_off_until_they_change() {
+    let rumble = "[rumble]\nswap = true\n";
+    let file = ProfileFile::new("single_sections", rumble);
+    let mut reloader = ProfileReloader::new(&file.0);
+    reloader.reload().unwrap();
+    let rumble_rule = RuleId::new(RuleKind::Rumble, 0);
+    reloader.set_rule_enabled(rumble_rule, false);
+
+    file.write(&format!("{}{}", rumble, A_TO_X));
+    reloader.reload().unwrap();
+    assert!(!reloader.profile().is_rule_enabled(rumble_rule));
+
+    file.write("[rumble]\nswap = false\n");
+    reloader.reload().unwrap();
+    assert!(reloader.profile().is_rule_enabled(rumble_rule));
+  }
+}
@@ -0,0 +1,333 @@
+// Recordings of the states seen by the hook, for reproducing bug reports,
+// building regression fixtures and checking what the mappings actually did.
+//
+// Every frame holds the state the controller reported (`input`) and the state
+// handed to the game after all rules ran (`output`) for one XInputGetState
+// call. Recordings are written in a compact binary format and can be exported
+// as JSON lines.
+//
+// Binary format, all integers little-endian:
+//
+//   header (12 bytes)
+//     0   8  magic, the ASCII bytes "XIRECORD"
+//     8   2  format version (u16), currently 1
+//     10  2  frame size in bytes (u16), currently 44
+//
+//   frames (frame size bytes each, until the end of the file)
+//     0   8  timestamp in microseconds since the recording started (u64)
+//     8   4  user_index (u32)
+//     12  16 input state
+//     28  16 output state
+//
+//   state (16 bytes)
+//     0   4  dwPacketNumber (u32)
+//     4   2  wButtons (u16)
+//     6   1  bLeftTrigger (u8)
+//     7   1  bRightTrigger (u8)
+//     8   2  sThumbLX (i16)
+//     10  2  sThumbLY (i16)
+//     12  2  sThumbRX (i16)
+//     14  2  sThumbRY (i16)
+//
+// Readers accept larger frame sizes and ignore the extra bytes, so fields can
+// be appended without bumping the version.
+
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path, time::Duration};
+use serde::{Deserialize, Serialize};
+use super::xinput_state::{XInputGamepad, XInputState};
+
+pub const MAGIC: [u8; 8] = *b"XIRECORD";
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 12;
+const STATE_SIZE: usize = 16;
+const FRAME_SIZE: usize = 8 + 4 + 2 * STATE_SIZE;
+
+/// One XInputGetState call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+  /// Time since the recording started.
+  #[serde(rename = "at_us", with = "micros")]
+  pub at: Duration,
+  pub user_index: u32,
+  /// The state as the controller reported it.
+  pub input: XInputState,
+  /// The state handed to the game.
+  pub output: XInputState,
+}
+
+// Timestamps are whole microseconds in both formats
+mod micros {
+  use std::time::Duration;
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(at: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(at.as_micros() as u64)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_micros)
+  }
+}
+
+fn invalid_data(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn encode_state(state: &XInputState, bytes: &mut [u8]) {
+  let gamepad = &state.gamepad;
+  bytes[0..4].copy_from_slice(&state.packet_number.to_le_bytes());
+  bytes[4..6].copy_from_slice(&gamepad.buttons.to_le_bytes());
+  bytes[6] = gamepad.left_trigger;
+  bytes[7] = gamepad.right_trigger;
+  bytes[8..10].copy_from_slice(&gamepad.thumb_lx.to_le_bytes());
+  bytes[10..12].copy_from_slice(&gamepad.thumb_ly.to_le_bytes());
+  bytes[12..14].copy_from_slice(&gamepad.thumb_rx.to_le_bytes());
+  bytes[14..16].copy_from_slice(&gamepad.thumb_ry.to_le_bytes());
+}
+
+fn decode_state(bytes: &[u8]) -> XInputState {
+  let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+  let i16_at = |i: usize| i16::from_le_bytes([bytes[i], bytes[i + 1]]);
+  XInputState {
+    packet_number: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    gamepad: XInputGamepad {
+      buttons: u16_at(4),
+      left_trigger: bytes[6],
+      right_trigger: bytes[7],
+      thumb_lx: i16_at(8),
+      thumb_ly: i16_at(10),
+      thumb_rx: i16_at(12),
+      thumb_ry: i16_at(14),
+    },
+  }
+}
+
+/// Writes frames in the binary format.
+pub struct RecordingWriter<W: Write> {
+  writer: W,
+  frames: u64,
+}
+
+impl RecordingWriter<BufWriter<File>> {
+  /// Creates (or truncates) a recording file.
+  pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+    Self::new(BufWriter::new(File::create(path)?))
+  }
+}
+
+impl<W: Write> RecordingWriter<W> {
+  /// Writes the header.
+  pub fn new(mut writer: W) -> io::Result<Self> {
+    let mut header = [0; HEADER_SIZE];
+    header[0..8].copy_from_slice(&MAGIC);
+    header[8..10].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header[10..12].copy_from_slice(&(FRAME_SIZE as u16).to_le_bytes());
+    writer.write_all(&header)?;
+    Ok(Self { writer, frames: 0 })
+  }
+
+  pub fn write_frame(&mut self, frame: &RecordedFrame) -> io::Result<()> {
+    let mut bytes = [0; FRAME_SIZE];
+    bytes[0..8].copy_from_slice(&(frame.at.as_micros() as u64).to_le_bytes());
+    bytes[8..12].copy_from_slice(&frame.user_index.to_le_bytes());
+    encode_state(&frame.input, &mut bytes[12..28]);
+    encode_state(&frame.output, &mut bytes[28..44]);
+    self.writer.write_all(&bytes)?;
+    self.frames += 1;
+    Ok(())
+  }
+
+  /// How many frames have been written.
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  /// Flushes and returns the underlying writer.
+  pub fn finish(mut self) -> io::Result<W> {
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+}
+
+/// Reads frames in the binary format. Iterating yields every frame until the
+/// end of the recording.
+pub struct RecordingReader<R: Read> {
+  reader: R,
+  frame_size: usize,
+}
+
+impl RecordingReader<BufReader<File>> {
+  pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+    Self::new(BufReader::new(File::open(path)?))
+  }
+}
+
+impl<R: Read> RecordingReader<R> {
+  /// Reads and checks the header.
+  pub fn new(mut reader: R) -> io::Result<Self> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header).map_err(|error| match error.kind() {
+      io::ErrorKind::UnexpectedEof => invalid_data("not a recording, the file is shorter than the header".to_owned()),
+      _ => error,
+    })?;
+
+    if header[0..8] != MAGIC {
+      return Err(invalid_data("not a recording, the file does not start with XIRECORD".to_owned()));
+    }
+    let version = u16::from_le_bytes([header[8], header[9]]);
+    if version != FORMAT_VERSION {
+      return Err(invalid_data(format!("recording format version {} is not supported, expected {}", version, FORMAT_VERSION)));
+    }
+    let frame_size = u16::from_le_bytes([header[10], header[11]]) as usize;
+    if frame_size < FRAME_SIZE {
+      return Err(invalid_data(format!("frame size {} is too small, expected at least {}", frame_size, FRAME_SIZE)));
+    }
+
+    Ok(Self { reader, frame_size })
+  }
+
+  /// Returns the next frame, or `None` at the end of the recording. A frame
+  /// cut off part way is an `UnexpectedEof` error.
+  pub fn read_frame(&mut self) -> io::Result<Option<RecordedFrame>> {
+    let mut bytes = vec![0; self.frame_size];
+    let mut read = 0;
+    while read < bytes.len() {
+      match self.reader.read(&mut bytes[read..]) {
+        Ok(0) if read == 0 => return Ok(None),
+        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(n) => read += n,
+        Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+        Err(error) => return Err(error),
+      }
+    }
+
+    Ok(Some(RecordedFrame {
+      at: Duration::from_micros(u64::from_le_bytes(bytes[0..8].try_into().unwrap())),
+      user_index: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+      input: decode_state(&bytes[12..28]),
+      output: decode_state(&bytes[28..44]),
+    }))
+  }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+  type Item = io::Result<RecordedFrame>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read_frame().transpose()
+  }
+}
+
+/// Writes one frame as a line of JSON, e.g.
+/// `{"at_us":16000,"user_index":0,"input":{...},"output":{...}}`.
+pub fn write_json_line(frame: &RecordedFrame, mut writer: impl Write) -> io::Result<()> {
+  serde_json::to_writer(&mut writer, frame)?;
+  writer.write_all(b"\n")
+}
+
+/// Converts a binary recording to JSON lines. Returns the number of frames.
+pub fn export_json_lines(reader: impl Read, mut writer: impl Write) -> io::Result<u64> {
+  let mut frames = 0;
+  for frame in RecordingReader::new(reader)? {
+    write_json_line(&frame?, &mut writer)?;
+    frames += 1;
+  }
+  writer.flush()?;
+  Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frames() -> Vec<RecordedFrame> {
+    let state = |packet_number: u32, gamepad: XInputGamepad| XInputState { packet_number, gamepad };
+    vec![
+      RecordedFrame { at: Duration::ZERO, user_index: 0, input: state(1, XInputGamepad::default()), output: state(1, XInputGamepad::default()) },
+      RecordedFrame {
+        at: Duration::from_micros(16_667),
+        user_index: 3,
+        input: state(u32::MAX, XInputGamepad { buttons: 0xf3ff, left_trigger: 1, right_trigger: 255, thumb_lx: i16::MIN, thumb_ly: -1, thumb_rx: 1, thumb_ry: i16::MAX }),
+        output: state(7, XInputGamepad { buttons: 0x1000, thumb_lx: 12345, ..Default::default() }),
+      },
+    ]
+  }
+
+  fn record(frames: &[RecordedFrame]) -> Vec<u8> {
+    let mut writer = RecordingWriter::new(vec![]).unwrap();
+    for frame in frames {
+      writer.write_frame(frame).unwrap();
+    }
+    assert_eq!(writer.frames(), frames.len() as u64);
+    writer.finish().unwrap()
+  }
+
+  fn read(bytes: &[u8]) -> io::Result<Vec<RecordedFrame>> {
+    RecordingReader::new(bytes)?.collect()
+  }
+
+  fn header_error(bytes: &[u8]) -> String {
+    RecordingReader::new(bytes).err().unwrap().to_string()
+  }
+
+  #[test]
+  fn frames_survive_a_round_trip() {
+    let bytes = record(&frames());
+    assert_eq!(bytes.len(), HEADER_SIZE + 2 * FRAME_SIZE);
+    assert_eq!(&bytes[0..8], b"XIRECORD");
+    assert_eq!(read(&bytes).unwrap(), frames());
+    assert!(read(&record(&[])).unwrap().is_empty());
+  }
+
+  #[test]
+  fn rejects_other_files() {
+    let mut bytes = record(&frames());
+    bytes[0] = b'Y';
+    assert_eq!(header_error(&bytes), "not a recording, the file does not start with XIRECORD");
+    assert_eq!(header_error(b"XIREC"), "not a recording, the file is shorter than the header");
+  }
+
+  #[test]
+  fn rejects_unsupported_headers() {
+    let mut bytes = record(&frames());
+    bytes[8..10].copy_from_slice(&2u16.to_le_bytes());
+    assert_eq!(header_error(&bytes), "recording format version 2 is not supported, expected 1");
+
+    let mut bytes = record(&frames());
+    bytes[10..12].copy_from_slice(&43u16.to_le_bytes());
+    assert_eq!(header_error(&bytes), "frame size 43 is too small, expected at least 44");
+  }
+
+  #[test]
+  fn a_cut_off_frame_is_unexpected_eof() {
+    let bytes = record(&frames());
+    let mut reader = RecordingReader::new(&bytes[..bytes.len() - 1]).unwrap();
+    assert_eq!(reader.read_frame().unwrap(), Some(frames()[0]));
+    assert_eq!(reader.read_frame().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn extra_frame_bytes_are_skipped() {
+    // a newer writer with 4 more bytes per frame
+    let bytes = record(&frames());
+    let mut wider = bytes[..HEADER_SIZE].to_vec();
+    wider[10..12].copy_from_slice(&(FRAME_SIZE as u16 + 4).to_le_bytes());
+    for frame in bytes[HEADER_SIZE..].chunks(FRAME_SIZE) {
+      wider.extend_from_slice(frame);
+      wider.extend_from_slice(&[0xaa; 4]);
+    }
+    assert_eq!(read(&wider).unwrap(), frames());
+  }
+
+  #[test]
+  fn exports_one_json_line_per_frame() {
+    let mut json = vec![];
+    assert_eq!(export_json_lines(record(&frames()).as_slice(), &mut json).unwrap(), 2);
+    let lines: Vec<&str> = std::str::from_utf8(&json).unwrap().lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with(r#"{"at_us":16667,"user_index":3,"#), "{}", lines[1]);
+    let parsed: Vec<RecordedFrame> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(parsed, frames());
+  }
+}
@@ -0,0 +1,194 @@
+// Slot routing: which physical pads feed the slot the game sees. By default
+// each slot reads its own pad. The profile's `[routing]` section can read
+// another pad instead (e.g. make pad 2 appear as player 1), or merge several
+// pads into one for co-pilot or assistive play: the game sees a button down
+// while it is down on any of the pads.
+//
+// Routing works on XInput `user_index` values, so to swap two players give
+// both slots a profile that reads the other pad. Routing moves a pad rather
+// than copying it: a pad feeds at most one slot, and a slot whose pad was
+// routed elsewhere reads as not connected. The game's rumble for a slot goes
+// to every pad routed to it.
+
+use std::convert::TryFrom;
+use serde::Deserialize;
+use super::xinput_state::{XInputState, XUSER_MAX_COUNT};
+
+/// How the sticks of merged pads combine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StickMerge {
+  /// The stick pushed furthest wins.
+  #[default]
+  Largest,
+  /// The sticks add up, clamped to the stick's range.
+  Sum,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RoutingConfig")]
+pub struct Routing {
+  /// The pads feeding the slot. Empty means the slot's own pad.
+  pub pads: Vec<u32>,
+  pub sticks: StickMerge,
+}
+
+// The profile format, checked before it becomes a Routing
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoutingConfig {
+  pads: Vec<u32>,
+  #[serde(default)]
+  sticks: StickMerge,
+}
+
+impl TryFrom<RoutingConfig> for Routing {
+  type Error = String;
+
+  fn try_from(config: RoutingConfig) -> Result<Self, Self::Error> {
+    if config.pads.is_empty() {
+      return Err("pads needs at least one pad".to_owned());
+    }
+    for (i, pad) in config.pads.iter().enumerate() {
+      if *pad >= XUSER_MAX_COUNT {
+        return Err(format!("pads must be below {}, got {}", XUSER_MAX_COUNT, pad));
+      }
+      if config.pads[..i].contains(pad) {
+        return Err(format!("pad {} is listed twice", pad));
+      }
+    }
+    Ok(Routing { pads: config.pads, sticks: config.sticks })
+  }
+}
+
+/// Works out which pads feed each slot from every slot's routing. The pads
+/// named in a routing go to the first slot naming them, then each slot without
+/// routing reads its own pad unless a routing took it. A slot can end up with
+/// no pads at all.
+pub fn routing_table(routings: [&Routing; XUSER_MAX_COUNT as usize]) -> [Vec<u32>; XUSER_MAX_COUNT as usize] {
+  let mut taken = [false; XUSER_MAX_COUNT as usize];
+  let mut table = routings.map(|routing| {
+    let pads: Vec<u32> = routing.pads.iter().copied().filter(|pad| !taken[*pad as usize]).collect();
+    for pad in &pads {
+      taken[*pad as usize] = true;
+    }
+    pads
+  });
+
+  for (user_index, pads) in table.iter_mut().enumerate() {
+    if routings[user_index].pads.is_empty() && !taken[user_index] {
+      pads.push(user_index as u32);
+    }
+  }
+  table
+}
+
+impl Routing {
+  /// Merges the states of the connected pads feeding a slot into one. Buttons
+  /// are down if they are down on any pad and triggers are pulled as far as
+  /// the furthest. The packet number changes whenever any pad's does.
+  pub fn merge(&self, states: &[XInputState]) -> XInputState {
+    let mut merged = XInputState::default();
+    for state in states {
+      let gamepad = &state.gamepad;
+      merged.packet_number = merged.packet_number.wrapping_add(state.packet_number);
+      merged.gamepad.buttons |= gamepad.buttons;
+      merged.gamepad.left_trigger = merged.gamepad.left_trigger.max(gamepad.left_trigger);
+      merged.gamepad.right_trigger = merged.gamepad.right_trigger.max(gamepad.right_trigger);
+    }
+
+    let left = states.iter().map(|state| (state.gamepad.thumb_lx, state.gamepad.thumb_ly));
+    let right = states.iter().map(|state| (state.gamepad.thumb_rx, state.gamepad.thumb_ry));
+    (merged.gamepad.thumb_lx, merged.gamepad.thumb_ly) = self.merge_stick(left);
+    (merged.gamepad.thumb_rx, merged.gamepad.thumb_ry) = self.merge_stick(right);
+    merged
+  }
+
+  fn merge_stick(&self, sticks: impl Iterator<Item = (i16, i16)>) -> (i16, i16) {
+    match self.sticks {
+      StickMerge::Largest => sticks.max_by_key(|(x, y)| (*x as i64).pow(2) + (*y as i64).pow(2)).unwrap_or_default(),
+      StickMerge::Sum => {
+        let (x, y) = sticks.fold((0i32, 0i32), |(sum_x, sum_y), (x, y)| (sum_x + x as i32, sum_y + y as i32));
+        let clamp = |value: i32| value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        (clamp(x), clamp(y))
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::xinput_state::{XInputGamepad, XINPUT_GAMEPAD_A, XINPUT_GAMEPAD_B};
+
+  fn routing(pads: &[u32]) -> Routing {
+    Routing { pads: pads.to_vec(), sticks: StickMerge::Largest }
+  }
+
+  fn state(packet_number: u32, gamepad: XInputGamepad) -> XInputState {
+    XInputState { packet_number, gamepad }
+  }
+
+  #[test]
+  fn every_slot_reads_its_own_pad_by_default() {
+    let none = Routing::default();
+    assert_eq!(routing_table([&none; 4]), [vec![0], vec![1], vec![2], vec![3]]);
+  }
+
+  #[test]
+  fn reordering_moves_pads_between_slots() {
+    let (none, read_1, read_0) = (Routing::default(), routing(&[1]), routing(&[0]));
+    assert_eq!(routing_table([&read_1, &read_0, &none, &none]), [vec![1], vec![0], vec![2], vec![3]]);
+    // pad 0 is left unread and slot 1 lost its pad
+    assert_eq!(routing_table([&read_1, &none, &none, &none]), [vec![1], vec![], vec![2], vec![3]]);
+  }
+
+  #[test]
+  fn merged_pads_leave_their_own_slots() {
+    let (none, merge) = (Routing::default(), routing(&[0, 1]));
+    assert_eq!(routing_table([&merge, &none, &none, &none]), [vec![0, 1], vec![], vec![2], vec![3]]);
+  }
+
+  #[test]
+  fn the_first_slot_naming_a_pad_gets_it() {
+    // the same profile loaded on every slot
+    let merge = routing(&[0, 1]);
+    assert_eq!(routing_table([&merge; 4]), [vec![0, 1], vec![], vec![], vec![]]);
+    let (read_1, merge) = (routing(&[1]), routing(&[1, 2]));
+    assert_eq!(routing_table([&read_1, &merge, &Routing::default(), &Routing::default()]), [vec![1], vec![2], vec![], vec![3]]);
+  }
+
+  #[test]
+  fn merge_ors_buttons_and_takes_the_furthest_trigger() {
+    let merged = routing(&[0, 1]).merge(&[
+      state(3, XInputGamepad { buttons: XINPUT_GAMEPAD_A, left_trigger: 200, right_trigger: 10, ..Default::default() }),
+      state(u32::MAX, XInputGamepad { buttons: XINPUT_GAMEPAD_B, left_trigger: 50, right_trigger: 90, ..Default::default() }),
+    ]);
+    assert_eq!(merged.packet_number, 2);
+    assert_eq!(merged.gamepad.buttons, XINPUT_GAMEPAD_A | XINPUT_GAMEPAD_B);
+    assert_eq!((merged.gamepad.left_trigger, merged.gamepad.right_trigger), (200, 90));
+  }
+
+  #[test]
+  fn merge_picks_or_adds_up_sticks() {
+    let states = [
+      state(1, XInputGamepad { thumb_lx: 20000, thumb_ly: 0, thumb_rx: -30000, ..Default::default() }),
+      state(1, XInputGamepad { thumb_lx: 15000, thumb_ly: 15000, thumb_rx: -10000, ..Default::default() }),
+    ];
+    let largest = routing(&[0, 1]).merge(&states);
+    assert_eq!((largest.gamepad.thumb_lx, largest.gamepad.thumb_ly), (15000, 15000));
+    assert_eq!(largest.gamepad.thumb_rx, -30000);
+
+    let sum = Routing { pads: vec![0, 1], sticks: StickMerge::Sum }.merge(&states);
+    assert_eq!((sum.gamepad.thumb_lx, sum.gamepad.thumb_ly), (i16::MAX, 15000));
+    assert_eq!(sum.gamepad.thumb_rx, i16::MIN);
+  }
+
+  #[test]
+  fn rejects_bad_pads() {
+    let error = |pads: &[u32]| Routing::try_from(RoutingConfig { pads: pads.to_vec(), sticks: StickMerge::Largest }).unwrap_err();
+    assert_eq!(error(&[]), "pads needs at least one pad");
+    assert_eq!(error(&[0, 4]), "pads must be below 4, got 4");
+    assert_eq!(error(&[1, 2, 1]), "pad 1 is listed twice");
+  }
+}
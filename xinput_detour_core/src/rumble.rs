@@ -0,0 +1,212 @@
+// Rumble: the vibration the game asks for through XInputSetState, reshaped by
+// the profile's `[rumble]` options, plus rumble cues from macros and other
+// scheduled functions (see MutableXInputState::set_rumble). The hook runs each
+// motor at the faster of the two.
+
+use std::convert::TryFrom;
+use serde::Deserialize;
+use super::xinput_state::XInputVibration;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RumbleOptionsConfig")]
+pub struct RumbleOptions {
+  /// Swap the left and right motors.
+  pub swap: bool,
+  /// Multiplies the speed of each motor, after `swap`.
+  pub left_scale: f32,
+  pub right_scale: f32,
+  /// The fastest each motor may run, after scaling.
+  pub left_max: u16,
+  pub right_max: u16,
+  /// Stop the game's rumble altogether. Rumble cues still play.
+  pub mute: bool,
+}
+
+// The profile format, checked before it becomes RumbleOptions
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RumbleOptionsConfig {
+  #[serde(default)]
+  swap: bool,
+  #[serde(default = "default_scale")]
+  left_scale: f32,
+  #[serde(default = "default_scale")]
+  right_scale: f32,
+  #[serde(default = "default_max")]
+  left_max: u16,
+  #[serde(default = "default_max")]
+  right_max: u16,
+  #[serde(default)]
+  mute: bool,
+}
+
+fn default_scale() -> f32 {
+  1.0
+}
+
+fn default_max() -> u16 {
+  u16::MAX
+}
+
+impl TryFrom<RumbleOptionsConfig> for RumbleOptions {
+  type Error = String;
+
+  fn try_from(config: RumbleOptionsConfig) -> Result<Self, Self::Error> {
+    for (name, scale) in [("left_scale", config.left_scale), ("right_scale", config.right_scale)] {
+      if !(scale >= 0.0 && scale.is_finite()) {
+        return Err(format!("{} must be at least 0, got {}", name, scale));
+      }
+    }
+
+    Ok(RumbleOptions {
+      swap: config.swap,
+      left_scale: config.left_scale,
+      right_scale: config.right_scale,
+      left_max: config.left_max,
+      right_max: config.right_max,
+      mute: config.mute,
+    })
+  }
+}
+
+impl Default for RumbleOptions {
+  /// Leaves the game's rumble as it is.
+  fn default() -> Self {
+    Self { swap: false, left_scale: 1.0, right_scale: 1.0, left_max: u16::MAX, right_max: u16::MAX, mute: false }
+  }
+}
+
+impl RumbleOptions {
+  /// Reshapes the vibration the game asked for.
+  pub fn apply(&self, vibration: XInputVibration) -> XInputVibration {
+    if self.mute {
+      return XInputVibration::default();
+    }
+
+    let (left, right) = match self.swap {
+      true => (vibration.right_motor_speed, vibration.left_motor_speed),
+      false => (vibration.left_motor_speed, vibration.right_motor_speed),
+    };
+    let scale = |speed: u16, scale: f32, max: u16| ((speed as f32 * scale).round().min(u16::MAX as f32) as u16).min(max);
+    XInputVibration {
+      left_motor_speed: scale(left, self.left_scale, self.left_max),
+      right_motor_speed: scale(right, self.right_scale, self.right_max),
+    }
+  }
+}
+
+/// What the hook knows about the motors of one controller slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RumbleState {
+  // what the game last asked for, after the profile reshaped it
+  game: XInputVibration,
+  // what scheduled functions asked for on the latest poll
+  cue: Option<XInputVibration>,
+  // what was last sent to the controller
+  sent: Option<XInputVibration>,
+}
+
+impl RumbleState {
+  pub fn set_game(&mut self, vibration: XInputVibration) {
+    self.game = vibration;
+  }
+
+  pub fn set_cue(&mut self, cue: Option<XInputVibration>) {
+    self.cue = cue;
+  }
+
+  /// What the motors should run at: the faster of the game and the cue.
+  pub fn output(&self) -> XInputVibration {
+    let cue = self.cue.unwrap_or_default();
+    XInputVibration {
+      left_motor_speed: self.game.left_motor_speed.max(cue.left_motor_speed),
+      right_motor_speed: self.game.right_motor_speed.max(cue.right_motor_speed),
+    }
+  }
+
+  /// Returns the output and remembers that it was sent to the controller.
+  pub fn send(&mut self) -> XInputVibration {
+    let output = self.output();
+    self.sent = Some(output);
+    output
+  }
+
+  /// Returns the output to send if it changed since it was last sent.
+  pub fn take_changed(&mut self) -> Option<XInputVibration> {
+    if self.output() == self.sent.unwrap_or_default() {
+      return None;
+    }
+    Some(self.send())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options(toml: &str) -> RumbleOptions {
+    toml::from_str(toml).unwrap()
+  }
+
+  fn vibration(left_motor_speed: u16, right_motor_speed: u16) -> XInputVibration {
+    XInputVibration { left_motor_speed, right_motor_speed }
+  }
+
+  #[test]
+  fn default_options_leave_the_rumble_alone() {
+    assert_eq!(options("").apply(vibration(1000, 65535)), vibration(1000, 65535));
+  }
+
+  #[test]
+  fn swaps_then_scales_then_caps() {
+    let options = options("swap = true\nleft_scale = 2.0\nright_scale = 0.5\nright_max = 400");
+    assert_eq!(options.apply(vibration(1000, 300)), vibration(600, 400));
+    assert_eq!(options.apply(vibration(600, 40000)), vibration(65535, 300));
+  }
+
+  #[test]
+  fn mute_stops_the_game_rumble() {
+    assert_eq!(options("mute = true\nleft_scale = 2.0").apply(vibration(1000, 2000)), vibration(0, 0));
+  }
+
+  #[test]
+  fn rejects_bad_scales() {
+    let error = |toml: &str| toml::from_str::<RumbleOptions>(toml).unwrap_err().message().to_owned();
+    assert_eq!(error("left_scale = -1.0"), "left_scale must be at least 0, got -1");
+    assert_eq!(error("right_scale = nan"), "right_scale must be at least 0, got NaN");
+  }
+
+  #[test]
+  fn cues_run_the_motors_at_least_as_fast() {
+    let mut state = RumbleState::default();
+    state.set_game(vibration(1000, 5000));
+    state.set_cue(Some(vibration(3000, 0)));
+    assert_eq!(state.output(), vibration(3000, 5000));
+  }
+
+  #[test]
+  fn take_changed_reports_only_changes() {
+    let mut state = RumbleState::default();
+    // the motors start stopped
+    assert_eq!(state.take_changed(), None);
+
+    state.set_game(vibration(1000, 0));
+    assert_eq!(state.take_changed(), Some(vibration(1000, 0)));
+    assert_eq!(state.take_changed(), None);
+
+    // a cue slower than the game changes nothing
+    state.set_cue(Some(vibration(500, 0)));
+    assert_eq!(state.take_changed(), None);
+    state.set_cue(Some(vibration(500, 200)));
+    assert_eq!(state.take_changed(), Some(vibration(1000, 200)));
+
+    // the cue ending stops its motor again
+    state.set_cue(None);
+    assert_eq!(state.take_changed(), Some(vibration(1000, 0)));
+
+    // send keeps take_changed from repeating it
+    state.set_game(vibration(0, 0));
+    assert_eq!(state.send(), vibration(0, 0));
+    assert_eq!(state.take_changed(), None);
+  }
+}
@@ -0,0 +1,234 @@
+// A headless stand-in for the game. It feeds scripted gamepad frames through
+// the same pipeline the detour runs on every XInputGetState call, but with
+// virtual timestamps instead of the wall clock, so rules and macros behave the
+// same on every run and can be checked with plain `cargo test` assertions.
+
+use std::{sync::Arc, time::Duration};
+use super::button::Buttons;
+use super::clock::ManualClock;
+use super::controller_slot::ControllerSlot;
+use super::pipeline::process_controller_state;
+use super::profile::Profile;
+use super::xinput_state::{XInputGamepad, XInputState};
+
+/// One gamepad state at a point in virtual time, measured from the start of
+/// the simulation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+  pub at: Duration,
+  pub gamepad: XInputGamepad,
+}
+
+impl Frame {
+  pub fn new(at: Duration, gamepad: XInputGamepad) -> Self {
+    Self { at, gamepad }
+  }
+
+  /// A frame with only the given buttons down.
+  pub fn buttons(at: Duration, buttons: Buttons) -> Self {
+    Self::new(at, XInputGamepad { buttons: buttons.bits(), ..Default::default() })
+  }
+
+  pub fn pressed(&self) -> Buttons {
+    Buttons::from_bits(self.gamepad.buttons)
+  }
+}
+
+/// Builds evenly spaced frames from a list of gamepad states, e.g. one every
+/// 16ms for a game polling at roughly 60 frames per second.
+pub fn frames_every(interval: Duration, gamepads: impl IntoIterator<Item = XInputGamepad>) -> Vec<Frame> {
+  gamepads
+    .into_iter()
+    .enumerate()
+    .map(|(i, gamepad)| Frame::new(interval * i as u32, gamepad))
+    .collect()
+}
+
+/// A single controller slot driven by scripted frames.
+pub struct Simulation {
+  user_index: u32,
+  profile: Arc<Profile>,
+  slot: ControllerSlot,
+  // shared with the slot, set to the timestamp of each frame
+  clock: ManualClock,
+  last: Option<Frame>,
+  packet_number: u32,
+}
+
+impl Simulation {
+  /// Simulates controller slot 0 with the given profile.
+  pub fn new(profile: Profile) -> Self {
+    let clock = ManualClock::new();
+    Self {
+      user_index: 0,
+      profile: Arc::new(profile),
+      slot: ControllerSlot::new(Box::new(clock.clone())),
+      clock,
+      last: None,
+      packet_number: 0,
+    }
+  }
+
+  /// Simulates a different controller slot, for rules that only apply to one
+  /// player.
+  pub fn with_user_index(mut self, user_index: u32) -> Self {
+    self.user_index = user_index;
+    self
+  }
+
+  pub fn profile(&self) -> &Profile {
+    &self.profile
+  }
+
+  /// Switches to another profile. Like in the hook, the rules start over
+  /// unless it is a clone of the current one, e.g. with a rule switched off.
+  pub fn set_profile(&mut self, profile: Profile) {
+    self.profile = Arc::new(profile);
+  }
+
+  /// The slot's scheduled functions and button history, e.g. to push a macro
+  /// the way the control channel does.
+  pub fn slot_mut(&mut self) -> &mut ControllerSlot {
+    &mut self.slot
+  }
+
+  /// Runs one poll and returns what the game would see. Frames must be fed in
+  /// time order.
+  pub fn step(&mut self, frame: Frame) -> Frame {
+    if let Some(last) = self.last {
+      assert!(frame.at >= last.at, "frames must be fed in time order");
+    }
+
+    // XInput only bumps the packet number when the state changed
+    if self.last.is_none_or(|last| last.gamepad != frame.gamepad) {
+      self.packet_number = self.packet_number.wrapping_add(1);
+    }
+    self.last = Some(frame);
+    self.clock.set(frame.at);
+
+    let state = XInputState { packet_number: self.packet_number, gamepad: frame.gamepad };
+    let output = process_controller_state(self.user_index, state, &self.profile, &mut self.slot);
+    Frame::new(frame.at, output.gamepad)
+  }
+
+  /// Runs every frame and returns the output frames in the same order.
+  pub fn run(&mut self, frames: impl IntoIterator<Item = Frame>) -> Vec<Frame> {
+    frames.into_iter().map(|frame| self.step(frame)).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::button::Button;
+  use crate::clock::FrameClock;
+
+  const FRAME: Duration = Duration::from_millis(16);
+
+  fn profile(toml: &str) -> Profile {
+    Profile::from_toml_str(toml).unwrap()
+  }
+
+  #[test]
+  fn holding_b_yields_x() {
+    let mut simulation = Simulation::new(profile("[[remap]]\nfrom = \"B\"\nto = \"X\""));
+    let held_b = frames_every(FRAME, [XInputGamepad { buttons: Button::B.mask(), ..Default::default() }; 3]);
+    let output = simulation.run(held_b);
+    assert_eq!(output.len(), 3);
+    for frame in output {
+      assert_eq!(frame.pressed(), Buttons::from(Button::X));
+    }
+  }
+
+  #[test]
+  fn macro_steps_follow_virtual_time() {
+    let mut simulation = Simulation::new(profile(
+      r#"
+        [[macro]]
+        on = "DPAD_DOWN"
+        suppress = true
+        steps = [
+          { duration_ms = 100, press = ["A"] },
+          { duration_ms = 100, release = ["A"] },
+        ]
+      "#,
+    ));
+    let on = simulation.step(Frame::buttons(Duration::ZERO, Button::DpadDown.into()));
+    assert_eq!(on.pressed(), Buttons::from(Button::A));
+    assert!(simulation.step(Frame::buttons(Duration::from_millis(99), Buttons::empty())).pressed().contains(Button::A));
+    assert!(!simulation.step(Frame::buttons(Duration::from_millis(100), Buttons::empty())).pressed().contains(Button::A));
+    assert!(!simulation.step(Frame::buttons(Duration::from_millis(250), Buttons::empty())).pressed().contains(Button::A));
+    assert!(simulation.slot_mut().scheduled_functions.is_empty());
+  }
+
+  #[test]
+  fn frame_clock_times_macro_steps_in_polls() {
+    let mut simulation = Simulation::new(profile(
+      r#"
+        [[macro]]
+        on = "DPAD_DOWN"
+        suppress = true
+        steps = [{ duration_ms = 48, press = ["A"] }]
+      "#,
+    ));
+    simulation.slot_mut().clock = Box::new(FrameClock::new(FRAME));
+
+    // the timestamps are ignored, only the number of polls counts
+    let mut frames = vec![Frame::buttons(Duration::ZERO, Button::DpadDown.into())];
+    frames.extend((1..6).map(|i| Frame::buttons(Duration::from_secs(i), Buttons::empty())));
+    let pressed: Vec<bool> = simulation.run(frames).iter().map(|frame| frame.pressed().contains(Button::A)).collect();
+    assert_eq!(pressed, [true, true, true, false, false, false]);
+  }
+
+  #[test]
+  fn macro_pulls_the_trigger() {
+    let mut simulation = Simulation::new(profile(
+      r#"
+        [[macro]]
+        on = "DPAD_DOWN"
+        suppress = true
+        steps = [
+          { duration_ms = 100, right_trigger = 255 },
+          { duration_ms = 100, right_trigger = 0 },
+        ]
+      "#,
+    ));
+    let output = simulation.run([
+      Frame::buttons(Duration::ZERO, Button::DpadDown.into()),
+      Frame::buttons(Duration::from_millis(50), Buttons::empty()),
+      Frame::buttons(Duration::from_millis(150), Buttons::empty()),
+    ]);
+    assert_eq!(output[0].pressed(), Buttons::empty());
+    assert_eq!(output[0].gamepad.right_trigger, 255);
+    assert_eq!(output[1].gamepad.right_trigger, 255);
+    assert_eq!(output[2].gamepad.right_trigger, 0);
+  }
+
+  #[test]
+  fn switching_profiles_starts_the_rules_over() {
+    let latch = "[[latch]]\non = \"LEFT_SHOULDER\"\npress = \"A\"\nsuppress = true";
+    let mut simulation = Simulation::new(profile(latch));
+    let output = simulation.run([
+      Frame::buttons(Duration::ZERO, Button::LeftShoulder.into()),
+      Frame::buttons(FRAME, Buttons::empty()),
+    ]);
+    assert!(output[1].pressed().contains(Button::A));
+
+    // a reload of the same file forgets the latch
+    simulation.set_profile(profile(latch));
+    assert_eq!(simulation.step(Frame::buttons(FRAME * 2, Buttons::empty())).pressed(), Buttons::empty());
+  }
+
+  #[test]
+  fn trigger_override_only_applies_while_its_button_is_held() {
+    let mut simulation = Simulation::new(profile("[[trigger]]\nside = \"right\"\nvalue = 255\nwhen = \"RIGHT_SHOULDER\""));
+    let output = simulation.run([
+      Frame::buttons(Duration::ZERO, Buttons::empty()),
+      Frame::buttons(FRAME, Button::RightShoulder.into()),
+      Frame::buttons(FRAME * 2, Buttons::empty()),
+    ]);
+    assert_eq!(output[0].gamepad.right_trigger, 0);
+    assert_eq!(output[1].gamepad.right_trigger, 255);
+    assert_eq!(output[2].gamepad.right_trigger, 0);
+  }
+}
@@ -0,0 +1,882 @@
+// Stick shaping: rewrites a stick's raw value with a deadzone, an outer
+// deadzone, an anti-deadzone and a response curve, in that order, and then
+// transforms it (gate correction, rotation, inversion and scaling).
+//
+// All distances are in raw stick units (0 to 32,767), like
+// XINPUT_GAMEPAD_LEFT_THUMB_DEADZONE. Between the deadzone and the outer
+// deadzone the stick is mapped to 0..1, the curve reshapes that, and the
+// anti-deadzone lifts the result so the smallest push already clears the
+// game's own deadzone.
+//
+// The stick to buttons and buttons to stick rules below turn a stick into a
+// D-pad for menus, or let the D-pad steer.
+
+use std::{convert::TryFrom, f32::consts::TAU, time::Duration};
+use serde::Deserialize;
+use super::button::Button;
+use super::mutable_xinput_state::{ButtonState, MutableXInputState};
+
+const MAX: f32 = i16::MAX as f32;
+
+/// Where the deadzone is and how the stick behaves around it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadzoneShape {
+  /// A circle. Outside of it the stick keeps its raw length, so there is a
+  /// jump at the edge of the deadzone.
+  Radial,
+  /// A circle, with the rest of the range stretched to start at zero at its
+  /// edge.
+  #[default]
+  ScaledRadial,
+  /// Each axis on its own, stretched like `scaled_radial`. Small pushes on
+  /// one axis are ignored while the other is pushed.
+  Axial,
+  /// `scaled_radial`, and the stick snaps to straight up, down, left or right
+  /// while it is within the deadzone of an axis.
+  Cross,
+}
+
+/// How the distance past the deadzone (0 to 1) maps to the output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResponseCurve {
+  Linear,
+  /// The distance to the power of the exponent. Above 1 gives finer control
+  /// near the centre, below 1 a quicker start.
+  Power(f32),
+  /// Straight lines between `(input, output)` points, sorted by input.
+  Points(Vec<(f32, f32)>),
+}
+
+impl ResponseCurve {
+  pub fn apply(&self, distance: f32) -> f32 {
+    match self {
+      ResponseCurve::Linear => distance,
+      ResponseCurve::Power(exponent) => distance.powf(*exponent),
+      ResponseCurve::Points(points) => {
+        let next = points.partition_point(|(input, _)| *input < distance);
+        if next == 0 {
+          return points[0].1;
+        }
+        if next == points.len() {
+          return points[points.len() - 1].1;
+        }
+        let ((x0, y0), (x1, y1)) = (points[next - 1], points[next]);
+        y0 + (y1 - y0) * (distance - x0) / (x1 - x0)
+      },
+    }
+  }
+
+  // Checks the `curve`, `exponent` and `points` fields of a profile section
+  pub(crate) fn from_config(curve: CurveKind, exponent: Option<f32>, points: Option<Vec<(f32, f32)>>) -> Result<Self, String> {
+    match (curve, exponent, points) {
+      (CurveKind::Linear, None, None) => Ok(ResponseCurve::Linear),
+      (CurveKind::Power, Some(exponent), None) if exponent > 0.0 && exponent.is_finite() => Ok(ResponseCurve::Power(exponent)),
+      (CurveKind::Power, Some(exponent), None) => Err(format!("exponent must be above 0, got {}", exponent)),
+      (CurveKind::Power, None, None) => Err("a power curve needs an exponent".to_owned()),
+      (CurveKind::Custom, None, Some(points)) => Ok(ResponseCurve::Points(check_points(points)?)),
+      (CurveKind::Custom, None, None) => Err("a custom curve needs points".to_owned()),
+      (_, Some(_), _) => Err("exponent only goes with curve = \"power\"".to_owned()),
+      (_, _, Some(_)) => Err("points only go with curve = \"custom\"".to_owned()),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "StickShapingConfig")]
+pub struct StickShaping {
+  pub shape: DeadzoneShape,
+  /// Pushes up to this far are ignored.
+  pub deadzone: i16,
+  /// Pushes this far or further count as all the way.
+  pub outer_deadzone: i16,
+  /// The smallest push the game sees once the stick leaves the deadzone.
+  pub anti_deadzone: i16,
+  pub curve: ResponseCurve,
+  pub transform: StickTransform,
+}
+
+/// Corrects between the round range of most sticks and the square one some
+/// games expect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GateCorrection {
+  /// Stretches the circle out to the corners, so diagonals reach full on
+  /// both axes.
+  CircleToSquare,
+  /// Pulls the corners in, so a square-gated stick is never longer than a
+  /// straight push.
+  SquareToCircle,
+}
+
+/// What is done to a stick after its deadzone and curve, in field order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StickTransform {
+  pub gate: Option<GateCorrection>,
+  /// Anticlockwise, in radians.
+  pub rotation: f32,
+  pub invert_x: bool,
+  pub invert_y: bool,
+  /// Sensitivity. Each axis saturates at its limit.
+  pub scale: f32,
+}
+
+impl Default for StickTransform {
+  fn default() -> Self {
+    Self { gate: None, rotation: 0.0, invert_x: false, invert_y: false, scale: 1.0 }
+  }
+}
+
+impl StickTransform {
+  /// Transforms a stick value where 1 is a full push.
+  pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    let longest_axis = x.abs().max(y.abs());
+    let (x, y) = match self.gate {
+      _ if longest_axis == 0.0 => (x, y),
+      Some(GateCorrection::CircleToSquare) => (x * length / longest_axis, y * length / longest_axis),
+      Some(GateCorrection::SquareToCircle) => (x * longest_axis / length, y * longest_axis / length),
+      None => (x, y),
+    };
+
+    let (sin, cos) = self.rotation.sin_cos();
+    let (x, y) = (x * cos - y * sin, x * sin + y * cos);
+    let x = if self.invert_x { -x } else { x };
+    let y = if self.invert_y { -y } else { y };
+    ((x * self.scale).clamp(-1.0, 1.0), (y * self.scale).clamp(-1.0, 1.0))
+  }
+}
+
+// How a curve is named in a profile, next to its `exponent` or `points`
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CurveKind {
+  #[default]
+  Linear,
+  Power,
+  Custom,
+}
+
+// The profile format, checked before it becomes a StickShaping
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StickShapingConfig {
+  #[serde(default)]
+  shape: DeadzoneShape,
+  #[serde(default)]
+  deadzone: i16,
+  #[serde(default = "default_outer_deadzone")]
+  outer_deadzone: i16,
+  #[serde(default)]
+  anti_deadzone: i16,
+  #[serde(default)]
+  curve: CurveKind,
+  exponent: Option<f32>,
+  points: Option<Vec<(f32, f32)>>,
+  gate: Option<GateCorrection>,
+  #[serde(default)]
+  rotate_degrees: f32,
+  #[serde(default)]
+  invert_x: bool,
+  #[serde(default)]
+  invert_y: bool,
+  #[serde(default = "default_scale")]
+  scale: f32,
+}
+
+fn default_scale() -> f32 {
+  1.0
+}
+
+fn default_outer_deadzone() -> i16 {
+  i16::MAX
+}
+
+impl TryFrom<StickShapingConfig> for StickShaping {
+  type Error = String;
+
+  fn try_from(config: StickShapingConfig) -> Result<Self, Self::Error> {
+    if config.deadzone < 0 || config.deadzone >= config.outer_deadzone {
+      return Err(format!(
+        "deadzone must be at least 0 and below outer_deadzone ({}), got {}",
+        config.outer_deadzone, config.deadzone
+      ));
+    }
+    if !(0..i16::MAX).contains(&config.anti_deadzone) {
+      return Err(format!("anti_deadzone must be between 0 and 32766, got {}", config.anti_deadzone));
+    }
+    if !config.rotate_degrees.is_finite() {
+      return Err(format!("rotate_degrees must be a number of degrees, got {}", config.rotate_degrees));
+    }
+    if !(config.scale > 0.0 && config.scale.is_finite()) {
+      return Err(format!("scale must be above 0, got {}", config.scale));
+    }
+
+    Ok(StickShaping {
+      shape: config.shape,
+      deadzone: config.deadzone,
+      outer_deadzone: config.outer_deadzone,
+      anti_deadzone: config.anti_deadzone,
+      curve: ResponseCurve::from_config(config.curve, config.exponent, config.points)?,
+      transform: StickTransform {
+        gate: config.gate,
+        rotation: config.rotate_degrees.to_radians(),
+        invert_x: config.invert_x,
+        invert_y: config.invert_y,
+        scale: config.scale,
+      },
+    })
+  }
+}
+
+fn check_points(points: Vec<(f32, f32)>) -> Result<Vec<(f32, f32)>, String> {
+  if points.len() < 2 {
+    return Err("a custom curve needs at least two points".to_owned());
+  }
+  if let Some((input, output)) = points.iter().find(|(input, output)| !(0.0..=1.0).contains(input) || !(0.0..=1.0).contains(output)) {
+    return Err(format!("curve points must be between 0 and 1, got [{}, {}]", input, output));
+  }
+  if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+    return Err("curve points must be sorted by input, without repeats".to_owned());
+  }
+  Ok(points)
+}
+
+impl Default for StickShaping {
+  /// Leaves the stick as it is.
+  fn default() -> Self {
+    Self {
+      shape: DeadzoneShape::default(),
+      deadzone: 0,
+      outer_deadzone: i16::MAX,
+      anti_deadzone: 0,
+      curve: ResponseCurve::Linear,
+      transform: StickTransform::default(),
+    }
+  }
+}
+
+impl StickShaping {
+  /// Reshapes and transforms a raw stick value.
+  pub fn apply(&self, raw_stick: (i16, i16)) -> (i16, i16) {
+    let x = (raw_stick.0 as f32 / MAX).max(-1.0);
+    let y = (raw_stick.1 as f32 / MAX).max(-1.0);
+    let (x, y) = self.transform.apply(self.shape(x, y));
+    (to_raw(x), to_raw(y))
+  }
+
+  // Whether the deadzones and curve change anything
+  fn reshapes(&self) -> bool {
+    self.deadzone > 0 || self.outer_deadzone < i16::MAX || self.anti_deadzone > 0 || self.curve != ResponseCurve::Linear
+  }
+
+  fn shape(&self, x: f32, y: f32) -> (f32, f32) {
+    if !self.reshapes() {
+      return (x, y);
+    }
+
+    let deadzone = self.deadzone as f32 / MAX;
+    match self.shape {
+      DeadzoneShape::Radial | DeadzoneShape::ScaledRadial => self.shape_radial(x, y),
+      DeadzoneShape::Axial => (self.shape_axis(x), self.shape_axis(y)),
+      DeadzoneShape::Cross => {
+        let (shaped_x, shaped_y) = self.shape_radial(x, y);
+        let length = (shaped_x * shaped_x + shaped_y * shaped_y).sqrt();
+        if x.abs() < deadzone {
+          (0.0, length.copysign(y))
+        } else if y.abs() < deadzone {
+          (length.copysign(x), 0.0)
+        } else {
+          (shaped_x, shaped_y)
+        }
+      },
+    }
+  }
+
+  // Shapes the length of the stick and keeps its direction
+  fn shape_radial(&self, x: f32, y: f32) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    let distance = if self.shape == DeadzoneShape::Radial {
+      if length <= self.deadzone as f32 / MAX {
+        0.0
+      } else {
+        (length / (self.outer_deadzone as f32 / MAX)).min(1.0)
+      }
+    } else {
+      self.distance(length)
+    };
+
+    if distance == 0.0 {
+      return (0.0, 0.0);
+    }
+    let output = self.output(distance);
+    (x / length * output, y / length * output)
+  }
+
+  fn shape_axis(&self, value: f32) -> f32 {
+    let distance = self.distance(value.abs());
+    if distance == 0.0 {
+      return 0.0;
+    }
+    self.output(distance).copysign(value)
+  }
+
+  // How far past the deadzone a length is, from 0 to 1
+  fn distance(&self, length: f32) -> f32 {
+    let deadzone = self.deadzone as f32 / MAX;
+    let outer_deadzone = self.outer_deadzone as f32 / MAX;
+    ((length - deadzone) / (outer_deadzone - deadzone)).clamp(0.0, 1.0)
+  }
+
+  // The curve, lifted by the anti-deadzone
+  fn output(&self, distance: f32) -> f32 {
+    let anti_deadzone = self.anti_deadzone as f32 / MAX;
+    anti_deadzone + (1.0 - anti_deadzone) * self.curve.apply(distance).clamp(0.0, 1.0)
+  }
+}
+
+fn to_raw(value: f32) -> i16 {
+  (value * MAX).round().clamp(i16::MIN as f32, MAX) as i16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StickSide {
+  Left,
+  Right,
+}
+
+/// One axis of one stick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StickAxis {
+  LeftX,
+  LeftY,
+  RightX,
+  RightY,
+}
+
+/// Where each stick axis takes its value from, e.g. `left_x = "right_x"`
+/// steers with the right stick's X axis. An axis that is not set keeps its
+/// own value, so a single axis can be swapped across sticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisSwizzle {
+  pub left_x: Option<StickAxis>,
+  pub left_y: Option<StickAxis>,
+  pub right_x: Option<StickAxis>,
+  pub right_y: Option<StickAxis>,
+}
+
+impl AxisSwizzle {
+  pub fn apply(&self, controller_state: &MutableXInputState) {
+    let (left_x, left_y) = controller_state.left_stick_raw();
+    let (right_x, right_y) = controller_state.right_stick_raw();
+    let value = |axis: StickAxis| match axis {
+      StickAxis::LeftX => left_x,
+      StickAxis::LeftY => left_y,
+      StickAxis::RightX => right_x,
+      StickAxis::RightY => right_y,
+    };
+    let source = |axis: Option<StickAxis>, own: StickAxis| value(axis.unwrap_or(own));
+
+    controller_state.set_left_stick_raw((source(self.left_x, StickAxis::LeftX), source(self.left_y, StickAxis::LeftY)));
+    controller_state.set_right_stick_raw((source(self.right_x, StickAxis::RightX), source(self.right_y, StickAxis::RightY)));
+  }
+}
+
+fn stick(controller_state: &MutableXInputState, side: StickSide) -> (i16, i16) {
+  match side {
+    StickSide::Left => controller_state.left_stick_raw(),
+    StickSide::Right => controller_state.right_stick_raw(),
+  }
+}
+
+fn set_stick(controller_state: &MutableXInputState, side: StickSide, values: (i16, i16)) {
+  match side {
+    StickSide::Left => controller_state.set_left_stick_raw(values),
+    StickSide::Right => controller_state.set_right_stick_raw(values),
+  }
+}
+
+fn default_up() -> Button {
+  Button::DpadUp
+}
+
+fn default_down() -> Button {
+  Button::DpadDown
+}
+
+fn default_left() -> Button {
+  Button::DpadLeft
+}
+
+fn default_right() -> Button {
+  Button::DpadRight
+}
+
+/// Presses direction buttons, the D-pad unless set, while a stick is pushed.
+/// Unless `keep_source` is set the stick is centred for the game.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "StickToButtonsConfig")]
+pub struct StickToButtons {
+  pub stick: StickSide,
+  /// Up, down, left and right.
+  pub buttons: [Button; 4],
+  /// 4 for straight directions only, 8 to add the diagonals, which press two
+  /// buttons.
+  pub directions: u8,
+  /// How far the stick is pushed before a button goes down.
+  pub threshold: i16,
+  /// How far back the stick goes before the button lets go, at most
+  /// `threshold`, so a stick resting near the threshold does not flicker.
+  pub release: i16,
+  pub keep_source: bool,
+}
+
+// The profile format, checked before it becomes a StickToButtons
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StickToButtonsConfig {
+  stick: StickSide,
+  #[serde(default = "default_up")]
+  up: Button,
+  #[serde(default = "default_down")]
+  down: Button,
+  #[serde(default = "default_left")]
+  left: Button,
+  #[serde(default = "default_right")]
+  right: Button,
+  #[serde(default = "default_directions")]
+  directions: u8,
+  #[serde(default = "default_threshold")]
+  threshold: i16,
+  release: Option<i16>,
+  #[serde(default)]
+  keep_source: bool,
+}
+
+fn default_directions() -> u8 {
+  8
+}
+
+fn default_threshold() -> i16 {
+  StickToButtons::DEFAULT_THRESHOLD
+}
+
+impl TryFrom<StickToButtonsConfig> for StickToButtons {
+  type Error = String;
+
+  fn try_from(config: StickToButtonsConfig) -> Result<Self, Self::Error> {
+    if config.directions != 4 && config.directions != 8 {
+      return Err(format!("directions must be 4 or 8, got {}", config.directions));
+    }
+    if config.threshold <= 0 {
+      return Err(format!("threshold must be above 0, got {}", config.threshold));
+    }
+    let release = config.release.unwrap_or(config.threshold / 4 * 3);
+    if release <= 0 || release > config.threshold {
+      return Err(format!("release must be above 0 and at most threshold ({}), got {}", config.threshold, release));
+    }
+
+    Ok(StickToButtons {
+      stick: config.stick,
+      buttons: [config.up, config.down, config.left, config.right],
+      directions: config.directions,
+      threshold: config.threshold,
+      release,
+      keep_source: config.keep_source,
+    })
+  }
+}
+
+/// What a stick to buttons rule remembers between polls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StickToButtonsState {
+  // the direction pressed, counted anticlockwise from right in steps of 45°
+  direction: Option<u8>,
+}
+
+impl StickToButtons {
+  pub const DEFAULT_THRESHOLD: i16 = 16_384;
+  /// How far past the edge of its direction the stick can turn before another
+  /// direction is pressed.
+  pub const DIRECTION_HYSTERESIS: f32 = TAU / 48.0;
+
+  /// Presses the buttons of the direction the stick is pushed in.
+  pub fn apply(&self, controller_state: &MutableXInputState, state: &mut StickToButtonsState) {
+    let (x, y) = stick(controller_state, self.stick);
+    if !self.keep_source {
+      set_stick(controller_state, self.stick, (0, 0));
+    }
+
+    let (x, y) = (x as f32, y as f32);
+    let length = (x * x + y * y).sqrt();
+    let threshold = if state.direction.is_some() { self.release } else { self.threshold };
+    if length < threshold as f32 {
+      state.direction = None;
+      return;
+    }
+
+    // keep the current direction until the stick is clearly in another one
+    let step = if self.directions == 8 { 1 } else { 2 };
+    let sector = TAU / self.directions as f32;
+    let angle = y.atan2(x).rem_euclid(TAU);
+    let keep = state.direction.is_some_and(|direction| {
+      let centre = direction as f32 * TAU / 8.0;
+      let off = (angle - centre).rem_euclid(TAU);
+      off.min(TAU - off) <= sector / 2.0 + Self::DIRECTION_HYSTERESIS
+    });
+    if !keep {
+      let nearest = (angle / sector).round() as u8 % self.directions;
+      state.direction = Some(nearest * step);
+    }
+
+    let [up, down, left, right] = self.buttons;
+    let pressed: &[Button] = match state.direction.unwrap_or_default() {
+      0 => &[right],
+      1 => &[right, up],
+      2 => &[up],
+      3 => &[up, left],
+      4 => &[left],
+      5 => &[left, down],
+      6 => &[down],
+      _ => &[down, right],
+    };
+    for button in pressed {
+      controller_state.set(*button, ButtonState::DOWN);
+    }
+  }
+}
+
+/// Pushes a stick while direction buttons, the D-pad unless set, are held.
+/// The stick moves `magnitude` far, growing from 0 over `ramp_ms`, and takes
+/// over from the physical stick while any of the buttons is held. Unless
+/// `keep_source` is set the buttons are hidden from the game.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "ButtonsToStickConfig")]
+pub struct ButtonsToStick {
+  pub stick: StickSide,
+  /// Up, down, left and right.
+  pub buttons: [Button; 4],
+  pub magnitude: i16,
+  pub ramp: Duration,
+  pub keep_source: bool,
+}
+
+// The profile format, checked before it becomes a ButtonsToStick
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ButtonsToStickConfig {
+  stick: StickSide,
+  #[serde(default = "default_up")]
+  up: Button,
+  #[serde(default = "default_down")]
+  down: Button,
+  #[serde(default = "default_left")]
+  left: Button,
+  #[serde(default = "default_right")]
+  right: Button,
+  #[serde(default = "default_magnitude")]
+  magnitude: i16,
+  #[serde(default)]
+  ramp_ms: u64,
+  #[serde(default)]
+  keep_source: bool,
+}
+
+fn default_magnitude() -> i16 {
+  i16::MAX
+}
+
+impl TryFrom<ButtonsToStickConfig> for ButtonsToStick {
+  type Error = String;
+
+  fn try_from(config: ButtonsToStickConfig) -> Result<Self, Self::Error> {
+    if config.magnitude <= 0 {
+      return Err(format!("magnitude must be above 0, got {}", config.magnitude));
+    }
+
+    Ok(ButtonsToStick {
+      stick: config.stick,
+      buttons: [config.up, config.down, config.left, config.right],
+      magnitude: config.magnitude,
+      ramp: Duration::from_millis(config.ramp_ms),
+      keep_source: config.keep_source,
+    })
+  }
+}
+
+impl ButtonsToStick {
+  /// `held` tells whether a button is down, which the caller may read before
+  /// other rules changed it.
+  pub fn apply(&self, controller_state: &MutableXInputState, held: impl Fn(Button) -> bool) {
+    let [up, down, left, right] = self.buttons;
+    let direction = |positive: Button, negative: Button| held(positive) as i8 as f32 - held(negative) as i8 as f32;
+    let (x, y) = (direction(right, left), direction(up, down));
+    if !self.keep_source {
+      for button in self.buttons.into_iter().filter(|button| held(*button)) {
+        controller_state.set(button, ButtonState::UP);
+      }
+    }
+    if x == 0.0 && y == 0.0 {
+      return;
+    }
+
+    // the ramp starts with the first button of the direction
+    let history = controller_state.history();
+    let held_for = self.buttons.iter()
+      .filter(|button| held(**button))
+      .filter_map(|button| history.held_duration(button.mask()))
+      .max()
+      .unwrap_or_default();
+    let progress = if held_for >= self.ramp { 1.0 } else { held_for.as_secs_f32() / self.ramp.as_secs_f32() };
+
+    // diagonals reach as far as straight directions
+    let length = (x * x + y * y).sqrt();
+    let magnitude = self.magnitude as f32 * progress;
+    set_stick(controller_state, self.stick, (to_raw(x / length * magnitude / MAX), to_raw(y / length * magnitude / MAX)));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::button::Buttons;
+  use crate::profile::Profile;
+  use crate::simulation::{Frame, Simulation};
+  use crate::xinput_state::{XInputGamepad, XInputState};
+
+  fn shaping(toml: &str) -> StickShaping {
+    toml::from_str(toml).unwrap()
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<StickShaping>(toml).unwrap_err().message().to_owned()
+  }
+
+  fn assert_near((x, y): (i16, i16), (expected_x, expected_y): (i16, i16)) {
+    let near = |value: i16, expected: i16| (value as i32 - expected as i32).abs() <= 2;
+    assert!(near(x, expected_x) && near(y, expected_y), "got ({}, {}), expected about ({}, {})", x, y, expected_x, expected_y);
+  }
+
+  #[test]
+  fn no_deadzone_leaves_the_stick_alone() {
+    let shaping = StickShaping::default();
+    for stick in [(0, 0), (1, -1), (1000, -2000), (i16::MAX, i16::MAX)] {
+      assert_eq!(shaping.apply(stick), stick);
+    }
+    assert_eq!(shaping.apply((i16::MIN, i16::MIN)), (-i16::MAX, -i16::MAX));
+  }
+
+  #[test]
+  fn scaled_radial_starts_at_zero_past_the_deadzone() {
+    let shaping = shaping("deadzone = 8000");
+    assert_eq!(shaping.apply((7999, 0)), (0, 0));
+    assert_eq!(shaping.apply((5000, -5000)), (0, 0));
+    assert_eq!(shaping.apply((8000, 0)), (0, 0));
+    assert_near(shaping.apply((20384, 0)), (16384, 0));
+    assert_eq!(shaping.apply((i16::MAX, 0)), (i16::MAX, 0));
+  }
+
+  #[test]
+  fn radial_keeps_the_raw_length() {
+    let shaping = shaping("shape = \"radial\"\ndeadzone = 8000");
+    assert_eq!(shaping.apply((8000, 0)), (0, 0));
+    assert_near(shaping.apply((8001, 0)), (8001, 0));
+  }
+
+  #[test]
+  fn axial_ignores_each_axis_on_its_own() {
+    let shaping = shaping("shape = \"axial\"\ndeadzone = 8000");
+    assert_eq!(shaping.apply((4000, i16::MAX)), (0, i16::MAX));
+    assert_near(shaping.apply((20384, -20384)), (16384, -16384));
+  }
+
+  #[test]
+  fn cross_snaps_to_the_axis() {
+    let shaping = shaping("shape = \"cross\"\ndeadzone = 8000");
+    let (x, y) = shaping.apply((4000, 20000));
+    assert_eq!(x, 0);
+    assert_near((x, y), (0, 16400));
+    let (x, y) = shaping.apply((-20000, 4000));
+    assert_near((x, y), (-16400, 0));
+    assert_eq!(y, 0);
+  }
+
+  #[test]
+  fn outer_deadzone_reaches_full_early() {
+    let shaping = shaping("outer_deadzone = 24000");
+    assert_eq!(shaping.apply((24000, 0)), (i16::MAX, 0));
+    assert_eq!(shaping.apply((0, -30000)), (0, -i16::MAX));
+    assert_near(shaping.apply((12000, 0)), (16384, 0));
+  }
+
+  #[test]
+  fn anti_deadzone_lifts_the_smallest_push() {
+    let shaping = shaping("deadzone = 4000\nanti_deadzone = 8000");
+    assert_eq!(shaping.apply((4000, 0)), (0, 0));
+    assert_near(shaping.apply((4001, 0)), (8001, 0));
+    assert_eq!(shaping.apply((i16::MAX, 0)), (i16::MAX, 0));
+  }
+
+  #[test]
+  fn curves_reshape_the_distance() {
+    let power = shaping("curve = \"power\"\nexponent = 2.0");
+    assert_near(power.apply((16384, 0)), (8192, 0));
+    assert_eq!(power.apply((i16::MAX, 0)), (i16::MAX, 0));
+
+    let custom = shaping("curve = \"custom\"\npoints = [[0.0, 0.0], [0.5, 0.1], [1.0, 1.0]]");
+    assert_near(custom.apply((16384, 0)), (3277, 0));
+    assert_near(custom.apply((0, -24575)), (0, -18022));
+  }
+
+  #[test]
+  fn transforms_saturate_at_the_stick_range() {
+    let scaled = shaping("scale = 2.0");
+    assert_eq!(scaled.apply((20000, -20000)), (i16::MAX, -i16::MAX));
+    assert_eq!(scaled.apply((i16::MIN, i16::MAX)), (-i16::MAX, i16::MAX));
+
+    let inverted = shaping("invert_x = true\ninvert_y = true");
+    assert_eq!(inverted.apply((i16::MIN, i16::MAX)), (i16::MAX, -i16::MAX));
+  }
+
+  #[test]
+  fn transforms_rotate_and_correct_the_gate() {
+    assert_near(shaping("rotate_degrees = 90.0").apply((i16::MAX, 0)), (0, i16::MAX));
+    assert_near(shaping("gate = \"circle_to_square\"").apply((23170, 23170)), (i16::MAX, i16::MAX));
+    assert_near(shaping("gate = \"square_to_circle\"").apply((i16::MAX, i16::MAX)), (23170, 23170));
+  }
+
+  #[test]
+  fn swizzle_takes_axes_from_anywhere() {
+    let gamepad = XInputGamepad { thumb_lx: 1, thumb_ly: 2, thumb_rx: 3, thumb_ry: i16::MIN, ..Default::default() };
+    let controller_state = MutableXInputState::new(XInputState { packet_number: 1, gamepad });
+    let swizzle: AxisSwizzle = toml::from_str("left_x = \"right_y\"\nright_x = \"left_x\"\nright_y = \"left_x\"").unwrap();
+    swizzle.apply(&controller_state);
+    assert_eq!(controller_state.left_stick_raw(), (i16::MIN, 2));
+    assert_eq!(controller_state.right_stick_raw(), (1, 1));
+  }
+
+  // The buttons a stick to buttons rule presses for each stick position,
+  // polled one after the other
+  fn stick_buttons(rule: &str, sticks: &[(i16, i16)]) -> Vec<Buttons> {
+    let rule: StickToButtons = toml::from_str(&format!("stick = \"left\"\n{}", rule)).unwrap();
+    let mut state = StickToButtonsState::default();
+    sticks.iter().map(|&(thumb_lx, thumb_ly)| {
+      let gamepad = XInputGamepad { thumb_lx, thumb_ly, ..Default::default() };
+      let controller_state = MutableXInputState::new(XInputState { packet_number: 1, gamepad });
+      rule.apply(&controller_state, &mut state);
+      assert_eq!(controller_state.left_stick_raw(), (0, 0));
+      controller_state.buttons()
+    }).collect()
+  }
+
+  fn buttons(buttons: &[Button]) -> Buttons {
+    buttons.iter().copied().collect()
+  }
+
+  #[test]
+  fn eight_directions_press_two_buttons_on_diagonals() {
+    for (stick, pressed) in [
+      ((20000, 0), buttons(&[Button::DpadRight])),
+      ((20000, 20000), buttons(&[Button::DpadRight, Button::DpadUp])),
+      ((0, -20000), buttons(&[Button::DpadDown])),
+      ((-20000, -20000), buttons(&[Button::DpadLeft, Button::DpadDown])),
+      // 25°, past the edge between right and up right
+      ((18126, 8452), buttons(&[Button::DpadRight, Button::DpadUp])),
+    ] {
+      assert_eq!(stick_buttons("", &[stick]), [pressed], "stick at {:?}", stick);
+    }
+  }
+
+  #[test]
+  fn four_directions_never_press_two_buttons() {
+    for (stick, pressed) in [
+      ((20000, 18000), Button::DpadRight),
+      ((18000, 20000), Button::DpadUp),
+      ((-20000, -18000), Button::DpadLeft),
+    ] {
+      assert_eq!(stick_buttons("directions = 4", &[stick]), [Buttons::from(pressed)], "stick at {:?}", stick);
+    }
+  }
+
+  #[test]
+  fn a_direction_holds_a_little_past_its_edge() {
+    // 25° stays right once right is pressed, 35° does not
+    let pressed = stick_buttons("", &[(20000, 0), (18126, 8452), (16383, 11472)]);
+    assert_eq!(pressed, [Button::DpadRight.into(), Button::DpadRight.into(), buttons(&[Button::DpadRight, Button::DpadUp])]);
+  }
+
+  #[test]
+  fn buttons_let_go_below_the_release_threshold() {
+    let pressed = stick_buttons("threshold = 16000\nrelease = 12000", &[(15999, 0), (16000, 0), (12000, 0), (11999, 0), (15000, 0)]);
+    let right = Buttons::from(Button::DpadRight);
+    assert_eq!(pressed, [Buttons::empty(), right, right, Buttons::empty(), Buttons::empty()]);
+  }
+
+  fn simulation(rule: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("[[buttons_to_stick]]\nstick = \"left\"\n{}", rule)).unwrap())
+  }
+
+  fn held(ms: u64, buttons: &[Button]) -> Frame {
+    Frame::buttons(Duration::from_millis(ms), buttons.iter().copied().collect())
+  }
+
+  #[test]
+  fn buttons_ramp_the_stick_to_full() {
+    let mut simulation = simulation("ramp_ms = 100");
+    let output = simulation.run([0, 50, 100, 200].map(|ms| held(ms, &[Button::DpadRight])));
+    assert_eq!(output.iter().map(|frame| frame.gamepad.thumb_ly).collect::<Vec<_>>(), [0; 4]);
+    let x: Vec<i16> = output.iter().map(|frame| frame.gamepad.thumb_lx).collect();
+    assert_eq!(x[0], 0);
+    assert_near((x[1], 0), (16384, 0));
+    assert_eq!(x[2..], [i16::MAX, i16::MAX]);
+    assert!(output.iter().all(|frame| frame.pressed().is_empty()));
+
+    // letting go gives the stick back
+    assert_eq!(simulation.step(held(216, &[])).gamepad.thumb_lx, 0);
+  }
+
+  #[test]
+  fn diagonals_reach_as_far_as_straight_directions() {
+    let mut simulation = simulation("magnitude = 20000\nkeep_source = true");
+    let output = simulation.step(held(0, &[Button::DpadUp, Button::DpadLeft]));
+    assert_near((output.gamepad.thumb_lx, output.gamepad.thumb_ly), (-14142, 14142));
+    assert_eq!(output.pressed(), buttons(&[Button::DpadUp, Button::DpadLeft]));
+  }
+
+  #[test]
+  fn rejects_bad_shaping() {
+    assert_eq!(error("deadzone = 20000\nouter_deadzone = 20000"), "deadzone must be at least 0 and below outer_deadzone (20000), got 20000");
+    assert_eq!(error("deadzone = -1"), "deadzone must be at least 0 and below outer_deadzone (32767), got -1");
+    assert_eq!(error("anti_deadzone = 32767"), "anti_deadzone must be between 0 and 32766, got 32767");
+    assert_eq!(error("scale = 0.0"), "scale must be above 0, got 0");
+    assert_eq!(error("rotate_degrees = inf"), "rotate_degrees must be a number of degrees, got inf");
+  }
+
+  #[test]
+  fn rejects_bad_curves() {
+    assert_eq!(error("curve = \"power\""), "a power curve needs an exponent");
+    assert_eq!(error("curve = \"power\"\nexponent = 0.0"), "exponent must be above 0, got 0");
+    assert_eq!(error("exponent = 2.0"), "exponent only goes with curve = \"power\"");
+    assert_eq!(error("curve = \"custom\""), "a custom curve needs points");
+    assert_eq!(error("curve = \"custom\"\npoints = [[0.0, 0.0]]"), "a custom curve needs at least two points");
+    assert_eq!(error("curve = \"custom\"\npoints = [[0.0, 0.0], [1.0, 1.5]]"), "curve points must be between 0 and 1, got [1, 1.5]");
+    assert_eq!(error("curve = \"custom\"\npoints = [[0.5, 0.0], [0.5, 1.0]]"), "curve points must be sorted by input, without repeats");
+  }
+
+  #[test]
+  fn rejects_bad_stick_to_buttons() {
+    let error = |toml: &str| toml::from_str::<StickToButtons>(toml).unwrap_err().message().to_owned();
+    assert_eq!(error("stick = \"left\"\ndirections = 6"), "directions must be 4 or 8, got 6");
+    assert_eq!(error("stick = \"left\"\nthreshold = 0"), "threshold must be above 0, got 0");
+    assert_eq!(error("stick = \"left\"\nthreshold = 10000\nrelease = 12000"), "release must be above 0 and at most threshold (10000), got 12000");
+  }
+
+  #[test]
+  fn rejects_bad_buttons_to_stick() {
+    let error = toml::from_str::<ButtonsToStick>("stick = \"right\"\nmagnitude = 0").unwrap_err();
+    assert_eq!(error.message(), "magnitude must be above 0, got 0");
+  }
+}
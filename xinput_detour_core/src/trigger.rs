@@ -0,0 +1,256 @@
+// Trigger rules: reshaping a trigger's 0 to 255 range with deadzones and a
+// response curve or a digital hair trigger, pressing a button with a trigger,
+// and pushing a trigger with a button.
+
+use std::{convert::TryFrom, time::Duration};
+use serde::Deserialize;
+use super::button::Button;
+use super::mutable_xinput_state::{ButtonState, MutableXInputState};
+use super::profile::TriggerSide;
+use super::stick::{CurveKind, ResponseCurve};
+
+const MAX: f32 = u8::MAX as f32;
+
+fn trigger(controller_state: &MutableXInputState, side: TriggerSide) -> u8 {
+  match side {
+    TriggerSide::Left => controller_state.left_trigger(),
+    TriggerSide::Right => controller_state.right_trigger(),
+  }
+}
+
+fn set_trigger(controller_state: &MutableXInputState, side: TriggerSide, value: u8) {
+  match side {
+    TriggerSide::Left => controller_state.set_left_trigger(value),
+    TriggerSide::Right => controller_state.set_right_trigger(value),
+  }
+}
+
+fn default_threshold() -> u8 {
+  MutableXInputState::TRIGGER_THRESHOLD
+}
+
+fn default_value() -> u8 {
+  u8::MAX
+}
+
+/// How a trigger's pull maps to what the game sees.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TriggerResponse {
+  /// Pulls up to `deadzone` are ignored, pulls from `outer_deadzone` on count
+  /// as all the way, and the curve shapes the range in between.
+  Analog { deadzone: u8, outer_deadzone: u8, curve: ResponseCurve },
+  /// Fully released below the threshold and fully pulled from it on.
+  HairTrigger { threshold: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "TriggerShapingConfig")]
+pub struct TriggerShaping {
+  pub response: TriggerResponse,
+}
+
+// The profile format, checked before it becomes a TriggerShaping
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggerShapingConfig {
+  deadzone: Option<u8>,
+  outer_deadzone: Option<u8>,
+  #[serde(default)]
+  curve: CurveKind,
+  exponent: Option<f32>,
+  points: Option<Vec<(f32, f32)>>,
+  #[serde(default)]
+  hair_trigger: bool,
+  threshold: Option<u8>,
+}
+
+impl TryFrom<TriggerShapingConfig> for TriggerShaping {
+  type Error = String;
+
+  fn try_from(config: TriggerShapingConfig) -> Result<Self, Self::Error> {
+    if config.hair_trigger {
+      let analog_fields = config.deadzone.is_some() || config.outer_deadzone.is_some()
+        || !matches!(config.curve, CurveKind::Linear) || config.exponent.is_some() || config.points.is_some();
+      if analog_fields {
+        return Err("a hair trigger is either off or fully pulled, it takes a threshold but no deadzones or curve".to_owned());
+      }
+      let threshold = config.threshold.unwrap_or_else(default_threshold);
+      return Ok(TriggerShaping { response: TriggerResponse::HairTrigger { threshold } });
+    }
+
+    if config.threshold.is_some() {
+      return Err("threshold only goes with hair_trigger, use deadzone otherwise".to_owned());
+    }
+    let deadzone = config.deadzone.unwrap_or(0);
+    let outer_deadzone = config.outer_deadzone.unwrap_or(u8::MAX);
+    if deadzone >= outer_deadzone {
+      return Err(format!("deadzone must be below outer_deadzone ({}), got {}", outer_deadzone, deadzone));
+    }
+    let curve = ResponseCurve::from_config(config.curve, config.exponent, config.points)?;
+    Ok(TriggerShaping { response: TriggerResponse::Analog { deadzone, outer_deadzone, curve } })
+  }
+}
+
+impl TriggerShaping {
+  /// Reshapes a raw trigger value.
+  pub fn apply(&self, value: u8) -> u8 {
+    match &self.response {
+      TriggerResponse::HairTrigger { threshold } => if value >= *threshold { u8::MAX } else { 0 },
+      TriggerResponse::Analog { deadzone, outer_deadzone, curve } => {
+        if value <= *deadzone {
+          return 0;
+        }
+        let distance = ((value - deadzone) as f32 / (outer_deadzone - deadzone) as f32).min(1.0);
+        (curve.apply(distance).clamp(0.0, 1.0) * MAX).round() as u8
+      },
+    }
+  }
+}
+
+/// Presses `to` while a trigger is pulled to `threshold` or further. Unless
+/// `keep_source` is set the trigger is hidden from the game.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerToButton {
+  pub side: TriggerSide,
+  pub to: Button,
+  #[serde(default = "default_threshold")]
+  pub threshold: u8,
+  #[serde(default)]
+  pub keep_source: bool,
+}
+
+impl TriggerToButton {
+  /// `value` is the trigger after the `[filters]` and `[triggers]` shaping,
+  /// before any other trigger rule changed it.
+  pub fn apply(&self, controller_state: &MutableXInputState, value: u8) {
+    if !self.keep_source {
+      set_trigger(controller_state, self.side, 0);
+    }
+    if value >= self.threshold {
+      controller_state.set(self.to, ButtonState::DOWN);
+    }
+  }
+}
+
+/// Pushes a trigger to `value` while `from` is held, rising from 0 over
+/// `ramp_ms` so a button can feather a trigger. Unless `keep_source` is set
+/// the button is hidden from the game.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonToTrigger {
+  pub from: Button,
+  pub side: TriggerSide,
+  #[serde(default = "default_value")]
+  pub value: u8,
+  #[serde(default)]
+  pub ramp_ms: u64,
+  #[serde(default)]
+  pub keep_source: bool,
+}
+
+impl ButtonToTrigger {
+  /// `held` tells whether `from` is down, which the caller may read before
+  /// other rules changed it.
+  pub fn apply(&self, controller_state: &MutableXInputState, held: bool) {
+    if !held {
+      return;
+    }
+    if !self.keep_source {
+      controller_state.set(self.from, ButtonState::UP);
+    }
+
+    let held_for = controller_state.history().held_duration(self.from.mask()).unwrap_or_default();
+    let ramp = Duration::from_millis(self.ramp_ms);
+    let progress = if held_for >= ramp { 1.0 } else { held_for.as_secs_f32() / ramp.as_secs_f32() };
+    let value = (self.value as f32 * progress).round() as u8;
+
+    // the physical trigger still works while the button is held
+    let current = trigger(controller_state, self.side);
+    set_trigger(controller_state, self.side, current.max(value));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::profile::Profile;
+  use crate::simulation::{Frame, Simulation};
+  use crate::xinput_state::XInputGamepad;
+
+  fn shaping(toml: &str) -> TriggerShaping {
+    toml::from_str(toml).unwrap()
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<TriggerShaping>(toml).unwrap_err().message().to_owned()
+  }
+
+  #[test]
+  fn deadzones_stretch_the_range_between_them() {
+    let shaping = shaping("deadzone = 55\nouter_deadzone = 155");
+    assert_eq!(shaping.apply(0), 0);
+    assert_eq!(shaping.apply(55), 0);
+    assert_eq!(shaping.apply(105), 128);
+    assert_eq!(shaping.apply(155), u8::MAX);
+    assert_eq!(shaping.apply(u8::MAX), u8::MAX);
+  }
+
+  #[test]
+  fn hair_trigger_is_off_or_fully_pulled() {
+    let shaping = shaping("hair_trigger = true\nthreshold = 10");
+    assert_eq!(shaping.apply(9), 0);
+    assert_eq!(shaping.apply(10), u8::MAX);
+  }
+
+  fn simulation(rule: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(rule).unwrap())
+  }
+
+  fn pulled(ms: u64, left_trigger: u8) -> Frame {
+    Frame::new(Duration::from_millis(ms), XInputGamepad { left_trigger, ..Default::default() })
+  }
+
+  #[test]
+  fn trigger_holds_the_button_from_the_threshold_on() {
+    let mut hidden = simulation("[[trigger_to_button]]\nside = \"left\"\nto = \"LEFT_SHOULDER\"\nthreshold = 100");
+    let output = hidden.run([pulled(0, 99), pulled(16, 100), pulled(32, 255), pulled(48, 99), pulled(64, 0)]);
+    let pressed: Vec<bool> = output.iter().map(|frame| frame.pressed().contains(Button::LeftShoulder)).collect();
+    assert_eq!(pressed, [false, true, true, false, false]);
+    assert!(output.iter().all(|frame| frame.gamepad.left_trigger == 0));
+
+    let mut kept = simulation("[[trigger_to_button]]\nside = \"left\"\nto = \"LEFT_SHOULDER\"\nkeep_source = true");
+    let output = kept.step(pulled(0, 200));
+    assert_eq!((output.pressed(), output.gamepad.left_trigger), (Button::LeftShoulder.into(), 200));
+  }
+
+  #[test]
+  fn button_ramps_the_trigger_up() {
+    let mut simulation = simulation("[[button_to_trigger]]\nfrom = \"LEFT_SHOULDER\"\nside = \"left\"\nvalue = 200\nramp_ms = 100");
+    let held = |ms: u64| Frame::buttons(Duration::from_millis(ms), Button::LeftShoulder.into());
+    let output = simulation.run([held(0), held(25), held(50), held(100), held(500)]);
+    let values: Vec<u8> = output.iter().map(|frame| frame.gamepad.left_trigger).collect();
+    assert_eq!(values, [0, 50, 100, 200, 200]);
+    assert!(output.iter().all(|frame| frame.pressed().is_empty()));
+
+    // letting go drops the trigger at once, the next press ramps again
+    assert_eq!(simulation.step(pulled(516, 0)).gamepad.left_trigger, 0);
+    assert_eq!(simulation.step(held(532)).gamepad.left_trigger, 0);
+  }
+
+  #[test]
+  fn the_physical_trigger_still_works_during_a_ramp() {
+    let mut simulation = simulation("[[button_to_trigger]]\nfrom = \"A\"\nside = \"left\"\nramp_ms = 1000");
+    let gamepad = XInputGamepad { buttons: Button::A.mask(), left_trigger: 180, ..Default::default() };
+    let output = simulation.run([Frame::new(Duration::ZERO, gamepad), Frame::new(Duration::from_millis(100), gamepad)]);
+    assert_eq!(output.iter().map(|frame| frame.gamepad.left_trigger).collect::<Vec<_>>(), [180, 180]);
+  }
+
+  #[test]
+  fn rejects_bad_shaping() {
+    assert_eq!(error("hair_trigger = true\ndeadzone = 10"), "a hair trigger is either off or fully pulled, it takes a threshold but no deadzones or curve");
+    assert_eq!(error("threshold = 10"), "threshold only goes with hair_trigger, use deadzone otherwise");
+    assert_eq!(error("deadzone = 200\nouter_deadzone = 100"), "deadzone must be below outer_deadzone (100), got 200");
+    assert_eq!(error("curve = \"power\"\nexponent = -1.0"), "exponent must be above 0, got -1");
+  }
+}
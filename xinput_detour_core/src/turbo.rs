@@ -0,0 +1,241 @@
+// Turbo (rapid-fire) rules: while a button is held, or after it toggled turbo
+// on, a target button is pressed and released over and over. The rate is
+// either a frequency in real time or a number of polls per cycle.
+
+use std::{convert::TryFrom, time::Duration};
+use serde::Deserialize;
+use super::button::Button;
+use super::mutable_xinput_state::{ButtonState, MutableXInputState};
+
+/// How long one press and release cycle lasts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TurboRate {
+  /// Cycles per second.
+  Hz(f64),
+  /// Polls per cycle, for rates locked to the game's frame rate.
+  Polls(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "TurboConfig")]
+pub struct Turbo {
+  pub on: Button,
+  /// The button to pulse, `on` itself unless set.
+  pub target: Button,
+  pub rate: TurboRate,
+  /// The part of every cycle the target is held down, between 0 and 1.
+  pub duty: f64,
+  /// Each press of `on` switches turbo on or off instead of it running only
+  /// while `on` is held.
+  pub toggle: bool,
+  /// Hide the `on` button from the game.
+  pub suppress: bool,
+}
+
+// The profile format, checked before it becomes a Turbo
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TurboConfig {
+  on: Button,
+  target: Option<Button>,
+  rate_hz: Option<f64>,
+  every_polls: Option<u32>,
+  #[serde(default = "default_duty")]
+  duty: f64,
+  #[serde(default)]
+  toggle: bool,
+  #[serde(default)]
+  suppress: bool,
+}
+
+fn default_duty() -> f64 {
+  0.5
+}
+
+impl TryFrom<TurboConfig> for Turbo {
+  type Error = String;
+
+  fn try_from(config: TurboConfig) -> Result<Self, Self::Error> {
+    let rate = match (config.rate_hz, config.every_polls) {
+      (Some(_), Some(_)) => return Err("a turbo takes either rate_hz or every_polls, not both".to_owned()),
+      (Some(hz), None) if !(hz > 0.0 && hz.is_finite()) => return Err(format!("rate_hz must be above 0, got {}", hz)),
+      (Some(hz), None) => TurboRate::Hz(hz),
+      (None, Some(polls)) if polls < 2 => return Err(format!("every_polls must be at least 2, got {}", polls)),
+      (None, Some(polls)) => TurboRate::Polls(polls),
+      (None, None) => Turbo::DEFAULT_RATE,
+    };
+    if !(config.duty > 0.0 && config.duty < 1.0) {
+      return Err(format!("duty must be between 0 and 1, got {}", config.duty));
+    }
+
+    Ok(Turbo {
+      on: config.on,
+      target: config.target.unwrap_or(config.on),
+      rate,
+      duty: config.duty,
+      toggle: config.toggle,
+      suppress: config.suppress,
+    })
+  }
+}
+
+/// What a turbo remembers between polls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TurboState {
+  toggled_on: bool,
+  // when the current run of pulses started and how many polls it has seen
+  started: Option<Duration>,
+  polls: u64,
+}
+
+impl Turbo {
+  pub const DEFAULT_RATE: TurboRate = TurboRate::Hz(10.0);
+
+  /// A turbo on `on` itself at the default rate.
+  pub fn new(on: Button) -> Self {
+    Self { on, target: on, rate: Self::DEFAULT_RATE, duty: default_duty(), toggle: false, suppress: false }
+  }
+
+  /// Pulses the target while turbo is active. `held` tells whether `on` is
+  /// down, which the caller may read before other rules changed it.
+  pub fn apply(&self, controller_state: &MutableXInputState, state: &mut TurboState, held: bool) {
+    let active = if self.toggle {
+      if controller_state.just_pressed(self.on) {
+        state.toggled_on = !state.toggled_on;
+      }
+      state.toggled_on
+    } else {
+      held
+    };
+
+    if held && self.suppress {
+      controller_state.set(self.on, ButtonState::UP);
+    }
+
+    if !active {
+      state.started = None;
+      state.polls = 0;
+      return;
+    }
+
+    let now = controller_state.now();
+    let started = *state.started.get_or_insert(now);
+    let pressed = match self.rate {
+      TurboRate::Hz(hz) => {
+        let period = 1.0 / hz;
+        let phase = (now.saturating_sub(started).as_secs_f64() % period) / period;
+        phase < self.duty
+      },
+      TurboRate::Polls(polls) => {
+        // at least one poll down and one up every cycle
+        let down_polls = ((polls as f64 * self.duty).round() as u64).clamp(1, polls as u64 - 1);
+        state.polls % (polls as u64) < down_polls
+      },
+    };
+    state.polls += 1;
+
+    let button_state = if pressed { ButtonState::DOWN } else { ButtonState::UP };
+    controller_state.set(self.target, button_state);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::button::Buttons;
+  use crate::profile::Profile;
+  use crate::simulation::{Frame, Simulation};
+
+  fn simulation(turbo: &str) -> Simulation {
+    Simulation::new(Profile::from_toml_str(&format!("[[turbo]]\n{}", turbo)).unwrap())
+  }
+
+  fn at(ms: u64, buttons: Buttons) -> Frame {
+    Frame::buttons(Duration::from_millis(ms), buttons)
+  }
+
+  // Whether the button reaches the game on each frame
+  fn pressed(output: Vec<Frame>, button: Button) -> Vec<bool> {
+    output.iter().map(|frame| frame.pressed().contains(button)).collect()
+  }
+
+  #[test]
+  fn pulses_at_the_rate_while_held() {
+    let mut simulation = simulation("on = \"A\"\nrate_hz = 10.0");
+    let held = [10, 30, 70, 90, 120, 170].map(|ms| at(ms, Button::A.into()));
+    let output = simulation.run(held);
+    // the cycle starts with the first held poll, at 10ms
+    assert_eq!(pressed(output, Button::A), [true, true, false, false, true, false]);
+  }
+
+  #[test]
+  fn duty_sets_the_part_of_the_cycle_held_down() {
+    let mut simulation = simulation("on = \"A\"\nrate_hz = 10.0\nduty = 0.25");
+    let held = [0, 20, 30, 90, 100, 120].map(|ms| at(ms, Button::A.into()));
+    assert_eq!(pressed(simulation.run(held), Button::A), [true, true, false, false, true, true]);
+  }
+
+  #[test]
+  fn every_polls_counts_polls_not_time() {
+    let mut simulation = simulation("on = \"A\"\nevery_polls = 4");
+    // uneven timestamps change nothing
+    let held = [0, 1, 500, 501, 502, 3000, 3001, 3002].map(|ms| at(ms, Button::A.into()));
+    assert_eq!(pressed(simulation.run(held), Button::A), [true, true, false, false, true, true, false, false]);
+  }
+
+  #[test]
+  fn releasing_starts_the_next_run_with_a_press() {
+    let mut simulation = simulation("on = \"A\"\nevery_polls = 4");
+    let output = simulation.run([
+      at(0, Button::A.into()),
+      at(16, Button::A.into()),
+      at(32, Button::A.into()),
+      at(48, Buttons::empty()),
+      at(64, Button::A.into()),
+    ]);
+    assert_eq!(pressed(output, Button::A), [true, true, false, false, true]);
+  }
+
+  #[test]
+  fn toggle_switches_on_and_off_with_each_press() {
+    let mut simulation = simulation("on = \"LEFT_SHOULDER\"\ntarget = \"A\"\nevery_polls = 2\ntoggle = true");
+    let output = simulation.run([
+      at(0, Button::LeftShoulder.into()),
+      at(16, Buttons::empty()),
+      at(32, Buttons::empty()),
+      at(48, Buttons::empty()),
+      at(64, Button::LeftShoulder.into()),
+      at(80, Buttons::empty()),
+    ]);
+    assert_eq!(pressed(output, Button::A), [true, false, true, false, false, false]);
+  }
+
+  #[test]
+  fn suppress_hides_the_source_button() {
+    let mut suppressed = simulation("on = \"LEFT_SHOULDER\"\ntarget = \"A\"\nevery_polls = 2\nsuppress = true");
+    let output = suppressed.run([at(0, Button::LeftShoulder.into()), at(16, Button::LeftShoulder.into())]);
+    assert_eq!(output.iter().map(Frame::pressed).collect::<Vec<_>>(), [Buttons::from(Button::A), Buttons::empty()]);
+
+    let mut unsuppressed = simulation("on = \"LEFT_SHOULDER\"\ntarget = \"A\"\nevery_polls = 2");
+    let output = unsuppressed.run([at(0, Button::LeftShoulder.into()), at(16, Button::LeftShoulder.into())]);
+    assert_eq!(pressed(output, Button::LeftShoulder), [true, true]);
+  }
+
+  fn error(toml: &str) -> String {
+    toml::from_str::<Turbo>(toml).unwrap_err().message().to_owned()
+  }
+
+  #[test]
+  fn rejects_bad_rates() {
+    assert_eq!(error("on = \"A\"\nrate_hz = 10.0\nevery_polls = 4"), "a turbo takes either rate_hz or every_polls, not both");
+    assert_eq!(error("on = \"A\"\nrate_hz = 0.0"), "rate_hz must be above 0, got 0");
+    assert_eq!(error("on = \"A\"\nrate_hz = inf"), "rate_hz must be above 0, got inf");
+    assert_eq!(error("on = \"A\"\nevery_polls = 1"), "every_polls must be at least 2, got 1");
+  }
+
+  #[test]
+  fn rejects_a_duty_cycle_that_never_pulses() {
+    assert_eq!(error("on = \"A\"\nduty = 0.0"), "duty must be between 0 and 1, got 0");
+    assert_eq!(error("on = \"A\"\nduty = 1.0"), "duty must be between 0 and 1, got 1");
+  }
+}
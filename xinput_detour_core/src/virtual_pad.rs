@@ -0,0 +1,101 @@
+// Virtual pads: controllers on XInput slots with no device behind them, so
+// bots or second-player dummies can join local matches. While a slot is empty
+// and has a virtual pad, the hook hands the game the virtual pad's state
+// instead of ERROR_DEVICE_NOT_CONNECTED. The rules, macros and playback run on
+// it like on a real pad, so a virtual pad can be driven by the buttons set over
+// the control channel, by macros triggered over it or by a recording.
+//
+// A slot gets a virtual pad when its profile has `virtual = true` under
+// `[device]`, or when one is plugged in over the control channel. A real
+// controller connected to the slot always wins over the virtual pad.
+
+use super::xinput_state::{XInputGamepad, XInputState};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VirtualPad {
+  plugged_in: bool,
+  // the buttons and axes set over the control channel
+  gamepad: XInputGamepad,
+  // the state last handed to the game, and its packet number
+  output: XInputGamepad,
+  packet_number: u32,
+}
+
+impl VirtualPad {
+  /// Whether a virtual pad was plugged in over the control channel.
+  pub fn is_plugged_in(&self) -> bool {
+    self.plugged_in
+  }
+
+  /// Plugs the virtual pad in or unplugs it. Unplugging releases everything.
+  pub fn set_plugged_in(&mut self, plugged_in: bool) {
+    self.plugged_in = plugged_in;
+    if !plugged_in {
+      self.gamepad = XInputGamepad::default();
+    }
+  }
+
+  /// Sets the buttons and axes the rules see on the next poll.
+  pub fn set_gamepad(&mut self, gamepad: XInputGamepad) {
+    self.gamepad = gamepad;
+  }
+
+  /// The state to run the rules on.
+  pub fn state(&self) -> XInputState {
+    XInputState { packet_number: self.packet_number, gamepad: self.gamepad }
+  }
+
+  /// Stamps the state handed to the game with a packet number that changes
+  /// whenever the state does, like a real pad's. Games skip polls whose
+  /// packet number did not change.
+  pub fn stamp(&mut self, state: XInputState) -> XInputState {
+    if state.gamepad != self.output {
+      self.output = state.gamepad;
+      self.packet_number = self.packet_number.wrapping_add(1);
+    }
+    XInputState { packet_number: self.packet_number, gamepad: state.gamepad }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pressing(buttons: u16) -> XInputGamepad {
+    XInputGamepad { buttons, ..Default::default() }
+  }
+
+  #[test]
+  fn rules_see_the_gamepad_set_over_the_control_channel() {
+    let mut pad = VirtualPad::default();
+    pad.set_plugged_in(true);
+    pad.set_gamepad(pressing(0x1000));
+    assert_eq!(pad.state().gamepad, pressing(0x1000));
+
+    // unplugging lets go of everything
+    pad.set_plugged_in(false);
+    assert!(!pad.is_plugged_in());
+    assert_eq!(pad.state().gamepad, XInputGamepad::default());
+  }
+
+  #[test]
+  fn stamp_bumps_the_packet_number_only_on_changes() {
+    let mut pad = VirtualPad::default();
+    let stamp = |pad: &mut VirtualPad, packet_number: u32, gamepad: XInputGamepad| pad.stamp(XInputState { packet_number, gamepad });
+
+    // the rules' packet number is replaced with the pad's own
+    assert_eq!(stamp(&mut pad, 40, pressing(0x1000)), XInputState { packet_number: 1, gamepad: pressing(0x1000) });
+    assert_eq!(stamp(&mut pad, 41, pressing(0x1000)).packet_number, 1);
+    assert_eq!(stamp(&mut pad, 41, pressing(0x2000)), XInputState { packet_number: 2, gamepad: pressing(0x2000) });
+    assert_eq!(stamp(&mut pad, 42, XInputGamepad::default()).packet_number, 3);
+
+    // the next poll runs the rules on the latest packet number
+    assert_eq!(pad.state().packet_number, 3);
+  }
+
+  #[test]
+  fn packet_numbers_wrap_around() {
+    let mut pad = VirtualPad { packet_number: u32::MAX, ..Default::default() };
+    assert_eq!(pad.stamp(XInputState { packet_number: 0, gamepad: pressing(0x1000) }).packet_number, 0);
+  }
+}
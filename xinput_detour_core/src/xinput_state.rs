@@ -0,0 +1,110 @@
+// Plain-Rust mirrors of the winapi XINPUT_GAMEPAD, XINPUT_STATE and related
+// structs so that controller logic can be compiled and tested without Windows
+// headers.
+// The field layout and button bits match the XInput documentation exactly, so
+// converting at the hook boundary is a field-by-field copy.
+
+use serde::{Deserialize, Serialize};
+
+pub const XINPUT_GAMEPAD_DPAD_UP: u16 = 0x0001;
+pub const XINPUT_GAMEPAD_DPAD_DOWN: u16 = 0x0002;
+pub const XINPUT_GAMEPAD_DPAD_LEFT: u16 = 0x0004;
+pub const XINPUT_GAMEPAD_DPAD_RIGHT: u16 = 0x0008;
+pub const XINPUT_GAMEPAD_START: u16 = 0x0010;
+pub const XINPUT_GAMEPAD_BACK: u16 = 0x0020;
+pub const XINPUT_GAMEPAD_LEFT_THUMB: u16 = 0x0040;
+pub const XINPUT_GAMEPAD_RIGHT_THUMB: u16 = 0x0080;
+pub const XINPUT_GAMEPAD_LEFT_SHOULDER: u16 = 0x0100;
+pub const XINPUT_GAMEPAD_RIGHT_SHOULDER: u16 = 0x0200;
+/// Only reported by the undocumented XInputGetStateEx.
+pub const XINPUT_GAMEPAD_GUIDE: u16 = 0x0400;
+pub const XINPUT_GAMEPAD_A: u16 = 0x1000;
+pub const XINPUT_GAMEPAD_B: u16 = 0x2000;
+pub const XINPUT_GAMEPAD_X: u16 = 0x4000;
+pub const XINPUT_GAMEPAD_Y: u16 = 0x8000;
+
+pub const XINPUT_GAMEPAD_LEFT_THUMB_DEADZONE: i16 = 7849;
+pub const XINPUT_GAMEPAD_RIGHT_THUMB_DEADZONE: i16 = 8689;
+pub const XINPUT_GAMEPAD_TRIGGER_THRESHOLD: u8 = 30;
+
+pub const XINPUT_DEVTYPE_GAMEPAD: u8 = 0x01;
+
+pub const XINPUT_DEVSUBTYPE_UNKNOWN: u8 = 0x00;
+pub const XINPUT_DEVSUBTYPE_GAMEPAD: u8 = 0x01;
+pub const XINPUT_DEVSUBTYPE_WHEEL: u8 = 0x02;
+pub const XINPUT_DEVSUBTYPE_ARCADE_STICK: u8 = 0x03;
+pub const XINPUT_DEVSUBTYPE_FLIGHT_STICK: u8 = 0x04;
+pub const XINPUT_DEVSUBTYPE_DANCE_PAD: u8 = 0x05;
+pub const XINPUT_DEVSUBTYPE_GUITAR: u8 = 0x06;
+pub const XINPUT_DEVSUBTYPE_GUITAR_ALTERNATE: u8 = 0x07;
+pub const XINPUT_DEVSUBTYPE_DRUM_KIT: u8 = 0x08;
+pub const XINPUT_DEVSUBTYPE_GUITAR_BASS: u8 = 0x0B;
+pub const XINPUT_DEVSUBTYPE_ARCADE_PAD: u8 = 0x13;
+
+pub const XINPUT_CAPS_FFB_SUPPORTED: u16 = 0x0001;
+pub const XINPUT_CAPS_WIRELESS: u16 = 0x0002;
+pub const XINPUT_CAPS_VOICE_SUPPORTED: u16 = 0x0004;
+pub const XINPUT_CAPS_PMD_SUPPORTED: u16 = 0x0008;
+pub const XINPUT_CAPS_NO_NAVIGATION: u16 = 0x0010;
+
+pub const BATTERY_DEVTYPE_GAMEPAD: u8 = 0x00;
+pub const BATTERY_DEVTYPE_HEADSET: u8 = 0x01;
+
+pub const BATTERY_TYPE_DISCONNECTED: u8 = 0x00;
+pub const BATTERY_TYPE_WIRED: u8 = 0x01;
+pub const BATTERY_TYPE_ALKALINE: u8 = 0x02;
+pub const BATTERY_TYPE_NIMH: u8 = 0x03;
+pub const BATTERY_TYPE_UNKNOWN: u8 = 0xFF;
+
+pub const BATTERY_LEVEL_EMPTY: u8 = 0x00;
+pub const BATTERY_LEVEL_LOW: u8 = 0x01;
+pub const BATTERY_LEVEL_MEDIUM: u8 = 0x02;
+pub const BATTERY_LEVEL_FULL: u8 = 0x03;
+
+/// The number of controller slots (`user_index` 0 to 3) XInput supports.
+pub const XUSER_MAX_COUNT: u32 = 4;
+
+/// Mirror of `XINPUT_GAMEPAD`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XInputGamepad {
+  pub buttons: u16,
+  pub left_trigger: u8,
+  pub right_trigger: u8,
+  pub thumb_lx: i16,
+  pub thumb_ly: i16,
+  pub thumb_rx: i16,
+  pub thumb_ry: i16,
+}
+
+/// Mirror of `XINPUT_STATE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XInputState {
+  pub packet_number: u32,
+  pub gamepad: XInputGamepad,
+}
+
+/// Mirror of `XINPUT_VIBRATION`. Motor speeds go from 0 to 65,535; the left
+/// motor is the low-frequency one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XInputVibration {
+  pub left_motor_speed: u16,
+  pub right_motor_speed: u16,
+}
+
+/// Mirror of `XINPUT_CAPABILITIES`. The gamepad and vibration fields tell
+/// which buttons and axes the device has and the resolution of its motors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XInputCapabilities {
+  pub device_type: u8,
+  pub sub_type: u8,
+  pub flags: u16,
+  pub gamepad: XInputGamepad,
+  pub vibration: XInputVibration,
+}
+
+/// Mirror of `XINPUT_BATTERY_INFORMATION`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XInputBatteryInformation {
+  pub battery_type: u8,
+  pub battery_level: u8,
+}
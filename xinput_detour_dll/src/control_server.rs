@@ -0,0 +1,218 @@
+// The DLL side of the injector control channel. A background thread listens on
+// the named pipe and answers requests using the hook's global state.
+//
+// The thread stops when the client sends `Request::Goodbye` (or when the DLL is
+// detached), so the injector must say goodbye before ejecting the DLL or the
+// thread would keep running in unloaded code. Between requests, connected or
+// not, it also watches the profile files for changes.
+
+use std::{sync::atomic::{AtomicBool, Ordering}, thread, time::Duration};
+use xinput_detour_core::ipc::*;
+use xinput_detour_core::ipc_transport::StreamTransport;
+use xinput_detour_core::named_pipe::NamedPipe;
+use xinput_detour_core::playback::PlaybackOptions;
+use xinput_detour_core::profile::RuleId;
+use xinput_detour_core::xinput_state::{XInputGamepad, XInputState};
+use super::*;
+
+// how long the server sleeps between checks for new requests
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn start() {
+  RUNNING.store(true, Ordering::SeqCst);
+  thread::spawn(run);
+}
+
+pub fn stop() {
+  RUNNING.store(false, Ordering::SeqCst);
+}
+
+fn is_running() -> bool {
+  RUNNING.load(Ordering::SeqCst)
+}
+
+fn run() {
+  while is_running() {
+    let pipe = match NamedPipe::create(PIPE_NAME) {
+      Ok(pipe) => pipe,
+      Err(_) => {
+        check_profiles_for_changes();
+        thread::sleep(Duration::from_secs(1));
+        continue;
+      },
+    };
+
+    // wait for the injector to connect
+    loop {
+      if !is_running() {
+        return;
+      }
+      match pipe.try_accept() {
+        Ok(true) => break,
+        Ok(false) => {
+          check_profiles_for_changes();
+          thread::sleep(POLL_INTERVAL);
+        },
+        Err(_) => break,
+      }
+    }
+
+    let mut session = ControlSession::new(StreamTransport::new(pipe));
+    while is_running() {
+      check_profiles_for_changes();
+      match session.poll(&DllControl) {
+        Ok(SessionState::Open) => thread::sleep(POLL_INTERVAL),
+        // the injector said goodbye and is about to eject the DLL
+        Ok(SessionState::Closed) => {
+          stop();
+          return;
+        },
+        // the injector went away without saying goodbye, wait for it to come back
+        Err(_) => break,
+      }
+    }
+  }
+}
+
+struct DllControl;
+
+// The slots a request applies to: just the given one, or all of them
+fn slots(user_index: Option<u32>) -> Vec<usize> {
+  match user_index {
+    Some(user_index) => vec![user_index as usize],
+    None => (0..SLOTS).collect(),
+  }
+}
+
+impl ControlHandler for DllControl {
+  fn status(&self) -> HookStatus {
+    let profiles = PROFILES.lock().unwrap();
+    let controller_slots = CONTROLLER_SLOTS.lock().unwrap();
+    let slots = profiles.iter().zip(controller_slots.iter()).enumerate().map(|(slot, (profile_reloader, controller_slot))| {
+      let profile = profile_reloader.profile();
+      SlotStatus {
+        user_index: slot as u32,
+        profile_name: profile.name.clone(),
+        profile_path: profile_reloader.path().display().to_string(),
+        scheduled_functions: controller_slot.scheduled_functions.len(),
+        disabled_rules: profile.disabled_rules.iter().copied().collect(),
+        virtual_pad: profile.wants_virtual_pad() || controller_slot.virtual_pad.is_plugged_in(),
+      }
+    });
+
+    HookStatus {
+      hooked: XInputGetStateHook.is_enabled(),
+      polls: POLLS.load(Ordering::Relaxed),
+      slots: slots.collect(),
+      recording: recorder::path(),
+      playback: player::status(),
+    }
+  }
+
+  fn reload_profile(&self, user_index: Option<u32>) -> Result<String, String> {
+    let mut profiles = PROFILES.lock().unwrap();
+    let mut name = String::new();
+    for slot in slots(user_index) {
+      match profiles[slot].reload() {
+        Ok(profile) => name = profile.name.clone(),
+        Err(error) => return Err(format!("[slot {}] {}\nkeeping the previous profile", slot, error)),
+      }
+    }
+    Ok(name)
+  }
+
+  fn switch_profile(&self, path: &str, user_index: Option<u32>) -> Result<String, String> {
+    let mut profiles = PROFILES.lock().unwrap();
+    let mut name = String::new();
+    for slot in slots(user_index) {
+      match profiles[slot].switch_to(path) {
+        Ok(profile) => name = profile.name.clone(),
+        Err(error) => return Err(format!("[slot {}] {}\nkeeping the previous profile", slot, error)),
+      }
+    }
+    Ok(name)
+  }
+
+  fn set_rule_enabled(&self, rule: RuleId, enabled: bool, user_index: Option<u32>) -> Result<(), String> {
+    let mut profiles = PROFILES.lock().unwrap();
+    for slot in slots(user_index) {
+      if !profiles[slot].set_rule_enabled(rule, enabled) {
+        return Err(format!("[slot {}] the profile has no {:?} rule at index {}", slot, rule.kind, rule.index));
+      }
+    }
+    Ok(())
+  }
+
+  fn trigger_macro(&self, index: usize, user_index: u32) -> Result<(), String> {
+    let slot = user_index as usize;
+    let profile = PROFILES.lock().unwrap()[slot].profile();
+    match profile.macros.get(index) {
+      Some(macro_rule) => {
+        CONTROLLER_SLOTS.lock().unwrap()[slot].scheduled_functions.push(macro_rule.to_scheduled_function_stack());
+        Ok(())
+      },
+      None => Err(format!("[slot {}] the profile has no macro at index {}", slot, index)),
+    }
+  }
+
+  fn start_recording(&self, path: &str) -> Result<(), String> {
+    recorder::start(path)
+  }
+
+  fn stop_recording(&self) -> Result<(String, u64), String> {
+    recorder::stop()
+  }
+
+  fn start_playback(&self, path: &str, options: PlaybackOptions) -> Result<(), String> {
+    player::start(path, options)
+  }
+
+  fn pause_playback(&self, paused: bool) -> Result<(), String> {
+    player::set_paused(paused)
+  }
+
+  fn seek_playback(&self, position: Duration) -> Result<(), String> {
+    player::seek(position)
+  }
+
+  fn stop_playback(&self) -> Result<(), String> {
+    player::stop()
+  }
+
+  fn set_virtual_pad(&self, user_index: u32, plugged_in: bool) -> Result<(), String> {
+    CONTROLLER_SLOTS.lock().unwrap()[user_index as usize].virtual_pad.set_plugged_in(plugged_in);
+    Ok(())
+  }
+
+  fn set_virtual_pad_state(&self, user_index: u32, gamepad: XInputGamepad) -> Result<(), String> {
+    let slot = user_index as usize;
+    let profile = PROFILES.lock().unwrap()[slot].profile();
+    let mut controller_slots = CONTROLLER_SLOTS.lock().unwrap();
+    let virtual_pad = &mut controller_slots[slot].virtual_pad;
+    if !virtual_pad.is_plugged_in() && !profile.wants_virtual_pad() {
+      return Err(format!("[slot {}] has no virtual pad, plug one in first", slot));
+    }
+    virtual_pad.set_gamepad(gamepad);
+    Ok(())
+  }
+
+  fn take_logs(&self) -> Vec<String> {
+    let mut logs: Vec<String> = vec![];
+    for (slot, profile_reloader) in PROFILES.lock().unwrap().iter_mut().enumerate() {
+      logs.extend(profile_reloader.take_reports().into_iter().map(|report| format!("[slot {}] {}", slot, report)));
+    }
+    logs.append(&mut LOGS.lock().unwrap());
+    logs
+  }
+
+  fn controller_states(&self) -> Vec<(u32, XInputState)> {
+    let controller_states = CONTROLLER_STATES.lock().unwrap();
+    controller_states
+      .iter()
+      .enumerate()
+      .filter_map(|(user_index, state)| state.map(|state| (user_index as u32, state)))
+      .collect()
+  }
+}
@@ -1,121 +1,542 @@
-use std::{ffi::CString, iter, mem, error::Error, sync::Mutex};
+use std::{ffi::{CString, OsString}, iter, mem, error::Error, os::windows::ffi::OsStringExt, path::{Path, PathBuf}, sync::{Arc, Mutex}, sync::atomic::{AtomicU64, Ordering}};
 use winapi::um::*;
 use winapi::um::xinput::*;
-use winapi::shared::minwindef::{BOOL, DWORD, HINSTANCE, LPVOID, TRUE};
-use once_cell::sync::Lazy;
+use winapi::shared::minwindef::{BOOL, BYTE, DWORD, HINSTANCE, LPVOID, TRUE};
+use winapi::shared::winerror::{ERROR_DEVICE_NOT_CONNECTED, ERROR_SUCCESS};
+use once_cell::sync::{Lazy, OnceCell};
+use retour::static_detour;
 
-#[macro_use]
-mod function_scheduler;
+mod control_server;
+mod player;
+mod recorder;
+mod xinput_adapter;
 
-mod handle_controller_state;
-mod mutable_xinput_state;
+use xinput_detour_core::clock::{Clock, MonotonicClock};
+use xinput_detour_core::controller_slot::ControllerSlot;
+use xinput_detour_core::device::{STANDARD_GAMEPAD, VIRTUAL_PAD_BATTERY};
+use xinput_detour_core::pipeline::process_controller_state;
+use xinput_detour_core::profile::Profile;
+use xinput_detour_core::profile_reloader::ProfileReloader;
+use xinput_detour_core::routing::routing_table;
+use xinput_detour_core::xinput_state::{XInputState, XInputVibration, XINPUT_GAMEPAD_GUIDE};
+use xinput_adapter::*;
 
-use mutable_xinput_state::MutableXInputState;
-use function_scheduler::*;
-use handle_controller_state::*;
+// the remap profile to load until the injector switches to another one (see
+// profiles\default.toml for the format), relative to the folder of the DLL
+const PROFILE_PATH: &str = r"profiles\default.toml";
+
+// where the DLL was loaded from, set when it is attached
+static MODULE_PATH: OnceCell<PathBuf> = OnceCell::new();
+
+// set up the detours for the XInput functions
+static_detour! {
+  pub static XInputGetStateHook: unsafe extern "system" fn(DWORD, *mut XINPUT_STATE) -> DWORD;
+  pub static XInputGetStateExHook: unsafe extern "system" fn(DWORD, *mut XINPUT_STATE) -> DWORD;
+  pub static XInputSetStateHook: unsafe extern "system" fn(DWORD, *mut XINPUT_VIBRATION) -> DWORD;
+  pub static XInputGetCapabilitiesHook: unsafe extern "system" fn(DWORD, DWORD, *mut XINPUT_CAPABILITIES) -> DWORD;
+  pub static XInputGetBatteryInformationHook: unsafe extern "system" fn(DWORD, BYTE, *mut XINPUT_BATTERY_INFORMATION) -> DWORD;
+}
+
+// the number of controller slots XInput supports
+const SLOTS: usize = XUSER_MAX_COUNT as usize;
 
 // type definition for the original XInputGetState function
 type XInputGetStateFunc = unsafe extern "system" fn(DWORD, *mut XINPUT_STATE) -> DWORD;
 
-// set up a set of functions to be called on a schedule (see src\helpers\function_scheduler.rs)
-static SCHEDULED_FUNCTIONS: Lazy<Mutex<Vec<ScheduledFunctionStack>>> = Lazy::new(|| Mutex::new(vec![]));
+// the undocumented XInputGetStateEx is only exported by ordinal. It takes the
+// same arguments as XInputGetState but also reports the Guide button
+const XINPUT_GET_STATE_EX_ORDINAL: u16 = 100;
+
+// XINPUT_STATE as XInputGetStateEx fills it in, with a reserved DWORD after the gamepad
+#[repr(C)]
+struct XInputStateEx {
+  state: XINPUT_STATE,
+  reserved: DWORD,
+}
+
+// type definition for the original XInputSetState function
+type XInputSetStateFunc = unsafe extern "system" fn(DWORD, *mut XINPUT_VIBRATION) -> DWORD;
+
+// type definitions for the original XInputGetCapabilities and XInputGetBatteryInformation functions
+type XInputGetCapabilitiesFunc = unsafe extern "system" fn(DWORD, DWORD, *mut XINPUT_CAPABILITIES) -> DWORD;
+type XInputGetBatteryInformationFunc = unsafe extern "system" fn(DWORD, BYTE, *mut XINPUT_BATTERY_INFORMATION) -> DWORD;
+
+// the scheduled functions (see xinput_detour_core\src\function_scheduler.rs) and button
+// history of each controller slot, so a macro started on one pad never runs on another
+static CONTROLLER_SLOTS: Lazy<Mutex<[ControllerSlot; SLOTS]>> = Lazy::new(|| Mutex::new(std::array::from_fn(|_| ControllerSlot::new(new_clock()))));
+
+// the clock macros and button timings run on (see xinput_detour_core\src\clock.rs)
+// return FrameClock::default() instead for frame-perfect macros that count XInputGetState calls
+fn new_clock() -> Box<dyn Clock> {
+  Box::new(MonotonicClock::new())
+}
+
+// the active profile of each controller slot, loaded by the control server thread
+// rather than in DllMain and reloaded whenever the file changes
+static PROFILES: Lazy<Mutex<[ProfileReloader; SLOTS]>> = Lazy::new(|| Mutex::new(std::array::from_fn(|_| ProfileReloader::new(default_profile_path()))));
+
+// PROFILE_PATH next to the DLL, so it does not depend on the game's working
+// directory or on where the DLL was built
+fn default_profile_path() -> PathBuf {
+  match MODULE_PATH.get().and_then(|path| path.parent()) {
+    Some(dir) => dir.join(PROFILE_PATH),
+    None => PathBuf::from(PROFILE_PATH),
+  }
+}
+
+// bookkeeping reported to the injector over the control channel
+static POLLS: AtomicU64 = AtomicU64::new(0);
+static LOGS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(vec![]));
+static CONTROLLER_STATES: Lazy<Mutex<[Option<XInputState>; SLOTS]>> = Lazy::new(|| Mutex::new([None; SLOTS]));
+
+// the most log messages kept while no injector is listening
+const MAX_LOGS: usize = 1000;
+
+// Queues a message for the injector.
+fn log(message: String) {
+  let mut logs = LOGS.lock().unwrap();
+  if logs.len() < MAX_LOGS {
+    logs.push(message);
+  }
+}
 
 unsafe fn main() -> Result<(), Box<dyn Error>> {
   // Find the address of the XInputGetState function
-  let address = find_XInputGetState_address().expect("could not find 'XInputGetState' address");
+  let address = find_xinput_address("XInputGetState").expect("could not find 'XInputGetState' address");
   let target: XInputGetStateFunc = mem::transmute(address);
 
   // Initialize AND enable the detour
   XInputGetStateHook
     .initialize(target, xinput_get_state_detour)?
     .enable()?;
+  log(format!("hooked XInputGetState at {:#x}", address));
+
+  // Games that want the Guide button call XInputGetStateEx instead, which older
+  // xinput DLLs do not have
+  match find_xinput_ordinal_address(XINPUT_GET_STATE_EX_ORDINAL) {
+    Some(address) => {
+      let target: XInputGetStateFunc = mem::transmute(address);
+      XInputGetStateExHook
+        .initialize(target, xinput_get_state_ex_detour)?
+        .enable()?;
+      log(format!("hooked XInputGetStateEx at {:#x}", address));
+    },
+    None => log("could not find 'XInputGetStateEx' address, the Guide button is not available".to_owned()),
+  }
+
+  // Rumble is optional, the pad still works if XInputSetState cannot be hooked
+  match find_xinput_address("XInputSetState") {
+    Some(address) => {
+      let target: XInputSetStateFunc = mem::transmute(address);
+      XInputSetStateHook
+        .initialize(target, xinput_set_state_detour)?
+        .enable()?;
+      log(format!("hooked XInputSetState at {:#x}", address));
+    },
+    None => log("could not find 'XInputSetState' address, rumble is left alone".to_owned()),
+  }
+
+  // Device spoofing is optional too. xinput9_1_0.dll has no XInputGetBatteryInformation
+  match find_xinput_address("XInputGetCapabilities") {
+    Some(address) => {
+      let target: XInputGetCapabilitiesFunc = mem::transmute(address);
+      XInputGetCapabilitiesHook
+        .initialize(target, xinput_get_capabilities_detour)?
+        .enable()?;
+      log(format!("hooked XInputGetCapabilities at {:#x}", address));
+    },
+    None => log("could not find 'XInputGetCapabilities' address, capabilities are left alone".to_owned()),
+  }
+  match find_xinput_address("XInputGetBatteryInformation") {
+    Some(address) => {
+      let target: XInputGetBatteryInformationFunc = mem::transmute(address);
+      XInputGetBatteryInformationHook
+        .initialize(target, xinput_get_battery_information_detour)?
+        .enable()?;
+      log(format!("hooked XInputGetBatteryInformation at {:#x}", address));
+    },
+    None => log("could not find 'XInputGetBatteryInformation' address, battery information is left alone".to_owned()),
+  }
+
+  // Start listening for the injector
+  control_server::start();
 
   Ok(())
 }
 
 // XInputGetState function detour
 fn xinput_get_state_detour(user_index: DWORD, state_ptr: *mut XINPUT_STATE) -> DWORD {
-  // Call the original XInputGetState function so it loads the controller 
-  // state into the value referenced by state_ptr
-  let to_return =  unsafe { XInputGetStateHook.call(user_index, state_ptr) };
-
-  // Wrap the state_ptr in a MutableXInputState struct so we can modify the controller state safely
-  let controller_state = MutableXInputState::from_ptr(state_ptr);
-
-  // Lock the scheduled functions mutex so we can access the scheduled functions
-  let mut scheduled_functions = SCHEDULED_FUNCTIONS.lock().unwrap();
-
-  // Call handle_controller_state to modify the controller state
-  handle_controller_state(&controller_state, &mut scheduled_functions);
-
-  // If there are any scheduled functions, poll them and clean up the finished ones
-  if !scheduled_functions.is_empty() {
-    let mut completed: Vec<usize> = vec![];
-
-    // Poll each of the scheduled functions
-    for i in 0..scheduled_functions.len() {
-      let function_state = scheduled_functions[i].poll(&controller_state);
-      match function_state {
-        ScheduledFunctionState::Completed => completed.push(i),
-        _ => {},
-      }
+  get_state(user_index, state_ptr, false)
+}
+
+// XInputGetStateEx function detour. state_ptr points to an XInputStateEx
+fn xinput_get_state_ex_detour(user_index: DWORD, state_ptr: *mut XINPUT_STATE) -> DWORD {
+  get_state(user_index, state_ptr, true)
+}
+
+// Runs the rules on the pads routed to a slot and hands the result to the game.
+// `guide` tells whether the game called XInputGetStateEx, the only function
+// that reports the Guide button
+fn get_state(user_index: DWORD, state_ptr: *mut XINPUT_STATE, guide: bool) -> DWORD {
+  POLLS.fetch_add(1, Ordering::Relaxed);
+
+  // Everything below is kept per controller slot
+  let slot = user_index as usize;
+  if slot >= SLOTS || state_ptr.is_null() {
+    return unsafe {
+      if guide { XInputGetStateExHook.call(user_index, state_ptr) } else { XInputGetStateHook.call(user_index, state_ptr) }
+    };
+  }
+
+  // Hold on to the active profile and the pads routed to this slot for the rest
+  // of this poll
+  let (profile, pads) = routed_profile(slot);
+
+  // Call the original XInputGetState function for the pads routed to this slot
+  // so it loads their controller state
+  let pad_state = get_routed_state(&pads, &profile);
+
+  // The state is only valid if the original call succeeded, or if the slot is
+  // empty and may have a virtual pad
+  let to_return = match pad_state {
+    Ok(_) => ERROR_SUCCESS,
+    Err(error) => error,
+  };
+  let connected = to_return == ERROR_SUCCESS;
+  if !(connected || to_return == ERROR_DEVICE_NOT_CONNECTED) {
+    set_controller_state(user_index, None);
+    return to_return;
+  }
+
+  // Lock the controller slots mutex so we can access this slot's scheduled functions and history
+  let mut controller_slots = CONTROLLER_SLOTS.lock().unwrap();
+
+  // An empty slot stays empty unless it has a virtual pad
+  let virtual_pad = !connected && (profile.wants_virtual_pad() || controller_slots[slot].virtual_pad.is_plugged_in());
+  if !connected && !virtual_pad {
+    drop(controller_slots);
+    set_controller_state(user_index, None);
+    return to_return;
+  }
+
+  // Take the pads' state (or the virtual pad's) so we can modify it
+  let controller_state = match pad_state {
+    Ok(state) => state,
+    Err(_) => controller_slots[slot].virtual_pad.state(),
+  };
+
+  // Run the rules and scheduled functions on the pad (or on a recording being played),
+  // then write the result back for the game
+  let input_state = controller_state;
+  let controller_state = player::before_rules(user_index, controller_state);
+  let controller_state = process_controller_state(user_index, controller_state, &profile, &mut controller_slots[slot]);
+  let mut controller_state = player::after_rules(user_index, controller_state);
+  if !guide {
+    controller_state.gamepad.buttons &= !XINPUT_GAMEPAD_GUIDE;
+  }
+  let controller_state = if virtual_pad { controller_slots[slot].virtual_pad.stamp(controller_state) } else { controller_state };
+  write_xinput_state(&controller_state, unsafe { &mut *state_ptr });
+  set_controller_state(user_index, Some(controller_state));
+  recorder::record(user_index, input_state, controller_state);
+
+  // A combo may have asked for another profile, and a rumble cue may have
+  // started or ended. Let go of the controller slots first, the profiles are
+  // always locked before them
+  let profile_switch = controller_slots[slot].rule_state.take_profile_switch();
+  let rumble = controller_slots[slot].rumble.take_changed();
+  drop(controller_slots);
+  if let Some(path) = profile_switch {
+    switch_profile(slot, &path);
+  }
+  if let Some(vibration) = rumble {
+    send_vibration(&pads, vibration);
+  }
+
+  if virtual_pad { ERROR_SUCCESS } else { to_return }
+}
+
+// Returns a slot's active profile and the pads routed to the slot. Routing is
+// worked out across every slot's profile (see xinput_detour_core\src\routing.rs),
+// so a pad routed to another slot no longer feeds its own
+fn routed_profile(slot: usize) -> (Arc<Profile>, Vec<DWORD>) {
+  let profiles = PROFILES.lock().unwrap();
+  let active: [Arc<Profile>; SLOTS] = std::array::from_fn(|slot| profiles[slot].profile());
+  drop(profiles);
+  let mut table = routing_table(std::array::from_fn(|slot| active[slot].active_routing()));
+  (active[slot].clone(), mem::take(&mut table[slot]))
+}
+
+// Reads every pad routed to a slot and merges their states (see
+// xinput_detour_core\src\routing.rs). Succeeds if any of the pads is connected.
+fn get_routed_state(pads: &[DWORD], profile: &Profile) -> Result<XInputState, DWORD> {
+  let mut states = vec![];
+  let mut to_return = ERROR_DEVICE_NOT_CONNECTED;
+  for pad in pads {
+    match read_pad(*pad) {
+      Ok(state) => states.push(state),
+      Err(error) => to_return = error,
     }
+  }
+  if states.is_empty() {
+    return Err(to_return);
+  }
+  Ok(profile.routing.merge(&states))
+}
 
-    // Clean up all of the finished functions
-    completed.reverse();
-    for index in completed {
-      scheduled_functions.remove(index);
+// Reads a pad with the original XInputGetStateEx when it is hooked, so the rules
+// see the Guide button whichever function the game calls, or with the original
+// XInputGetState otherwise.
+fn read_pad(pad: DWORD) -> Result<XInputState, DWORD> {
+  let mut state: XInputStateEx = unsafe { mem::zeroed() };
+  let to_return = unsafe {
+    if XInputGetStateExHook.is_enabled() {
+      XInputGetStateExHook.call(pad, &mut state as *mut XInputStateEx as *mut XINPUT_STATE)
+    } else {
+      XInputGetStateHook.call(pad, &mut state.state)
     }
+  };
+  match to_return {
+    ERROR_SUCCESS => Ok(from_xinput_state(&state.state)),
+    error => Err(error),
+  }
+}
+
+// XInputSetState function detour
+fn xinput_set_state_detour(user_index: DWORD, vibration_ptr: *mut XINPUT_VIBRATION) -> DWORD {
+  let slot = user_index as usize;
+  if slot >= SLOTS || vibration_ptr.is_null() {
+    return unsafe { XInputSetStateHook.call(user_index, vibration_ptr) };
+  }
+
+  let (profile, pads) = routed_profile(slot);
+
+  // Reshape what the game asked for and run the motors of the pads routed to
+  // this slot at the faster of it and any rumble cue playing on this slot
+  let vibration = profile.apply_rumble(from_xinput_vibration(unsafe { &*vibration_ptr }));
+  let vibration = {
+    let mut controller_slots = CONTROLLER_SLOTS.lock().unwrap();
+    let rumble = &mut controller_slots[slot].rumble;
+    rumble.set_game(vibration);
+    rumble.send()
+  };
+
+  // A virtual pad has no motors but still takes the game's rumble
+  match set_routed_vibration(&pads, vibration) {
+    ERROR_DEVICE_NOT_CONNECTED if has_virtual_pad(slot, &profile) => ERROR_SUCCESS,
+    to_return => to_return,
   }
+}
 
+// Calls the original XInputSetState for every pad routed to a slot. Succeeds if
+// any of the pads is connected.
+fn set_routed_vibration(pads: &[DWORD], vibration: XInputVibration) -> DWORD {
+  let mut to_return = ERROR_DEVICE_NOT_CONNECTED;
+  for pad in pads {
+    let mut vibration = to_xinput_vibration(&vibration);
+    match unsafe { XInputSetStateHook.call(*pad, &mut vibration) } {
+      ERROR_SUCCESS => to_return = ERROR_SUCCESS,
+      error if to_return != ERROR_SUCCESS => to_return = error,
+      _ => {},
+    }
+  }
   to_return
 }
 
-// Returns the absolute address of the xinput*.dll module symbol.
-#[allow(non_snake_case)]
-fn find_XInputGetState_address() -> Option<usize> {
-  let xinput14 = "xinput1_4.dll";
-  let xinput13 = "xinput1_3.dll";
-  let xinput12 = "xinput1_2.dll";
-  let xinput11 = "xinput1_1.dll";
-  let xinput91 = "xinput9_1_0.dll";
-
-  for lib_name in [xinput14, xinput13, xinput12, xinput11, xinput91].into_iter() {
-    if let Some(address) = get_module_symbol_address(lib_name, "XInputGetState") {
-      return Some(address);
+// XInputGetCapabilities function detour
+fn xinput_get_capabilities_detour(user_index: DWORD, flags: DWORD, capabilities_ptr: *mut XINPUT_CAPABILITIES) -> DWORD {
+  let slot = user_index as usize;
+  if slot >= SLOTS || capabilities_ptr.is_null() {
+    return unsafe { XInputGetCapabilitiesHook.call(user_index, flags, capabilities_ptr) };
+  }
+
+  // Ask the first connected pad routed to this slot
+  let (profile, pads) = routed_profile(slot);
+  let to_return = call_first_connected(&pads, |pad| unsafe {
+    XInputGetCapabilitiesHook.call(pad, flags, capabilities_ptr)
+  });
+
+  // Only a connected or an empty slot can be spoofed, other errors go to the game as they are
+  let reported = match to_return {
+    ERROR_SUCCESS => Some(from_xinput_capabilities(unsafe { &*capabilities_ptr })),
+    ERROR_DEVICE_NOT_CONNECTED if has_virtual_pad(slot, &profile) => Some(STANDARD_GAMEPAD),
+    ERROR_DEVICE_NOT_CONNECTED => None,
+    _ => return to_return,
+  };
+
+  match profile.apply_capabilities(reported) {
+    Some(capabilities) => {
+      write_xinput_capabilities(&capabilities, unsafe { &mut *capabilities_ptr });
+      ERROR_SUCCESS
+    },
+    None => to_return,
+  }
+}
+
+// XInputGetBatteryInformation function detour
+fn xinput_get_battery_information_detour(user_index: DWORD, device_type: BYTE, battery_ptr: *mut XINPUT_BATTERY_INFORMATION) -> DWORD {
+  let slot = user_index as usize;
+  if slot >= SLOTS || battery_ptr.is_null() {
+    return unsafe { XInputGetBatteryInformationHook.call(user_index, device_type, battery_ptr) };
+  }
+
+  // Ask the first connected pad routed to this slot. Headset batteries are
+  // not spoofed
+  let (profile, pads) = routed_profile(slot);
+  let to_return = call_first_connected(&pads, |pad| unsafe {
+    XInputGetBatteryInformationHook.call(pad, device_type, battery_ptr)
+  });
+  if device_type != BATTERY_DEVTYPE_GAMEPAD {
+    return to_return;
+  }
+
+  let reported = match to_return {
+    ERROR_SUCCESS => Some(from_xinput_battery_information(unsafe { &*battery_ptr })),
+    ERROR_DEVICE_NOT_CONNECTED if has_virtual_pad(slot, &profile) => Some(VIRTUAL_PAD_BATTERY),
+    ERROR_DEVICE_NOT_CONNECTED => None,
+    _ => return to_return,
+  };
+
+  match profile.apply_battery_information(reported) {
+    Some(battery) => {
+      write_xinput_battery_information(&battery, unsafe { &mut *battery_ptr });
+      ERROR_SUCCESS
+    },
+    None => to_return,
+  }
+}
+
+// Calls an original XInput function for each pad in turn until one of them is
+// connected, for queries about the single device on a slot.
+fn call_first_connected(pads: &[DWORD], mut call: impl FnMut(DWORD) -> DWORD) -> DWORD {
+  let mut to_return = ERROR_DEVICE_NOT_CONNECTED;
+  for pad in pads {
+    to_return = call(*pad);
+    if to_return != ERROR_DEVICE_NOT_CONNECTED {
+      break;
     }
   }
+  to_return
+}
 
-  None
+// Whether an empty slot shows the game a virtual pad.
+fn has_virtual_pad(slot: usize, profile: &Profile) -> bool {
+  profile.wants_virtual_pad() || CONTROLLER_SLOTS.lock().unwrap()[slot].virtual_pad.is_plugged_in()
+}
+
+// Runs the motors of the pads routed to a slot at the given speeds, bypassing
+// the profile.
+fn send_vibration(pads: &[DWORD], vibration: XInputVibration) {
+  if XInputSetStateHook.is_enabled() {
+    set_routed_vibration(pads, vibration);
+  }
+}
+
+// Reloads the profiles whose files changed. The control server thread calls this
+// between requests, so the game's XInputGetState calls never wait on the file system.
+fn check_profiles_for_changes() {
+  for profile_reloader in PROFILES.lock().unwrap().iter_mut() {
+    profile_reloader.check_for_changes();
+  }
+}
+
+// Switches a slot to the profile a combo named, relative to the slot's current
+// profile file.
+fn switch_profile(slot: usize, path: &Path) {
+  let mut profiles = PROFILES.lock().unwrap();
+  let path = profiles[slot].path().parent().unwrap_or(Path::new("")).join(path);
+  match profiles[slot].switch_to(&path) {
+    Ok(profile) => log(format!("[slot {}] switched to profile {} ({})", slot, profile.name, path.display())),
+    Err(error) => log(format!("[slot {}] {}\nkeeping the previous profile", slot, error)),
+  }
+}
+
+// Records the state handed to the game so the injector can stream it.
+fn set_controller_state(user_index: DWORD, state: Option<XInputState>) {
+  if let Some(slot) = CONTROLLER_STATES.lock().unwrap().get_mut(user_index as usize) {
+    *slot = state;
+  }
+}
+
+// the xinput DLLs a game may have loaded, newest first
+const XINPUT_MODULES: [&str; 5] = ["xinput1_4.dll", "xinput1_3.dll", "xinput1_2.dll", "xinput1_1.dll", "xinput9_1_0.dll"];
+
+// Returns the absolute address of an xinput*.dll module symbol.
+fn find_xinput_address(symbol: &str) -> Option<usize> {
+  XINPUT_MODULES.into_iter().find_map(|module| get_module_symbol_address(module, symbol))
+}
+
+// Returns the absolute address of an xinput*.dll export that only has an ordinal.
+fn find_xinput_ordinal_address(ordinal: u16) -> Option<usize> {
+  XINPUT_MODULES.into_iter().find_map(|module| get_module_ordinal_address(module, ordinal))
 }
 
 // Returns a module symbol's absolute address.
 fn get_module_symbol_address(module: &str, symbol: &str) -> Option<usize> {
+  let symbol = CString::new(symbol).unwrap();
+  get_module_export_address(module, symbol.as_ptr())
+}
+
+// Returns the absolute address of a module export by ordinal.
+fn get_module_ordinal_address(module: &str, ordinal: u16) -> Option<usize> {
+  // like MAKEINTRESOURCEA, GetProcAddress takes the ordinal in place of the name
+  get_module_export_address(module, ordinal as usize as winnt::LPCSTR)
+}
+
+fn get_module_export_address(module: &str, export: winnt::LPCSTR) -> Option<usize> {
   let module = module
     .encode_utf16()
     .chain(iter::once(0))
     .collect::<Vec<u16>>();
-  let symbol = CString::new(symbol).unwrap();
   unsafe {
     let handle = libloaderapi::GetModuleHandleW(module.as_ptr());
-    match libloaderapi::GetProcAddress(handle, symbol.as_ptr()) as usize {
+    match libloaderapi::GetProcAddress(handle, export) as usize {
       0 => None,
       n => Some(n),
     }
   }
 }
 
+// The full path of a loaded module such as this DLL.
+fn get_module_path(module: HINSTANCE) -> Option<PathBuf> {
+  // long enough for any path, including those with the \\?\ prefix
+  let mut buffer = vec![0u16; 32_768];
+  let length = unsafe { libloaderapi::GetModuleFileNameW(module, buffer.as_mut_ptr(), buffer.len() as DWORD) } as usize;
+  match length {
+    0 => None,
+    n if n == buffer.len() => None,
+    n => Some(PathBuf::from(OsString::from_wide(&buffer[..n]))),
+  }
+}
+
 #[no_mangle]
 #[allow(non_snake_case)]
 pub unsafe extern "system" fn DllMain(
-  _module: HINSTANCE,
+  module: HINSTANCE,
   call_reason: DWORD,
   _reserved: LPVOID,
 ) -> BOOL {
   if call_reason == winnt::DLL_PROCESS_ATTACH {
+    if let Some(path) = get_module_path(module) {
+      let _ = MODULE_PATH.set(path);
+    }
     main().is_ok() as BOOL
   } else if call_reason == winnt::DLL_PROCESS_DETACH {
+    control_server::stop();
     XInputGetStateHook.disable().unwrap();
+    if XInputGetStateExHook.is_enabled() {
+      XInputGetStateExHook.disable().unwrap();
+    }
+    if XInputSetStateHook.is_enabled() {
+      XInputSetStateHook.disable().unwrap();
+    }
+    if XInputGetCapabilitiesHook.is_enabled() {
+      XInputGetCapabilitiesHook.disable().unwrap();
+    }
+    if XInputGetBatteryInformationHook.is_enabled() {
+      XInputGetBatteryInformationHook.disable().unwrap();
+    }
+    // flush whatever was recorded so far
+    let _ = recorder::stop();
     TRUE
   } else {
     TRUE
@@ -0,0 +1,80 @@
+// Plays a recording into the game while the injector asks for it (see
+// xinput_detour_core\src\playback.rs).
+
+use std::{sync::Mutex, time::Duration};
+use once_cell::sync::Lazy;
+use xinput_detour_core::clock::MonotonicClock;
+use xinput_detour_core::ipc::PlaybackStatus;
+use xinput_detour_core::playback::{Playback, PlaybackOptions, PlaybackState};
+use xinput_detour_core::xinput_state::XInputState;
+use super::log;
+
+struct CurrentPlayback {
+  path: String,
+  playback: Playback,
+}
+
+static PLAYBACK: Lazy<Mutex<Option<CurrentPlayback>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn start(path: &str, options: PlaybackOptions) -> Result<(), String> {
+  let playback = Playback::open(path, options, Box::new(MonotonicClock::new()))
+    .map_err(|error| format!("could not play {}: {}", path, error))?;
+  *PLAYBACK.lock().unwrap() = Some(CurrentPlayback { path: path.to_owned(), playback });
+  Ok(())
+}
+
+pub fn set_paused(paused: bool) -> Result<(), String> {
+  with_playback(|playback| playback.set_paused(paused))
+}
+
+pub fn seek(position: Duration) -> Result<(), String> {
+  with_playback(|playback| playback.seek(position))
+}
+
+pub fn stop() -> Result<(), String> {
+  PLAYBACK.lock().unwrap().take().map(|_| ()).ok_or_else(|| "not playing".to_owned())
+}
+
+pub fn status() -> Option<PlaybackStatus> {
+  PLAYBACK.lock().unwrap().as_ref().map(|current| PlaybackStatus {
+    path: current.path.clone(),
+    state: current.playback.state(),
+    position_ms: current.playback.position().as_millis() as u64,
+    duration_ms: current.playback.duration().as_millis() as u64,
+  })
+}
+
+/// Returns the state to run the rules on.
+pub fn before_rules(user_index: u32, state: XInputState) -> XInputState {
+  match PLAYBACK.lock().unwrap().as_mut() {
+    Some(current) => current.playback.before_rules(user_index, state),
+    None => state,
+  }
+}
+
+/// Returns the state to hand to the game, and drops the playback once it is over.
+pub fn after_rules(user_index: u32, state: XInputState) -> XInputState {
+  let mut playback = PLAYBACK.lock().unwrap();
+  let Some(current) = playback.as_mut() else {
+    return state;
+  };
+
+  let state = current.playback.after_rules(user_index, state);
+  match current.playback.state() {
+    PlaybackState::Finished => log(format!("finished playing {}", current.path)),
+    PlaybackState::Released => log(format!("stopped playing {}, the controller was used", current.path)),
+    PlaybackState::Playing | PlaybackState::Paused => return state,
+  }
+  *playback = None;
+  state
+}
+
+fn with_playback(f: impl FnOnce(&mut Playback)) -> Result<(), String> {
+  match PLAYBACK.lock().unwrap().as_mut() {
+    Some(current) => {
+      f(&mut current.playback);
+      Ok(())
+    },
+    None => Err("not playing".to_owned()),
+  }
+}
@@ -0,0 +1,62 @@
+// Records every XInputGetState call to a file while the injector asks for it
+// (see xinput_detour_core\src\recording.rs for the format).
+
+use std::{fs::File, io::{self, BufWriter}, sync::Mutex};
+use once_cell::sync::Lazy;
+use xinput_detour_core::clock::{Clock, MonotonicClock};
+use xinput_detour_core::recording::{RecordedFrame, RecordingWriter};
+use xinput_detour_core::xinput_state::XInputState;
+use super::log;
+
+struct Recording {
+  path: String,
+  writer: RecordingWriter<BufWriter<File>>,
+  // timestamps are measured from the start of the recording
+  clock: MonotonicClock,
+}
+
+static RECORDING: Lazy<Mutex<Option<Recording>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn start(path: &str) -> Result<(), String> {
+  let mut recording = RECORDING.lock().unwrap();
+  if let Some(recording) = recording.as_ref() {
+    return Err(format!("already recording to {}", recording.path));
+  }
+
+  let writer = RecordingWriter::create(path).map_err(|error| format!("could not create {}: {}", path, error))?;
+  *recording = Some(Recording { path: path.to_owned(), writer, clock: MonotonicClock::new() });
+  Ok(())
+}
+
+/// Finishes the recording and returns its path and how many frames it has.
+pub fn stop() -> Result<(String, u64), String> {
+  let recording = RECORDING.lock().unwrap().take().ok_or_else(|| "not recording".to_owned())?;
+  let frames = recording.writer.frames();
+  finish(recording)
+    .map(|path| (path, frames))
+    .map_err(|error| error.to_string())
+}
+
+/// The file being recorded to, if any.
+pub fn path() -> Option<String> {
+  RECORDING.lock().unwrap().as_ref().map(|recording| recording.path.clone())
+}
+
+pub fn record(user_index: u32, input: XInputState, output: XInputState) {
+  let mut recording = RECORDING.lock().unwrap();
+  let Some(current) = recording.as_mut() else {
+    return;
+  };
+
+  let frame = RecordedFrame { at: current.clock.now(), user_index, input, output };
+  if let Err(error) = current.writer.write_frame(&frame) {
+    // stop rather than report the same error on every poll
+    log(format!("stopped recording to {}: {}", current.path, error));
+    *recording = None;
+  }
+}
+
+fn finish(recording: Recording) -> io::Result<String> {
+  recording.writer.finish()?;
+  Ok(recording.path)
+}
@@ -0,0 +1,83 @@
+// Conversions between the winapi XINPUT_* structs and the platform-independent
+// types used by xinput_detour_core. These only run at the hook boundary.
+
+use winapi::um::xinput::{XINPUT_BATTERY_INFORMATION, XINPUT_CAPABILITIES, XINPUT_GAMEPAD, XINPUT_STATE, XINPUT_VIBRATION};
+use xinput_detour_core::xinput_state::{XInputBatteryInformation, XInputCapabilities, XInputGamepad, XInputState, XInputVibration};
+
+pub fn from_xinput_state(state: &XINPUT_STATE) -> XInputState {
+  XInputState {
+    packet_number: state.dwPacketNumber,
+    gamepad: from_xinput_gamepad(&state.Gamepad),
+  }
+}
+
+pub fn write_xinput_state(state: &XInputState, target: &mut XINPUT_STATE) {
+  target.dwPacketNumber = state.packet_number;
+  write_xinput_gamepad(&state.gamepad, &mut target.Gamepad);
+}
+
+pub fn from_xinput_vibration(vibration: &XINPUT_VIBRATION) -> XInputVibration {
+  XInputVibration {
+    left_motor_speed: vibration.wLeftMotorSpeed,
+    right_motor_speed: vibration.wRightMotorSpeed,
+  }
+}
+
+pub fn to_xinput_vibration(vibration: &XInputVibration) -> XINPUT_VIBRATION {
+  XINPUT_VIBRATION {
+    wLeftMotorSpeed: vibration.left_motor_speed,
+    wRightMotorSpeed: vibration.right_motor_speed,
+  }
+}
+
+pub fn from_xinput_capabilities(capabilities: &XINPUT_CAPABILITIES) -> XInputCapabilities {
+  XInputCapabilities {
+    device_type: capabilities.Type,
+    sub_type: capabilities.SubType,
+    flags: capabilities.Flags,
+    gamepad: from_xinput_gamepad(&capabilities.Gamepad),
+    vibration: from_xinput_vibration(&capabilities.Vibration),
+  }
+}
+
+pub fn write_xinput_capabilities(capabilities: &XInputCapabilities, target: &mut XINPUT_CAPABILITIES) {
+  target.Type = capabilities.device_type;
+  target.SubType = capabilities.sub_type;
+  target.Flags = capabilities.flags;
+  write_xinput_gamepad(&capabilities.gamepad, &mut target.Gamepad);
+  target.Vibration = to_xinput_vibration(&capabilities.vibration);
+}
+
+pub fn from_xinput_battery_information(battery: &XINPUT_BATTERY_INFORMATION) -> XInputBatteryInformation {
+  XInputBatteryInformation {
+    battery_type: battery.BatteryType,
+    battery_level: battery.BatteryLevel,
+  }
+}
+
+pub fn write_xinput_battery_information(battery: &XInputBatteryInformation, target: &mut XINPUT_BATTERY_INFORMATION) {
+  target.BatteryType = battery.battery_type;
+  target.BatteryLevel = battery.battery_level;
+}
+
+fn from_xinput_gamepad(gamepad: &XINPUT_GAMEPAD) -> XInputGamepad {
+  XInputGamepad {
+    buttons: gamepad.wButtons,
+    left_trigger: gamepad.bLeftTrigger,
+    right_trigger: gamepad.bRightTrigger,
+    thumb_lx: gamepad.sThumbLX,
+    thumb_ly: gamepad.sThumbLY,
+    thumb_rx: gamepad.sThumbRX,
+    thumb_ry: gamepad.sThumbRY,
+  }
+}
+
+fn write_xinput_gamepad(gamepad: &XInputGamepad, target: &mut XINPUT_GAMEPAD) {
+  target.wButtons = gamepad.buttons;
+  target.bLeftTrigger = gamepad.left_trigger;
+  target.bRightTrigger = gamepad.right_trigger;
+  target.sThumbLX = gamepad.thumb_lx;
+  target.sThumbLY = gamepad.thumb_ly;
+  target.sThumbRX = gamepad.thumb_rx;
+  target.sThumbRY = gamepad.thumb_ry;
+}